@@ -9,3 +9,27 @@ CREATE TABLE illusts(
     creator_name TEXT NOT NULL,
     comment TEXT NOT NULL
 );
+
+CREATE TABLE usages(
+    identity TEXT NOT NULL,
+    model TEXT NOT NULL,
+    date TEXT NOT NULL,
+    prompt_tokens INTEGER NOT NULL,
+    completion_tokens INTEGER NOT NULL,
+    reasoning_tokens INTEGER NOT NULL,
+    cached_tokens INTEGER NOT NULL,
+    PRIMARY KEY (identity, model, date)
+);
+
+CREATE TABLE tool_calls(
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    conversation_id TEXT NOT NULL,
+    identity TEXT NOT NULL,
+    tool_name TEXT NOT NULL,
+    arguments TEXT NOT NULL,
+    result_summary TEXT NULL,
+    error TEXT NULL,
+    duration_milliseconds INTEGER NOT NULL,
+    called_at TEXT NOT NULL
+);
+CREATE INDEX tool_calls_called_at ON tool_calls(called_at);
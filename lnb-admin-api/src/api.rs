@@ -2,6 +2,8 @@ mod auxiliary;
 mod conversations;
 mod error;
 mod reminders;
+mod tool_calls;
+mod usages;
 
 use crate::{application::Application, jwt_auth::JwtAuthLayer};
 
@@ -16,7 +18,9 @@ pub fn routes(config_admin_api: &ConfigAdminApi) -> Router<Application> {
         .route("/conversations/count", get(conversations::count))
         .route("/conversations/show", get(conversations::show))
         .route("/conversations/latest_ids", get(conversations::latest_ids))
-        .route("/reminders/count", get(reminders::count));
+        .route("/reminders/count", get(reminders::count))
+        .route("/tool_calls", get(tool_calls::list))
+        .route("/usages", get(usages::list));
 
     // JWT Auth
     if let Some(auth_config) = &config_admin_api.jwt_auth {
@@ -0,0 +1,75 @@
+use crate::{api::error::ApiError, application::Application};
+
+use axum::{
+    Json,
+    extract::{Query, State},
+};
+use lnb_common::extension::hash_identity;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use time::{Date, OffsetDateTime, macros::format_description};
+use uuid::Uuid;
+
+const FETCH_COUNT_DEFAULT: usize = 50;
+const FETCH_COUNT_MAX: usize = 500;
+
+#[derive(Debug, Deserialize)]
+pub struct ListRequest {
+    since: Option<String>,
+    until: Option<String>,
+    /// ハッシュ化前の identity 。
+    identity: Option<String>,
+    tool: Option<String>,
+    count: Option<usize>,
+}
+#[derive(Debug, Serialize)]
+pub struct ListResponseItem {
+    conversation_id: Uuid,
+    identity: String,
+    tool: String,
+    arguments: Value,
+    result_summary: Option<String>,
+    error: Option<String>,
+    duration_milliseconds: u128,
+    #[serde(with = "time::serde::rfc3339")]
+    called_at: OffsetDateTime,
+}
+pub async fn list(
+    State(state): State<Application>,
+    request: Query<ListRequest>,
+) -> Result<Json<Vec<ListResponseItem>>, ApiError> {
+    let since = request.since.as_deref().map(parse_date).transpose()?;
+    let until = request.until.as_deref().map(parse_date).transpose()?;
+    let hashed_identity = request.identity.as_deref().map(hash_identity);
+    let fetching_count = request.count.unwrap_or(FETCH_COUNT_DEFAULT).min(FETCH_COUNT_MAX);
+    let records = state
+        .tool_call
+        .fetch(
+            since,
+            until,
+            hashed_identity.as_deref(),
+            request.tool.as_deref(),
+            fetching_count,
+        )
+        .await?;
+
+    let response_items = records
+        .into_iter()
+        .map(|r| ListResponseItem {
+            conversation_id: r.conversation_id.0,
+            identity: r.identity,
+            tool: r.tool_name,
+            arguments: r.arguments,
+            result_summary: r.result_summary,
+            error: r.error,
+            duration_milliseconds: r.duration.as_millis(),
+            called_at: r.called_at,
+        })
+        .collect();
+    Ok(Json(response_items))
+}
+
+fn parse_date(date: &str) -> Result<Date, ApiError> {
+    Date::parse(date, format_description!("[year]-[month]-[day]"))
+        .map_err(|e| ApiError::InvalidRequest(format!("invalid date: {e}")))
+}
@@ -0,0 +1,64 @@
+use crate::{api::error::ApiError, application::Application};
+
+use axum::{
+    Json,
+    extract::{Query, State},
+};
+use lnb_common::{config::llm::ConfigLlmModelPrice, extension::hash_identity};
+use lnb_core::interface::llm::LlmUsage;
+use serde::{Deserialize, Serialize};
+use time::{Date, macros::format_description};
+
+#[derive(Debug, Deserialize)]
+pub struct ListRequest {
+    since: Option<String>,
+    until: Option<String>,
+    identity: Option<String>,
+}
+#[derive(Debug, Serialize)]
+pub struct ListResponseItem {
+    identity: String,
+    model: String,
+    date: String,
+    #[serde(flatten)]
+    usage: LlmUsage,
+    estimated_cost: Option<f64>,
+}
+pub async fn list(
+    State(state): State<Application>,
+    request: Query<ListRequest>,
+) -> Result<Json<Vec<ListResponseItem>>, ApiError> {
+    let since = request.since.as_deref().map(parse_date).transpose()?;
+    let until = request.until.as_deref().map(parse_date).transpose()?;
+    let hashed_identity = request.identity.as_deref().map(hash_identity);
+    let records = state.usage.fetch(since, until, hashed_identity.as_deref()).await?;
+
+    let response_items = records
+        .into_iter()
+        .map(|r| {
+            let estimated_cost = state.prices.get(&r.model).map(|p| estimate_cost(&r.usage, p));
+            ListResponseItem {
+                identity: r.identity,
+                model: r.model,
+                date: r.date.to_string(),
+                usage: r.usage,
+                estimated_cost,
+            }
+        })
+        .collect();
+    Ok(Json(response_items))
+}
+
+fn parse_date(date: &str) -> Result<Date, ApiError> {
+    Date::parse(date, format_description!("[year]-[month]-[day]"))
+        .map_err(|e| ApiError::InvalidRequest(format!("invalid date: {e}")))
+}
+
+fn estimate_cost(usage: &LlmUsage, price: &ConfigLlmModelPrice) -> f64 {
+    let uncached_tokens = usage.prompt_tokens.saturating_sub(usage.cached_tokens);
+    let cached_price = price.cached.unwrap_or(price.prompt);
+    let total = uncached_tokens as f64 * price.prompt
+        + usage.cached_tokens as f64 * cached_price
+        + usage.completion_tokens as f64 * price.completion;
+    total / 1_000_000.0
+}
@@ -1,7 +1,17 @@
-use lnb_common::persistence::{RedisReminderDb, SqliteConversationDb};
+use std::{collections::HashMap, sync::Arc};
+
+use lnb_common::{
+    config::llm::ConfigLlmModelPrice,
+    persistence::{RedisReminderDb, SqliteConversationDb, SqliteToolCallDb, SqliteUsageDb},
+};
 
 #[derive(Debug, Clone)]
 pub struct Application {
     pub conversation: SqliteConversationDb,
     pub reminder: RedisReminderDb,
+    pub usage: SqliteUsageDb,
+    pub tool_call: SqliteToolCallDb,
+
+    /// モデルごとの価格表。
+    pub prices: Arc<HashMap<String, ConfigLlmModelPrice>>,
 }
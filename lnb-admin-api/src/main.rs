@@ -2,13 +2,13 @@ mod api;
 mod application;
 mod jwt_auth;
 
-use std::path::PathBuf;
+use std::{path::PathBuf, sync::Arc};
 
 use anyhow::Result;
 use clap::Parser;
 use lnb_common::{
     config::load_config,
-    persistence::{RedisReminderDb, SqliteConversationDb},
+    persistence::{RedisReminderDb, SqliteConversationDb, SqliteToolCallDb, SqliteUsageDb},
 };
 use tokio::net::TcpListener;
 
@@ -25,9 +25,18 @@ async fn main() -> Result<()> {
     let args = Arguments::parse();
     let config = load_config(&args.config)?;
 
+    let prices = config
+        .llm
+        .models
+        .iter()
+        .filter_map(|(name, model)| Some((name.clone(), model.price?)))
+        .collect();
     let application = application::Application {
         conversation: SqliteConversationDb::connect(&config.storage.sqlite).await?,
         reminder: RedisReminderDb::connect(&config.reminder).await?,
+        usage: SqliteUsageDb::connect(&config.storage.sqlite).await?,
+        tool_call: SqliteToolCallDb::connect(&config.storage.sqlite).await?,
+        prices: Arc::new(prices),
     };
     let app_service = api::routes(&config.admin_api).with_state(application);
 
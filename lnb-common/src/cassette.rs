@@ -0,0 +1,309 @@
+//! 外部とのやりとりを記録・再生するカセット。
+//! デバッグオプション `cassette_record=<dir>` で記録し、 `cassette_replay=<dir>` で記録したものを再生する。
+
+use crate::debug::debug_option_value;
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use base64::{DecodeError, Engine, prelude::BASE64_STANDARD};
+use reqwest::{Client, Error as ReqwestError, IntoUrl, Method, RequestBuilder, Response, header::CONTENT_TYPE};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use serde_json::{Error as SerdeJsonError, Value};
+use thiserror::Error as ThisError;
+use tracing::info;
+
+const RECORD_OPTION: &str = "cassette_record";
+const REPLAY_OPTION: &str = "cassette_replay";
+
+/// 記録から取り除いた秘密の値の代わりに入れる文字列。
+const REDACTED: &str = "<redacted>";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CassetteMode {
+    Record,
+    Replay,
+}
+
+/// 1 回分のやりとり。
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CassetteInteraction {
+    pub key: String,
+    pub request: Value,
+    pub response: Value,
+}
+
+/// やりとりを 1 つの JSON ファイルに記録・再生する。
+/// 記録時は既存のファイルに追記し、再生時は同じ `key` のやりとりを記録された順に返す。
+#[derive(Debug)]
+pub struct Cassette {
+    mode: CassetteMode,
+    path: PathBuf,
+    interactions: Mutex<Vec<Option<CassetteInteraction>>>,
+}
+
+impl Cassette {
+    /// デバッグオプションに応じて `name` のカセットを開く。どちらも指定されていなければ `None` 。
+    pub fn open(name: &str) -> Result<Option<Cassette>, CassetteError> {
+        let (mode, directory) = match (debug_option_value(RECORD_OPTION), debug_option_value(REPLAY_OPTION)) {
+            (Some(_), Some(_)) => return Err(CassetteError::ConflictingModes),
+            (Some(directory), None) => (CassetteMode::Record, directory),
+            (None, Some(directory)) => (CassetteMode::Replay, directory),
+            (None, None) => return Ok(None),
+        };
+        Ok(Some(Cassette::open_in(mode, Path::new(&directory), name)?))
+    }
+
+    /// `directory` にある `name` のカセットを `mode` で開く。
+    pub fn open_in(mode: CassetteMode, directory: &Path, name: &str) -> Result<Cassette, CassetteError> {
+        let filename = name.replace(|c: char| !(c.is_ascii_alphanumeric() || "-_.".contains(c)), "_");
+        let path = directory.join(format!("{filename}.json"));
+        // 設定の再読み込みなどで開き直しても、それまでの記録は残す
+        let recorded: Vec<CassetteInteraction> = match mode {
+            CassetteMode::Record if !path.exists() => {
+                fs::create_dir_all(directory)?;
+                vec![]
+            }
+            _ => serde_json::from_str(&fs::read_to_string(&path)?)?,
+        };
+        info!("cassette {name} opened for {mode:?}: {}", path.display());
+
+        Ok(Cassette {
+            mode,
+            path,
+            interactions: Mutex::new(recorded.into_iter().map(Some).collect()),
+        })
+    }
+
+    pub fn mode(&self) -> CassetteMode {
+        self.mode
+    }
+
+    /// やりとりを追記し、ファイル全体を書き直す。
+    pub fn record(&self, key: &str, request: &impl Serialize, response: &impl Serialize) -> Result<(), CassetteError> {
+        let interaction = CassetteInteraction {
+            key: key.to_string(),
+            request: serde_json::to_value(request)?,
+            response: serde_json::to_value(response)?,
+        };
+
+        let mut locked = self.interactions.lock().expect("poisoned");
+        locked.push(Some(interaction));
+        let recorded: Vec<_> = locked.iter().flatten().collect();
+        fs::write(&self.path, serde_json::to_string_pretty(&recorded)?)?;
+        Ok(())
+    }
+
+    /// `key` について記録されているうち、まだ再生していない最初の応答を返す。
+    pub fn replay<T: DeserializeOwned>(&self, key: &str) -> Result<T, CassetteError> {
+        let mut locked = self.interactions.lock().expect("poisoned");
+        let Some(interaction) = locked
+            .iter_mut()
+            .find(|i| i.as_ref().is_some_and(|i| i.key == key))
+            .and_then(Option::take)
+        else {
+            return Err(CassetteError::NotRecorded(key.to_string()));
+        };
+        Ok(serde_json::from_value(interaction.response)?)
+    }
+}
+
+/// カセットを通して HTTP リクエストを送信する `Client` 。
+/// URL とボディは `with_secrets` で指定した値を伏せて記録し、ヘッダーは記録しない。
+#[derive(Debug, Clone)]
+pub struct CassetteClient {
+    client: Client,
+    cassette: Option<Arc<Cassette>>,
+    secrets: Arc<Vec<String>>,
+}
+
+impl CassetteClient {
+    pub fn new(client: Client, name: &str) -> Result<CassetteClient, CassetteError> {
+        let cassette = Cassette::open(name)?.map(Arc::new);
+        Ok(CassetteClient {
+            client,
+            cassette,
+            secrets: Arc::new(vec![]),
+        })
+    }
+
+    /// URL やボディに含まれうる秘密の値を指定する。キーと記録ではこれらを伏せる。
+    pub fn with_secrets(self, secrets: impl IntoIterator<Item = String>) -> CassetteClient {
+        let secrets = secrets.into_iter().filter(|s| !s.is_empty()).collect();
+        CassetteClient {
+            secrets: Arc::new(secrets),
+            ..self
+        }
+    }
+
+    pub fn get(&self, url: impl IntoUrl) -> RequestBuilder {
+        self.client.get(url)
+    }
+
+    pub fn post(&self, url: impl IntoUrl) -> RequestBuilder {
+        self.client.post(url)
+    }
+
+    pub fn request(&self, method: Method, url: impl IntoUrl) -> RequestBuilder {
+        self.client.request(method, url)
+    }
+
+    pub async fn send(&self, request: RequestBuilder) -> Result<Response, CassetteError> {
+        let request = request.build()?;
+        let Some(cassette) = &self.cassette else {
+            return Ok(self.client.execute(request).await?);
+        };
+
+        // 再生時も同じように伏せるので、キーは秘密の値が変わっても一致する
+        let key = redact(&format!("{} {}", request.method(), request.url()), &self.secrets);
+        match cassette.mode() {
+            CassetteMode::Record => {
+                let recorded_request = RecordedHttpRequest {
+                    body: request
+                        .body()
+                        .and_then(|b| b.as_bytes())
+                        .map(|b| RecordedBody::new(b).redacted(&self.secrets)),
+                };
+                let response = self.client.execute(request).await?;
+                let recorded_response = RecordedHttpResponse {
+                    status: response.status().as_u16(),
+                    content_type: response
+                        .headers()
+                        .get(CONTENT_TYPE)
+                        .and_then(|v| v.to_str().ok())
+                        .map(str::to_string),
+                    body: RecordedBody::new(&response.bytes().await?),
+                };
+                cassette.record(&key, &recorded_request, &recorded_response)?;
+                recorded_response.into_response()
+            }
+            CassetteMode::Replay => {
+                let recorded_response: RecordedHttpResponse = cassette.replay(&key)?;
+                recorded_response.into_response()
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct RecordedHttpRequest {
+    body: Option<RecordedBody>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct RecordedHttpResponse {
+    status: u16,
+    content_type: Option<String>,
+    body: RecordedBody,
+}
+
+impl RecordedHttpResponse {
+    fn into_response(self) -> Result<Response, CassetteError> {
+        let mut builder = http::Response::builder().status(self.status);
+        if let Some(content_type) = self.content_type {
+            builder = builder.header(CONTENT_TYPE, content_type);
+        }
+        let response = builder.body(self.body.into_bytes()?)?;
+        Ok(response.into())
+    }
+}
+
+/// UTF-8 として読めるボディはそのまま、それ以外は Base64 で記録する。
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case", tag = "kind", content = "value")]
+enum RecordedBody {
+    Text(String),
+    Base64(String),
+}
+
+impl RecordedBody {
+    fn new(bytes: &[u8]) -> RecordedBody {
+        match std::str::from_utf8(bytes) {
+            Ok(text) => RecordedBody::Text(text.to_string()),
+            Err(_) => RecordedBody::Base64(BASE64_STANDARD.encode(bytes)),
+        }
+    }
+
+    fn redacted(self, secrets: &[String]) -> RecordedBody {
+        match self {
+            RecordedBody::Text(text) => RecordedBody::Text(redact(&text, secrets)),
+            otherwise => otherwise,
+        }
+    }
+
+    fn into_bytes(self) -> Result<Vec<u8>, CassetteError> {
+        match self {
+            RecordedBody::Text(text) => Ok(text.into_bytes()),
+            RecordedBody::Base64(encoded) => Ok(BASE64_STANDARD.decode(encoded)?),
+        }
+    }
+}
+
+/// `secrets` の出現をそのままの形と URL エンコードした形の両方で伏せる。
+fn redact(text: &str, secrets: &[String]) -> String {
+    secrets.iter().fold(text.to_string(), |redacted, secret| {
+        let encoded: String = url::form_urlencoded::byte_serialize(secret.as_bytes()).collect();
+        redacted.replace(secret, REDACTED).replace(&encoded, REDACTED)
+    })
+}
+
+#[derive(Debug, ThisError)]
+pub enum CassetteError {
+    #[error("both {RECORD_OPTION} and {REPLAY_OPTION} are specified")]
+    ConflictingModes,
+
+    #[error("no recorded interaction left for {0}")]
+    NotRecorded(String),
+
+    #[error("cassette io error: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("cassette format error: {0}")]
+    Format(#[from] SerdeJsonError),
+
+    #[error("cassette body decode error: {0}")]
+    Decode(#[from] DecodeError),
+
+    #[error("http error: {0}")]
+    Http(#[from] http::Error),
+
+    #[error("communication error: {0}")]
+    Communication(#[from] ReqwestError),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Cassette, CassetteMode, redact};
+
+    use std::{env::temp_dir, fs};
+
+    use uuid::Uuid;
+
+    #[test]
+    fn reopening_for_record_keeps_interactions() {
+        let directory = temp_dir().join(format!("lnb-cassette-{}", Uuid::now_v7()));
+        let first = Cassette::open_in(CassetteMode::Record, &directory, "test").expect("should open");
+        first.record("key", &"first", &1).expect("should record");
+        let second = Cassette::open_in(CassetteMode::Record, &directory, "test").expect("should open");
+        second.record("key", &"second", &2).expect("should record");
+
+        let replaying = Cassette::open_in(CassetteMode::Replay, &directory, "test").expect("should open");
+        assert_eq!(replaying.replay::<i32>("key").expect("should replay"), 1);
+        assert_eq!(replaying.replay::<i32>("key").expect("should replay"), 2);
+        fs::remove_dir_all(&directory).ok();
+    }
+
+    #[test]
+    fn redacts_plain_and_encoded_secrets() {
+        let secrets = vec!["tok/en 1".to_string(), "abc123".to_string()];
+        let key = "GET https://example.com/v6/abc123/latest?key=tok%2Fen+1";
+        assert_eq!(
+            redact(key, &secrets),
+            "GET https://example.com/v6/<redacted>/latest?key=<redacted>"
+        );
+        assert_eq!(redact(r#"{"token":"tok/en 1"}"#, &secrets), r#"{"token":"<redacted>"}"#);
+    }
+}
@@ -1,3 +1,5 @@
+use std::collections::HashMap;
+
 use serde::Deserialize;
 
 #[derive(Debug, Clone, Deserialize)]
@@ -6,4 +8,26 @@ pub struct ConfigAssistant {
 
     #[serde(default = "Default::default")]
     pub sensitive_marker: String,
+
+    /// structured output で応答本文に付ける説明。省略時は組み込みのもの。
+    pub response_description: Option<String>,
+
+    /// `!persona` や各クライアントの設定で切り替えられるペルソナ。
+    #[serde(default)]
+    pub personas: HashMap<String, ConfigAssistantPersona>,
+}
+
+/// 名前付きのペルソナ。省略した項目は既定のものに従う。
+#[derive(Debug, Clone, Deserialize)]
+pub struct ConfigAssistantPersona {
+    pub system_role: String,
+
+    /// 既定で使うモデル。自動選択の規則や `!change` による指定が優先する。
+    pub model: Option<String>,
+
+    /// 使える function 。省略時はすべて。
+    pub tools: Option<Vec<String>>,
+
+    /// structured output で応答本文に付ける説明。
+    pub response_description: Option<String>,
 }
@@ -15,12 +15,18 @@ pub struct ConfigClientMastodon {
     pub max_length: usize,
     pub remote_fetch_delay_seconds: usize,
     pub math_renderer: ConfigClientMathRenderer,
+
+    /// 新しい会話で使うペルソナ。省略時は既定のペルソナ。
+    pub persona: Option<String>,
 }
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct ConfigClientDiscord {
     pub token: String,
     pub max_length: usize,
+
+    /// 新しい会話で使うペルソナ。省略時は既定のペルソナ。
+    pub persona: Option<String>,
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -7,18 +7,186 @@ use serde_json::Value;
 pub struct ConfigLlm {
     pub default: String,
     pub models: HashMap<String, ConfigLlmModel>,
+
+    #[serde(default = "Default::default")]
+    pub initialization: ConfigLlmInitialization,
+
+    /// 全モデル共通の回数制限。
+    #[serde(default)]
+    pub limits: ConfigLlmLimits,
+
+    /// ユーザーの権限ごとの回数制限。モデルごとの設定より優先する。
+    #[serde(default)]
+    pub role_limits: ConfigLlmRoleLimits,
+
+    /// モデルの自動選択規則。先頭から順に評価し、最初に条件を満たしたものを使う。
+    /// `!change` などでモデルが指定されている会話には適用しない。
+    #[serde(default)]
+    pub routes: Vec<ConfigLlmRoute>,
+}
+
+/// モデルの初期化に失敗したときの再試行設定。
+#[derive(Debug, Clone, Deserialize)]
+pub struct ConfigLlmInitialization {
+    /// 初回失敗後の再試行までの秒数。失敗するたびに倍になる。
+    pub cooldown_seconds: u64,
+
+    /// 再試行までの秒数の上限。
+    pub max_cooldown_seconds: u64,
+}
+
+impl Default for ConfigLlmInitialization {
+    fn default() -> ConfigLlmInitialization {
+        ConfigLlmInitialization {
+            cooldown_seconds: 30,
+            max_cooldown_seconds: 1800,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct ConfigLlmModel {
     pub backend: ConfigLlmBackend,
     pub config: Value,
+
+    /// 費用概算用の価格表。
+    pub price: Option<ConfigLlmModelPrice>,
+
+    /// コンテキストウィンドウの管理設定。省略時は全メッセージを送信する。
+    pub context: Option<ConfigLlmModelContext>,
+
+    /// このモデルでの回数制限。指定した項目だけ `ConfigLlm::limits` を上書きする。
+    pub limits: Option<ConfigLlmLimits>,
+}
+
+/// 1 回の応答の生成で LLM とやりとりする回数の制限。
+/// 省略した項目は上位の設定、それもなければ既定値に従う。
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct ConfigLlmLimits {
+    /// tool calling の往復回数の上限。
+    pub max_tool_rounds: Option<usize>,
+
+    /// 出力長で打ち切られた応答の続きを生成させる回数の上限。
+    pub max_continuation_rounds: Option<usize>,
+
+    /// tool calling と続きの生成を合わせた回数の上限。
+    pub max_total_rounds: Option<usize>,
+
+    /// 上限に達したときの扱い。
+    pub on_exceeded: Option<ConfigLlmLimitPolicy>,
+}
+
+impl ConfigLlmLimits {
+    /// `other` で指定されている項目を上書きする。
+    pub fn overridden_by(&self, other: &ConfigLlmLimits) -> ConfigLlmLimits {
+        ConfigLlmLimits {
+            max_tool_rounds: other.max_tool_rounds.or(self.max_tool_rounds),
+            max_continuation_rounds: other.max_continuation_rounds.or(self.max_continuation_rounds),
+            max_total_rounds: other.max_total_rounds.or(self.max_total_rounds),
+            on_exceeded: other.on_exceeded.or(self.on_exceeded),
+        }
+    }
+}
+
+/// `UserRole` の種類ごとの回数制限。
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct ConfigLlmRoleLimits {
+    pub privileged: Option<ConfigLlmLimits>,
+    pub normal: Option<ConfigLlmLimits>,
+    pub scoped: Option<ConfigLlmLimits>,
+}
+
+/// 回数制限に達したときの扱い。
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConfigLlmLimitPolicy {
+    /// エラーにする。
+    #[default]
+    Error,
+
+    /// それまでの本文に省略の印を付けて終了する。
+    /// tool calling の上限でまだ本文が生成されていなければ `Error` と同じ。
+    Truncate,
+
+    /// tool を渡さずに送信して最終的な応答を出させる。
+    /// 続きの生成の上限では `Truncate` と同じ。
+    FinalAnswer,
+}
+
+/// モデルの自動選択規則。
+#[derive(Debug, Clone, Deserialize)]
+pub struct ConfigLlmRoute {
+    /// 選択するモデル。
+    pub model: String,
+
+    /// 条件。指定した項目をすべて満たす場合に選択する。
+    #[serde(default)]
+    pub when: ConfigLlmRouteCondition,
+}
+
+/// モデルの自動選択の条件。判定には最後の `UserMessage` を使う。
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct ConfigLlmRouteCondition {
+    /// `UserRole` がこの scope を持つ。
+    pub scope: Option<String>,
+
+    /// 画像の有無。
+    pub has_image: Option<bool>,
+
+    /// identity のプラットフォーム部分 (`mastodon` など) 。
+    pub platform: Option<String>,
+
+    /// 本文の文字数の下限。
+    pub min_length: Option<usize>,
+
+    /// 本文の文字数の上限。
+    pub max_length: Option<usize>,
+
+    /// 本文にマッチする正規表現。
+    pub pattern: Option<String>,
+}
+
+/// コンテキストウィンドウの管理設定。
+#[derive(Debug, Clone, Deserialize)]
+pub struct ConfigLlmModelContext {
+    /// 送信するメッセージの概算トークン数の上限。
+    pub budget_tokens: usize,
+
+    /// 要約を除いて直近のメッセージに割り当てる割合。
+    #[serde(default = "ConfigLlmModelContext::default_recent_ratio")]
+    pub recent_ratio: f64,
+
+    /// 要約の生成に使うモデル。省略時は会話と同じモデルを使う。
+    pub summary_model: Option<String>,
+}
+
+impl ConfigLlmModelContext {
+    fn default_recent_ratio() -> f64 {
+        0.6
+    }
+}
+
+/// 100 万トークンあたりの価格。単位は任意だが全モデルで揃える。
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct ConfigLlmModelPrice {
+    pub prompt: f64,
+    pub completion: f64,
+
+    /// キャッシュされた prompt の価格。省略時は `prompt` と同じ。
+    pub cached: Option<f64>,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
 #[serde(rename_all = "snake_case")]
 pub enum ConfigLlmBackend {
     Openai,
+    Claude,
+
+    /// `models` に列挙したモデルを順に試す。
+    Fallback,
+
+    /// fixture に記述された応答を順に返す。テスト用。
+    Scripted,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
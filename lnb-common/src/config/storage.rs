@@ -21,4 +21,7 @@ pub enum ConfigStorageBackend {
 #[derive(Debug, Clone, Deserialize, Default)]
 pub struct ConfigStorageSqlite {
     pub filepath: PathBuf,
+
+    /// function の呼び出し記録を保持する日数。省略した場合は削除しない。
+    pub tool_call_retention_days: Option<u64>,
 }
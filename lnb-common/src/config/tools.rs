@@ -2,7 +2,10 @@ use lnb_daily_private::{
     masturbation::MasturbationConfiguration, menstruation::MenstruationConfiguration, schedule::ScheduleConfiguration,
     temperature::TemperatureConfiguration, underwear::UnderwearConfiguration,
 };
+use std::collections::HashMap;
+
 use serde::Deserialize;
+use serde_json::Value;
 
 /// [tool]
 #[derive(Debug, Clone, Default, Deserialize)]
@@ -12,6 +15,99 @@ pub struct ConfigTools {
     pub get_illust_url: Option<ConfigToolsGetIllustUrl>,
     pub exchange_rate: Option<ConfigToolsExchangeRate>,
     pub daily_private: Option<ConfigToolsDailyPrivate>,
+
+    /// `policies` でタイムアウトが指定されていない function のタイムアウト秒数。
+    pub default_timeout_seconds: Option<f64>,
+
+    /// function ごとの実行ポリシー。キーは function 名。
+    #[serde(default)]
+    pub policies: HashMap<String, ConfigToolsPolicy>,
+
+    /// 接続する MCP サーバー。キーはサーバー名で、提供される function 名の接頭辞になる。
+    #[serde(default)]
+    pub mcp_servers: HashMap<String, ConfigToolsMcpServer>,
+
+    /// 設定だけで定義する HTTP function 。
+    #[serde(default)]
+    pub http_tools: Vec<ConfigToolsHttpTool>,
+}
+
+/// function の実行ポリシー。
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct ConfigToolsPolicy {
+    /// タイムアウト秒数。
+    pub timeout_seconds: Option<f64>,
+
+    /// 指定した場合、この scope を持つユーザーにだけ function を提示する。
+    pub required_scope: Option<String>,
+}
+
+/// MCP サーバーへの接続方法。
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "snake_case", tag = "transport")]
+pub enum ConfigToolsMcpServer {
+    /// 子プロセスとして起動し、標準入出力で通信する。
+    Stdio {
+        command: String,
+
+        #[serde(default)]
+        args: Vec<String>,
+
+        #[serde(default)]
+        env: HashMap<String, String>,
+    },
+
+    /// Streamable HTTP で通信する。
+    Http {
+        url: String,
+
+        #[serde(default)]
+        headers: HashMap<String, String>,
+    },
+}
+
+/// HTTP リクエスト 1 回で完結する function 。
+/// `url` ・ `headers` の値・ `body` は upon のテンプレートで、
+/// `args` で引数を、 `secrets` で環境変数から読んだ値を参照できる。
+#[derive(Debug, Clone, Deserialize)]
+pub struct ConfigToolsHttpTool {
+    pub name: String,
+    pub description: String,
+
+    /// 引数の JSON Schema 。
+    pub parameters: Value,
+
+    #[serde(default)]
+    pub method: ConfigToolsHttpMethod,
+
+    pub url: String,
+
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+
+    /// テンプレートから参照する名前と、値を読む環境変数名。
+    #[serde(default)]
+    pub secrets: HashMap<String, String>,
+
+    /// 省略した場合、 GET 以外では引数をそのまま JSON で送る。
+    pub body: Option<String>,
+
+    /// 応答の JSON から結果として取り出す部分の JSON Pointer 。
+    pub extract: Option<String>,
+
+    /// rate limits の `tools` のカテゴリー名。
+    pub rate_limit: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum ConfigToolsHttpMethod {
+    #[default]
+    Get,
+    Post,
+    Put,
+    Patch,
+    Delete,
 }
 
 #[derive(Debug, Clone, Deserialize)]
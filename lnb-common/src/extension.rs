@@ -10,7 +10,11 @@ pub trait ContextExt {
 
 impl ContextExt for MessageContext {
     fn hashed_identity(&self) -> String {
-        let identity = self.identity().unwrap_or(SYSTEM_IDENTITY);
-        BASE64_STANDARD.encode(Sha256::digest(identity))
+        hash_identity(self.identity().unwrap_or(SYSTEM_IDENTITY))
     }
 }
+
+/// `ContextExt::hashed_identity` と同じ方法で identity をハッシュ化する。
+pub fn hash_identity(identity: &str) -> String {
+    BASE64_STANDARD.encode(Sha256::digest(identity))
+}
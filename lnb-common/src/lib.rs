@@ -1,3 +1,4 @@
+pub mod cassette;
 pub mod config;
 pub mod debug;
 pub mod extension;
@@ -1,12 +1,14 @@
+use crate::cassette::{CassetteClient, CassetteError};
+
 use lnb_core::APP_USER_AGENT;
-use reqwest::{Client, Error as ReqwestError};
+use reqwest::Error as ReqwestError;
 use serde::Deserialize;
 use serde_json::json;
 use thiserror::Error as ThisError;
 
 #[derive(Debug, Clone)]
 pub struct MathRendererClient {
-    client: Client,
+    client: CassetteClient,
     base_url: String,
     scale: f64,
 }
@@ -16,6 +18,9 @@ pub enum MathRendererError {
     #[error("math renderer communication error: {0}")]
     Communication(#[from] ReqwestError),
 
+    #[error("math renderer cassette error: {0}")]
+    Cassette(#[from] CassetteError),
+
     #[error("rendering error: {0}")]
     Rendering(String),
 }
@@ -28,6 +33,7 @@ struct RenderingError {
 impl MathRendererClient {
     pub fn new(base_url: impl Into<String>, scale: f64) -> Result<MathRendererClient, MathRendererError> {
         let client = reqwest::ClientBuilder::new().user_agent(APP_USER_AGENT).build()?;
+        let client = CassetteClient::new(client, "math_renderer")?;
         Ok(MathRendererClient {
             client,
             base_url: base_url.into(),
@@ -42,12 +48,8 @@ impl MathRendererClient {
             "scale": self.scale,
             "preserveAlpha": true,
         });
-        let resp = self
-            .client
-            .post(format!("{}/renderMath", self.base_url))
-            .json(&payload)
-            .send()
-            .await?;
+        let request = self.client.post(format!("{}/renderMath", self.base_url)).json(&payload);
+        let resp = self.client.send(request).await?;
         let png_bytes = if resp.status().is_success() {
             resp.bytes().await?
         } else {
@@ -68,12 +70,11 @@ impl MathRendererClient {
             "scale": self.scale,
             "preserveAlpha": true,
         });
-        let resp = self
+        let request = self
             .client
             .post(format!("{}/renderMathMultiple", self.base_url))
-            .json(&payload)
-            .send()
-            .await?;
+            .json(&payload);
+        let resp = self.client.send(request).await?;
         let png_bytes = if resp.status().is_success() {
             resp.bytes().await?
         } else {
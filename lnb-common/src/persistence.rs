@@ -1,7 +1,11 @@
 mod conversation_sqlite;
 mod error;
 mod reminder_redis;
+mod tool_call_sqlite;
+mod usage_sqlite;
 
 pub use conversation_sqlite::SqliteConversationDb;
 pub use error::PersistenceError;
 pub use reminder_redis::RedisReminderDb;
+pub use tool_call_sqlite::SqliteToolCallDb;
+pub use usage_sqlite::{SqliteUsageDb, UsageRecord};
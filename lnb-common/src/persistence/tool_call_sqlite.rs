@@ -0,0 +1,112 @@
+use crate::{config::storage::ConfigStorageSqlite, persistence::PersistenceError};
+
+use std::time::Duration;
+
+use futures::TryFutureExt;
+use lnb_core::{interface::storage::ToolCallRecord, model::conversation::ConversationId};
+use sqlx::{FromRow, SqlitePool, types::Json};
+use time::{Date, OffsetDateTime, UtcOffset};
+use uuid::Uuid;
+
+#[derive(Debug, Clone)]
+pub struct SqliteToolCallDb {
+    pool: SqlitePool,
+}
+
+impl SqliteToolCallDb {
+    pub async fn connect(config: &ConfigStorageSqlite) -> Result<SqliteToolCallDb, PersistenceError> {
+        let pool = SqlitePool::connect(&config.filepath.to_string_lossy())
+            .map_err(PersistenceError::by_backend)
+            .await?;
+        Ok(SqliteToolCallDb { pool })
+    }
+
+    pub async fn add(&self, record: &ToolCallRecord) -> Result<(), PersistenceError> {
+        sqlx::query(
+            r#"
+            INSERT INTO tool_calls
+                (conversation_id, identity, tool_name, arguments, result_summary, error, duration_milliseconds, called_at)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?);
+        "#,
+        )
+        .bind(record.conversation_id.0)
+        .bind(&record.identity)
+        .bind(&record.tool_name)
+        .bind(Json(&record.arguments))
+        .bind(&record.result_summary)
+        .bind(&record.error)
+        .bind(record.duration.as_millis() as i64)
+        .bind(record.called_at.to_offset(UtcOffset::UTC))
+        .execute(&self.pool)
+        .map_err(PersistenceError::by_backend)
+        .await?;
+        Ok(())
+    }
+
+    /// `before` より前の記録を削除する。
+    pub async fn purge(&self, before: OffsetDateTime) -> Result<u64, PersistenceError> {
+        let result = sqlx::query(r#"DELETE FROM tool_calls WHERE called_at < ?;"#)
+            .bind(before.to_offset(UtcOffset::UTC))
+            .execute(&self.pool)
+            .map_err(PersistenceError::by_backend)
+            .await?;
+        Ok(result.rows_affected())
+    }
+
+    /// 期間 (UTC の日付) ・ identity ・ function 名で絞り込んで新しい順に取得する。
+    pub async fn fetch(
+        &self,
+        since: Option<Date>,
+        until: Option<Date>,
+        identity: Option<&str>,
+        tool_name: Option<&str>,
+        count: usize,
+    ) -> Result<Vec<ToolCallRecord>, PersistenceError> {
+        let rows: Vec<SqliteRowToolCall> = sqlx::query_as(
+            r#"
+            SELECT conversation_id, identity, tool_name, arguments, result_summary, error, duration_milliseconds, called_at
+            FROM tool_calls
+            WHERE
+                (?1 IS NULL OR date(called_at) >= ?1) AND (?2 IS NULL OR date(called_at) <= ?2)
+                AND (?3 IS NULL OR identity = ?3) AND (?4 IS NULL OR tool_name = ?4)
+            ORDER BY called_at DESC, id DESC
+            LIMIT ?5;
+        "#,
+        )
+        .bind(since)
+        .bind(until)
+        .bind(identity)
+        .bind(tool_name)
+        .bind(count as i64)
+        .fetch_all(&self.pool)
+        .map_err(PersistenceError::by_backend)
+        .await?;
+
+        let records = rows
+            .into_iter()
+            .map(|r| ToolCallRecord {
+                conversation_id: ConversationId(r.conversation_id),
+                identity: r.identity,
+                tool_name: r.tool_name,
+                arguments: r.arguments.0,
+                result_summary: r.result_summary,
+                error: r.error,
+                duration: Duration::from_millis(r.duration_milliseconds as u64),
+                called_at: r.called_at,
+            })
+            .collect();
+        Ok(records)
+    }
+}
+
+#[derive(Debug, Clone, FromRow)]
+struct SqliteRowToolCall {
+    conversation_id: Uuid,
+    identity: String,
+    tool_name: String,
+    arguments: Json<serde_json::Value>,
+    result_summary: Option<String>,
+    error: Option<String>,
+    duration_milliseconds: i64,
+    called_at: OffsetDateTime,
+}
@@ -0,0 +1,104 @@
+use crate::{config::storage::ConfigStorageSqlite, persistence::PersistenceError};
+
+use futures::TryFutureExt;
+use lnb_core::interface::llm::LlmUsage;
+use sqlx::{FromRow, SqlitePool};
+use time::Date;
+
+#[derive(Debug, Clone)]
+pub struct SqliteUsageDb {
+    pool: SqlitePool,
+}
+
+/// identity・モデル・日付ごとのトークン使用量。
+#[derive(Debug, Clone)]
+pub struct UsageRecord {
+    pub identity: String,
+    pub model: String,
+    pub date: Date,
+    pub usage: LlmUsage,
+}
+
+impl SqliteUsageDb {
+    pub async fn connect(config: &ConfigStorageSqlite) -> Result<SqliteUsageDb, PersistenceError> {
+        let pool = SqlitePool::connect(&config.filepath.to_string_lossy())
+            .map_err(PersistenceError::by_backend)
+            .await?;
+        Ok(SqliteUsageDb { pool })
+    }
+
+    pub async fn add(&self, identity: &str, model: &str, date: Date, usage: &LlmUsage) -> Result<(), PersistenceError> {
+        sqlx::query(
+            r#"
+            INSERT INTO usages (identity, model, date, prompt_tokens, completion_tokens, reasoning_tokens, cached_tokens)
+            VALUES (?, ?, ?, ?, ?, ?, ?)
+            ON CONFLICT DO UPDATE SET
+                prompt_tokens = prompt_tokens + excluded.prompt_tokens,
+                completion_tokens = completion_tokens + excluded.completion_tokens,
+                reasoning_tokens = reasoning_tokens + excluded.reasoning_tokens,
+                cached_tokens = cached_tokens + excluded.cached_tokens;
+        "#,
+        )
+        .bind(identity)
+        .bind(model)
+        .bind(date)
+        .bind(usage.prompt_tokens as i64)
+        .bind(usage.completion_tokens as i64)
+        .bind(usage.reasoning_tokens as i64)
+        .bind(usage.cached_tokens as i64)
+        .execute(&self.pool)
+        .map_err(PersistenceError::by_backend)
+        .await?;
+        Ok(())
+    }
+
+    /// 期間と identity で絞り込んで取得する。日付の降順に並ぶ。
+    pub async fn fetch(
+        &self,
+        since: Option<Date>,
+        until: Option<Date>,
+        identity: Option<&str>,
+    ) -> Result<Vec<UsageRecord>, PersistenceError> {
+        let rows: Vec<SqliteRowUsage> = sqlx::query_as(
+            r#"
+            SELECT identity, model, date, prompt_tokens, completion_tokens, reasoning_tokens, cached_tokens
+            FROM usages
+            WHERE (?1 IS NULL OR date >= ?1) AND (?2 IS NULL OR date <= ?2) AND (?3 IS NULL OR identity = ?3)
+            ORDER BY date DESC, identity ASC, model ASC;
+        "#,
+        )
+        .bind(since)
+        .bind(until)
+        .bind(identity)
+        .fetch_all(&self.pool)
+        .map_err(PersistenceError::by_backend)
+        .await?;
+
+        let records = rows
+            .into_iter()
+            .map(|r| UsageRecord {
+                identity: r.identity,
+                model: r.model,
+                date: r.date,
+                usage: LlmUsage {
+                    prompt_tokens: r.prompt_tokens as u64,
+                    completion_tokens: r.completion_tokens as u64,
+                    reasoning_tokens: r.reasoning_tokens as u64,
+                    cached_tokens: r.cached_tokens as u64,
+                },
+            })
+            .collect();
+        Ok(records)
+    }
+}
+
+#[derive(Debug, Clone, FromRow)]
+struct SqliteRowUsage {
+    identity: String,
+    model: String,
+    date: Date,
+    prompt_tokens: i64,
+    completion_tokens: i64,
+    reasoning_tokens: i64,
+    cached_tokens: i64,
+}
@@ -1,4 +1,4 @@
-use std::{fs::read_to_string, io::Error as IoError, path::Path};
+use std::{collections::HashMap, fs::read_to_string, io::Error as IoError, path::Path};
 
 use lnb_rate_limiter::{Rate, RateFilter, RateLimiter};
 use regex::{Error as RegexError, Regex};
@@ -11,6 +11,10 @@ use time::Duration;
 pub struct RateLimits {
     pub conversation: RateLimitsCategory,
     pub image_generator: RateLimitsCategory,
+
+    /// 設定で定義した function などが名前で参照するカテゴリー。
+    #[serde(default)]
+    pub tools: HashMap<String, RateLimitsCategory>,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
@@ -1,4 +1,9 @@
-use std::{fs::read_to_string, io::Error as IoError, path::Path};
+use std::{
+    fs::read_to_string,
+    io::Error as IoError,
+    path::Path,
+    sync::{Arc, RwLock},
+};
 
 use lnb_core::model::user_role::UserRole;
 use regex::{Error as RegexError, Regex};
@@ -27,6 +32,24 @@ impl UserRolesGroup {
     }
 }
 
+/// 再読み込みで差し替えられる `UserRolesGroup` 。
+#[derive(Debug, Clone)]
+pub struct SharedUserRolesGroup(Arc<RwLock<UserRolesGroup>>);
+
+impl SharedUserRolesGroup {
+    pub fn new(group: UserRolesGroup) -> SharedUserRolesGroup {
+        SharedUserRolesGroup(Arc::new(RwLock::new(group)))
+    }
+
+    pub fn get(&self, user: &str) -> UserRole {
+        self.0.read().expect("poisoned").get(user).clone()
+    }
+
+    pub fn replace(&self, group: UserRolesGroup) {
+        *self.0.write().expect("poisoned") = group;
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct RoleFilter {
     pattern: Regex,
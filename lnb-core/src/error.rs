@@ -65,6 +65,10 @@ pub enum ServerError {
 
     #[error("rate limit exceeded; try later")]
     RateLimitExceeded,
+
+    /// 設定されていないペルソナが指定された。
+    #[error("persona {0} not found")]
+    PersonaNotFound(String),
 }
 
 impl ServerError {
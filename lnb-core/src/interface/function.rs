@@ -17,13 +17,21 @@ use serde_json::Value;
 
 pub type ArcFunction = Arc<dyn Function + 'static>;
 
-#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct FunctionDescriptor {
     pub name: String,
     pub description: String,
     pub parameters: DescribedSchema,
 }
 
+/// LLM の API が受け付ける function 名 (`^[a-zA-Z0-9_-]{1,64}$`) か。
+pub fn is_valid_function_name(name: &str) -> bool {
+    (1..=64).contains(&name.len())
+        && name
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'_' || b == b'-')
+}
+
 #[derive(Debug, Clone, Default)]
 pub struct FunctionResponse {
     pub result: Value,
@@ -1,13 +1,19 @@
 use crate::{
     error::LlmError,
     interface::function::FunctionDescriptor,
-    model::{conversation::IncompleteConversation, message::MessageToolCalling},
+    model::{
+        conversation::IncompleteConversation,
+        message::{AssistantActions, MessageToolCalling},
+    },
 };
 
-use std::sync::Arc;
+use std::{
+    ops::{Add, AddAssign},
+    sync::Arc,
+};
 
-use futures::future::BoxFuture;
-use serde::Deserialize;
+use futures::{FutureExt, channel::mpsc::UnboundedSender, future::BoxFuture};
+use serde::{Deserialize, Serialize};
 
 pub type ArcLlm = Arc<dyn Llm + 'static>;
 
@@ -17,7 +23,26 @@ pub trait Llm: Send + Sync {
         &'a self,
         conversation: &'a IncompleteConversation,
         function_descriptors: &'a [&'a FunctionDescriptor],
-    ) -> BoxFuture<'a, Result<LlmUpdate, LlmError>>;
+    ) -> BoxFuture<'a, Result<LlmResponse, LlmError>>;
+
+    /// `Conversation` を送信し、生成途中の差分を `delta_sender` に逐次送る。
+    /// 最終的な結果は `send_conversation` と同じものを返す。
+    /// デフォルトでは差分を送らずに `send_conversation` を呼ぶ。
+    fn send_conversation_stream<'a>(
+        &'a self,
+        conversation: &'a IncompleteConversation,
+        function_descriptors: &'a [&'a FunctionDescriptor],
+        delta_sender: UnboundedSender<LlmDelta>,
+    ) -> BoxFuture<'a, Result<LlmResponse, LlmError>> {
+        let _ = delta_sender;
+        self.send_conversation(conversation, function_descriptors)
+    }
+
+    /// バックエンドに到達でき、モデルが利用できるかを確認する。
+    /// デフォルトでは何もせずに成功とする。
+    fn probe(&self) -> BoxFuture<'_, Result<(), LlmError>> {
+        async { Ok(()) }.boxed()
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -28,10 +53,101 @@ pub enum LlmUpdate {
     Filtered,
 }
 
+/// `Llm` からの応答。
+#[derive(Debug, Clone)]
+pub struct LlmResponse {
+    pub update: LlmUpdate,
+
+    /// 実際に応答したモデル名。バックエンドが直接応答した場合は `None` 。
+    pub answered_model: Option<String>,
+
+    /// このリクエストで消費したトークン数。
+    pub usage: LlmUsage,
+
+    /// 応答したモデルより前に試して破棄したモデルごとの消費トークン数。
+    pub discarded_usages: Vec<(String, LlmUsage)>,
+
+    /// バックエンドが応答を保存している場合、その ID 。次の応答で連鎖の起点として使える。
+    pub response_id: Option<String>,
+
+    /// バックエンドが返した推論の要約。
+    pub reasoning_summary: Option<String>,
+}
+
+impl LlmResponse {
+    pub fn new(update: LlmUpdate, usage: LlmUsage) -> LlmResponse {
+        LlmResponse {
+            update,
+            answered_model: None,
+            usage,
+            discarded_usages: vec![],
+            response_id: None,
+            reasoning_summary: None,
+        }
+    }
+}
+
+impl From<LlmUpdate> for LlmResponse {
+    fn from(update: LlmUpdate) -> LlmResponse {
+        LlmResponse::new(update, LlmUsage::default())
+    }
+}
+
+/// トークン使用量。
+/// `reasoning_tokens` は `completion_tokens` の、 `cached_tokens` は `prompt_tokens` の内数。
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct LlmUsage {
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    pub reasoning_tokens: u64,
+    pub cached_tokens: u64,
+}
+
+impl Add for LlmUsage {
+    type Output = LlmUsage;
+
+    fn add(self, rhs: LlmUsage) -> LlmUsage {
+        LlmUsage {
+            prompt_tokens: self.prompt_tokens + rhs.prompt_tokens,
+            completion_tokens: self.completion_tokens + rhs.completion_tokens,
+            reasoning_tokens: self.reasoning_tokens + rhs.reasoning_tokens,
+            cached_tokens: self.cached_tokens + rhs.cached_tokens,
+        }
+    }
+}
+
+impl AddAssign for LlmUsage {
+    fn add_assign(&mut self, rhs: LlmUsage) {
+        *self = *self + rhs;
+    }
+}
+
+/// ストリーミング中に得られる差分。
+#[derive(Debug, Clone)]
+pub enum LlmDelta {
+    /// 応答本文の断片。
+    Text(String),
+
+    /// tool calling の断片。同じ `index` のものを結合すると 1 つの呼び出しになる。
+    ToolCalling {
+        index: usize,
+        id: Option<String>,
+        name: Option<String>,
+        arguments: String,
+    },
+
+    /// それまでに送った差分を破棄する。
+    Restart,
+}
+
 /// assistant role としての応答内容。
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct LlmAssistantResponse {
     pub text: String,
     pub language: Option<String>,
     pub sensitive: Option<bool>,
+
+    /// 返信本文以外の動作。 structured output の場合のみ得られる。
+    #[serde(default, flatten)]
+    pub actions: AssistantActions,
 }
@@ -1,18 +1,18 @@
-use futures::future::BoxFuture;
+use futures::{channel::mpsc::UnboundedSender, future::BoxFuture};
 
 use crate::{
     error::ServerError,
     interface::MessageContext,
     model::{
-        conversation::{ConversationId, ConversationUpdate},
+        conversation::{ConversationId, ConversationPersona, ConversationUpdate},
         message::Message,
     },
 };
 
 /// 旧 Assistant
 pub trait LnbServer: Send + Sync + 'static {
-    /// 新しい会話ツリーを開始する。
-    fn new_conversation(&self) -> BoxFuture<'_, Result<ConversationId, ServerError>>;
+    /// `persona` として新しい会話ツリーを開始する。
+    fn new_conversation(&self, persona: ConversationPersona) -> BoxFuture<'_, Result<ConversationId, ServerError>>;
 
     /// 会話ツリーを復元する。
     fn restore_conversation<'a>(
@@ -33,4 +33,27 @@ pub trait LnbServer: Send + Sync + 'static {
         conversation_id: ConversationId,
         user_message: Vec<Message>,
     ) -> BoxFuture<'_, Result<ConversationUpdate, ServerError>>;
+
+    /// `process_conversation` と同様だが、途中経過を `progress_sender` に逐次送る。
+    /// デフォルトでは途中経過を送らずに `process_conversation` を呼ぶ。
+    fn process_conversation_stream(
+        &self,
+        message_ctx: MessageContext,
+        conversation_id: ConversationId,
+        user_message: Vec<Message>,
+        progress_sender: UnboundedSender<ConversationProgress>,
+    ) -> BoxFuture<'_, Result<ConversationUpdate, ServerError>> {
+        let _ = progress_sender;
+        self.process_conversation(message_ctx, conversation_id, user_message)
+    }
+}
+
+/// `LnbServer::process_conversation_stream` の途中経過。
+#[derive(Debug, Clone)]
+pub enum ConversationProgress {
+    /// 生成途中の応答本文。その時点までの全文が入る。
+    Text(String),
+
+    /// tool を呼び出している。
+    ToolCalling(Vec<String>),
 }
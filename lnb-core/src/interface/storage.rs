@@ -1,9 +1,14 @@
 use crate::{
     error::StorageError,
+    interface::llm::LlmUsage,
     model::conversation::{Conversation, ConversationId},
 };
 
+use std::time::Duration;
+
 use futures::future::BoxFuture;
+use serde_json::Value;
+use time::{Date, OffsetDateTime};
 
 pub type BoxConversationStorage = Box<dyn ConversationStorage + 'static>;
 
@@ -33,6 +38,37 @@ pub trait ConversationStorage: Send + Sync {
         conversation: &'a Conversation,
         context_key: Option<&'a str>,
     ) -> BoxFuture<'a, Result<(), StorageError>>;
+
+    /// identity・モデル・日付ごとのトークン使用量に加算する。
+    fn add_usage<'a>(
+        &'a self,
+        identity: &'a str,
+        model: &'a str,
+        date: Date,
+        usage: LlmUsage,
+    ) -> BoxFuture<'a, Result<(), StorageError>>;
+
+    /// function の呼び出しを記録する。
+    fn add_tool_call<'a>(&'a self, record: &'a ToolCallRecord) -> BoxFuture<'a, Result<(), StorageError>>;
+}
+
+/// function の呼び出し 1 回分の記録。
+#[derive(Debug, Clone)]
+pub struct ToolCallRecord {
+    pub conversation_id: ConversationId,
+
+    /// ハッシュ化された identity 。
+    pub identity: String,
+
+    pub tool_name: String,
+    pub arguments: Value,
+
+    /// 結果の JSON の先頭部分。失敗した場合は `None` 。
+    pub result_summary: Option<String>,
+
+    pub error: Option<String>,
+    pub duration: Duration,
+    pub called_at: OffsetDateTime,
 }
 
 impl<T: ConversationStorage + 'static> From<T> for BoxConversationStorage {
@@ -1,4 +1,6 @@
-use crate::model::message::{AssistantMessage, Message, UserMessage};
+use crate::model::message::{AssistantActions, AssistantMessage, Message, UserMessage};
+
+use std::collections::HashMap;
 
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
@@ -29,25 +31,120 @@ impl ConversationModel {
     }
 }
 
+/// 会話で使うペルソナ。
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case", tag = "kind", content = "value")]
+pub enum ConversationPersona {
+    #[default]
+    Default,
+    Named(String),
+}
+
+impl ConversationPersona {
+    pub fn name(&self) -> Option<&str> {
+        match self {
+            ConversationPersona::Default => None,
+            ConversationPersona::Named(name) => Some(name),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Conversation {
     id: ConversationId,
     messages: Vec<Message>,
     model: ConversationModel,
+
+    #[serde(default)]
+    persona: ConversationPersona,
+
+    #[serde(default)]
+    summary: Option<ConversationSummary>,
+
+    /// モデル名ごとの、サーバー側に保存された応答の連鎖。
+    #[serde(default)]
+    response_chains: HashMap<String, ConversationResponseChain>,
 }
 
 impl Conversation {
     pub fn new_now(system: Option<Message>) -> Conversation {
+        Conversation::new_now_as(system, ConversationPersona::Default)
+    }
+
+    /// ペルソナを指定して作成する。
+    pub fn new_now_as(system: Option<Message>, persona: ConversationPersona) -> Conversation {
         Conversation {
             id: ConversationId::new_now(),
             messages: system.into_iter().collect(),
             model: ConversationModel::Default,
+            persona,
+            summary: None,
+            response_chains: HashMap::new(),
         }
     }
 
     pub fn id(&self) -> ConversationId {
         self.id
     }
+
+    pub fn messages(&self) -> &[Message] {
+        &self.messages
+    }
+
+    pub fn summary(&self) -> Option<&ConversationSummary> {
+        self.summary.as_ref()
+    }
+
+    pub fn persona(&self) -> &ConversationPersona {
+        &self.persona
+    }
+
+    /// `model` の応答の連鎖。
+    pub fn response_chain(&self, model: &str) -> Option<&ConversationResponseChain> {
+        self.response_chains.get(model)
+    }
+
+    /// 先頭の `SystemMessage` を置き換える。なければ先頭に挿入し、要約の範囲もずらす。
+    /// 応答の連鎖は古い `SystemMessage` を含むので破棄する。
+    pub fn replace_system(&mut self, system: Message) {
+        match self.messages.first_mut() {
+            Some(first @ Message::System(_)) => *first = system,
+            _ => {
+                self.messages.insert(0, system);
+                if let Some(summary) = &mut self.summary {
+                    summary.covered += 1;
+                }
+            }
+        }
+        self.response_chains.clear();
+    }
+}
+
+/// サーバー側に保存された応答の連鎖。
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ConversationResponseChain {
+    /// 最後の応答の ID 。
+    pub response_id: String,
+
+    /// `messages` の先頭からこの位置までを送信済み。直後の `Message` がこの応答の出力になる。
+    pub covered: usize,
+}
+
+/// 古いメッセージを置き換える要約。
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ConversationSummary {
+    /// `messages` の先頭からこの位置までを要約で置き換える。先頭の `SystemMessage` は常に残る。
+    pub covered: usize,
+
+    /// 要約本文。
+    pub text: String,
+}
+
+impl ConversationSummary {
+    /// LLM に送信する際の `Message` 表現。
+    pub fn to_message(&self) -> Message {
+        Message::new_system(format!("これまでの会話の要約:\n{}", self.text))
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -56,22 +153,48 @@ pub struct IncompleteConversation {
     pushed_messages: Vec<Message>,
     attachments: Vec<ConversationAttachment>,
     model_override: Option<ConversationModel>,
+    persona_override: Option<ConversationPersona>,
+    answered_model: Option<String>,
+    summary_update: Option<ConversationSummary>,
+    summary_message: Option<Message>,
+    response_description: Option<String>,
+    actions: AssistantActions,
+    response_chain_updates: HashMap<String, ConversationResponseChain>,
+    reasoning_summaries: Vec<String>,
 }
 
 impl IncompleteConversation {
     pub fn start(conversation: Conversation) -> IncompleteConversation {
+        let summary_message = conversation.summary.as_ref().map(ConversationSummary::to_message);
         IncompleteConversation {
             base: conversation,
             pushed_messages: vec![],
             attachments: vec![],
             model_override: None,
+            persona_override: None,
+            answered_model: None,
+            summary_update: None,
+            summary_message,
+            response_description: None,
+            actions: AssistantActions::default(),
+            response_chain_updates: HashMap::new(),
+            reasoning_summaries: vec![],
         }
     }
 
+    pub fn id(&self) -> ConversationId {
+        self.base.id
+    }
+
+    /// LLM に送信する `Message` 列。
+    /// 要約がある場合、先頭の `SystemMessage` の直後に要約を挟み、要約済みの範囲は送信しない。
     pub fn llm_sending_messages(&self) -> impl Iterator<Item = &Message> {
-        self.base
-            .messages
+        let system_count = self.leading_system_count();
+        let covered = self.summary().map_or(0, |s| s.covered).max(system_count);
+        self.base.messages[..system_count]
             .iter()
+            .chain(self.summary_message.iter())
+            .chain(self.base.messages[covered..].iter())
             .chain(self.pushed_messages.iter())
             .filter(|m| match m {
                 Message::User(um) => !um.skip_llm,
@@ -84,6 +207,91 @@ impl IncompleteConversation {
         self.model_override.as_ref().unwrap_or(&self.base.model)
     }
 
+    pub fn current_persona(&self) -> &ConversationPersona {
+        self.persona_override.as_ref().unwrap_or(&self.base.persona)
+    }
+
+    /// structured output で応答本文のフィールドに付ける説明。
+    pub fn response_description(&self) -> Option<&str> {
+        self.response_description.as_deref()
+    }
+
+    pub fn set_response_description(&mut self, description: impl Into<String>) {
+        self.response_description = Some(description.into());
+    }
+
+    /// `model` の応答の連鎖に続けて送信すべき `Message` 列。
+    /// 連鎖の後に他のモデルが応答したり要約が更新されたりして途切れている場合は `None` を返す。
+    pub fn chained_messages(&self, model: &str) -> Option<(&str, Vec<&Message>)> {
+        let chain = self
+            .response_chain_updates
+            .get(model)
+            .or_else(|| self.base.response_chains.get(model))?;
+        if self.summary().is_some_and(|s| s.covered > chain.covered) {
+            return None;
+        }
+
+        let mut messages = self
+            .base
+            .messages
+            .iter()
+            .chain(self.pushed_messages.iter())
+            .skip(chain.covered);
+        let Some(Message::Assistant(_) | Message::FunctionCalls(_)) = messages.next() else {
+            return None;
+        };
+        let mut chained = vec![];
+        for message in messages {
+            match message {
+                Message::User(um) if um.skip_llm => (),
+                Message::Assistant(am) if am.skip_llm => (),
+                Message::Assistant(_) | Message::FunctionCalls(_) => return None,
+                otherwise => chained.push(otherwise),
+            }
+        }
+        (!chained.is_empty()).then_some((chain.response_id.as_str(), chained))
+    }
+
+    /// `model` の応答の ID を記録する。ここまでの `Message` をすべて送信したものとして扱う。
+    pub fn set_response_chain(&mut self, model: impl Into<String>, response_id: impl Into<String>) {
+        let chain = ConversationResponseChain {
+            response_id: response_id.into(),
+            covered: self.base.messages.len() + self.pushed_messages.len(),
+        };
+        self.response_chain_updates.insert(model.into(), chain);
+    }
+
+    /// 元の `Conversation` の `Message` 列。
+    pub fn base_messages(&self) -> &[Message] {
+        &self.base.messages
+    }
+
+    /// 今回追加された `Message` 列。
+    pub fn pushed_messages(&self) -> &[Message] {
+        &self.pushed_messages
+    }
+
+    /// 元の `Conversation` の先頭に連続する `SystemMessage` の数。
+    pub fn leading_system_count(&self) -> usize {
+        self.base
+            .messages
+            .iter()
+            .take_while(|m| matches!(m, Message::System(_)))
+            .count()
+    }
+
+    /// 現在有効な要約。
+    pub fn summary(&self) -> Option<&ConversationSummary> {
+        self.summary_update.as_ref().or(self.base.summary.as_ref())
+    }
+
+    /// 要約を更新する。`covered` は元の `Conversation` の範囲内でなければならない。
+    pub fn set_summary(&mut self, summary: ConversationSummary) {
+        debug_assert!(summary.covered <= self.base.messages.len());
+        self.summary_message = Some(summary.to_message());
+        self.summary_update = Some(summary);
+    }
+
     /// 元の `Conversation` のうち最後にある `UserMessage` を取得する。
     pub fn last_user(&self) -> Option<&UserMessage> {
         let Some(Message::User(last_user)) = &self.pushed_messages.last() else {
@@ -112,6 +320,25 @@ impl IncompleteConversation {
         self.model_override.replace(model)
     }
 
+    pub fn set_persona_override(&mut self, persona: ConversationPersona) -> Option<ConversationPersona> {
+        self.persona_override.replace(persona)
+    }
+
+    /// 応答に付随する動作を記録する。
+    pub fn set_actions(&mut self, actions: AssistantActions) {
+        self.actions = actions;
+    }
+
+    /// 推論の要約を記録する。 `finish` で最終的な `AssistantMessage` にまとめられる。
+    pub fn push_reasoning_summary(&mut self, summary: impl Into<String>) {
+        self.reasoning_summaries.push(summary.into());
+    }
+
+    /// 実際に応答したモデル名を記録する。
+    pub fn set_answered_model(&mut self, model: impl Into<String>) {
+        self.answered_model = Some(model.into());
+    }
+
     /// 最後の `AssistantMessage` に指定された `AssistantMessage` の内容を追加する。
     /// 最後が `AssistantMessage` でなければ受け取ったものをそのまま追加する。
     pub fn push_assistant(&mut self, appending_message: AssistantMessage) {
@@ -130,7 +357,7 @@ impl IncompleteConversation {
     /// 最後の `AssistantMessage` に指定された `AssistantMessage` の内容を追加してそれを合計の `AssistantMessage` とする。
     /// 最後が `AssistantMessage` でなければ受け取ったものをそのまま適用する。
     pub fn finish(mut self, finished_response: AssistantMessage) -> ConversationUpdate {
-        let assistant_response = match self.pushed_messages.pop() {
+        let mut assistant_response = match self.pushed_messages.pop() {
             // Cut ありで完了
             Some(Message::Assistant(mut last_assistant)) => {
                 last_assistant.text.push_str(&finished_response.text);
@@ -151,6 +378,9 @@ impl IncompleteConversation {
             // 要素なし(普通 `User` が入ると思うけど)
             None => finished_response,
         };
+        if !self.reasoning_summaries.is_empty() {
+            assistant_response.reasoning_summary = Some(self.reasoning_summaries.join("\n\n"));
+        }
 
         ConversationUpdate {
             base_conversation_id: self.base.id,
@@ -158,6 +388,11 @@ impl IncompleteConversation {
             assistant_response,
             attachments: self.attachments,
             model_override: self.model_override,
+            persona_override: self.persona_override,
+            answered_model: self.answered_model,
+            summary_update: self.summary_update,
+            actions: self.actions,
+            response_chain_updates: self.response_chain_updates,
         }
     }
 }
@@ -169,6 +404,11 @@ pub struct ConversationUpdate {
     assistant_response: AssistantMessage,
     attachments: Vec<ConversationAttachment>,
     model_override: Option<ConversationModel>,
+    persona_override: Option<ConversationPersona>,
+    answered_model: Option<String>,
+    summary_update: Option<ConversationSummary>,
+    actions: AssistantActions,
+    response_chain_updates: HashMap<String, ConversationResponseChain>,
 }
 
 impl ConversationUpdate {
@@ -183,6 +423,11 @@ impl ConversationUpdate {
             assistant_response: assistant,
             attachments: vec![],
             model_override: None,
+            persona_override: None,
+            answered_model: None,
+            summary_update: None,
+            actions: AssistantActions::default(),
+            response_chain_updates: HashMap::new(),
         }
     }
 
@@ -202,6 +447,20 @@ impl ConversationUpdate {
         self.model_override.as_ref()
     }
 
+    pub fn persona_override(&self) -> Option<&ConversationPersona> {
+        self.persona_override.as_ref()
+    }
+
+    /// 最終的に応答したモデル名。
+    pub fn answered_model(&self) -> Option<&str> {
+        self.answered_model.as_deref()
+    }
+
+    /// 応答に付随する動作。会話履歴には保存されない。
+    pub fn actions(&self) -> &AssistantActions {
+        &self.actions
+    }
+
     pub fn complete_conversation_with(self, base_conversation: Conversation) -> Conversation {
         debug_assert!(base_conversation.id == self.base_conversation_id);
         let mut completed_conversation = base_conversation;
@@ -215,6 +474,21 @@ impl ConversationUpdate {
             completed_conversation.model = overridden;
         }
 
+        // ペルソナ変更を反映
+        if let Some(overridden) = self.persona_override {
+            completed_conversation.persona = overridden;
+        }
+
+        // 要約を更新
+        if let Some(summary) = self.summary_update {
+            completed_conversation.summary = Some(summary);
+        }
+
+        // 応答の連鎖を更新
+        completed_conversation
+            .response_chains
+            .extend(self.response_chain_updates);
+
         completed_conversation
     }
 }
@@ -228,3 +502,32 @@ pub enum ConversationAttachment {
         description: Option<String>,
     },
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::model::message::UserMessageContent;
+
+    fn user(text: &str) -> Message {
+        Message::new_user([UserMessageContent::Text(text.to_string())], None, None, false)
+    }
+
+    #[test]
+    fn inserting_system_shifts_summary() {
+        let mut conversation = Conversation::new_now(None);
+        conversation.messages.extend([user("1"), user("2"), user("3")]);
+        conversation.summary = Some(ConversationSummary {
+            covered: 2,
+            text: "要約".to_string(),
+        });
+
+        conversation.replace_system(Message::new_system("system"));
+        assert_eq!(conversation.messages().len(), 4);
+        assert_eq!(conversation.summary().map(|s| s.covered), Some(3));
+
+        // 置き換えるだけなら範囲は変わらない
+        conversation.replace_system(Message::new_system("updated"));
+        assert_eq!(conversation.summary().map(|s| s.covered), Some(3));
+    }
+}
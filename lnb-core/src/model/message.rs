@@ -1,3 +1,5 @@
+use crate::model::schema::{DescribeSchema, DescribedSchema};
+
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use url::Url;
@@ -55,6 +57,7 @@ impl Message {
             is_sensitive,
             language,
             skip_llm,
+            reasoning_summary: None,
         })
     }
 }
@@ -124,6 +127,10 @@ pub struct AssistantMessage {
     pub is_sensitive: bool,
     pub language: Option<String>,
     pub skip_llm: bool,
+
+    /// 応答に至るまでの推論の要約。デバッグ用に保存するだけで、ユーザーにも LLM にも送らない。
+    #[serde(default)]
+    pub reasoning_summary: Option<String>,
 }
 
 impl From<AssistantMessage> for Message {
@@ -131,3 +138,39 @@ impl From<AssistantMessage> for Message {
         Message::Assistant(value)
     }
 }
+
+/// structured output で LLM が要求する、返信本文以外の動作。
+/// 各クライアントは対応できないものを無視する。
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+pub struct AssistantActions {
+    /// 元の投稿に付けるリアクションの絵文字。
+    #[serde(default)]
+    pub reaction: Option<String>,
+
+    /// 返信に付ける注意書き。
+    #[serde(default)]
+    pub content_warning: Option<String>,
+
+    /// 返信の公開範囲の希望。
+    #[serde(default)]
+    pub visibility: Option<AssistantVisibility>,
+
+    /// 返信を送らない。
+    #[serde(default)]
+    pub no_reply: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AssistantVisibility {
+    Public,
+    Unlisted,
+    Private,
+    Direct,
+}
+
+impl DescribeSchema for AssistantVisibility {
+    fn describe_schema(name: impl Into<String>, description: impl Into<String>) -> DescribedSchema {
+        DescribedSchema::string_enum(name, description, ["public", "unlisted", "private", "direct"])
+    }
+}
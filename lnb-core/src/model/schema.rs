@@ -1,6 +1,18 @@
+use std::{
+    fmt::{Display, Formatter, Result as FmtResult},
+    net::{Ipv4Addr, Ipv6Addr},
+};
+
+use regex::Regex;
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use thiserror::Error as ThisError;
+use time::{Date, OffsetDateTime, format_description::well_known::Rfc3339, macros::format_description};
+use uuid::Uuid;
+
+pub use lnb_schema_derive::DescribedSchema;
 
-#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "snake_case", tag = "kind", content = "value")]
 pub enum DescribedSchemaType {
     Integer,
@@ -10,51 +22,164 @@ pub enum DescribedSchemaType {
     Enum(Vec<String>),
     Array(Box<DescribedSchema>),
     Object(Vec<DescribedSchema>),
+
+    /// いずれかのスキーマに適合する値。
+    Union(Vec<DescribedSchema>),
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct DescribedSchema {
     pub name: String,
     pub description: String,
     pub field_type: DescribedSchemaType,
     pub optional: bool,
+
+    /// object のフィールドとして省略できるかどうか。 `optional` と異なり null は許可しない。
+    #[serde(default)]
+    pub omittable: bool,
+
+    /// 省略された場合の値。
+    #[serde(default)]
+    pub default: Option<Value>,
+
+    #[serde(default)]
+    pub constraints: DescribedSchemaConstraints,
+}
+
+/// 値の範囲などの制約。型に合わないものは無視される。
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct DescribedSchemaConstraints {
+    pub minimum: Option<f64>,
+    pub maximum: Option<f64>,
+    pub format: Option<StringFormat>,
+    pub pattern: Option<SchemaPattern>,
+    pub min_items: Option<usize>,
+    pub max_items: Option<usize>,
+}
+
+/// `pattern` 制約の正規表現。構築時に一度だけコンパイルする。
+/// コンパイルできないものも保持し、 `DescribedSchema::check_patterns` で検出する。
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(from = "String", into = "String")]
+pub struct SchemaPattern {
+    source: String,
+    regex: Result<Regex, regex::Error>,
+}
+
+impl SchemaPattern {
+    pub fn new(source: impl Into<String>) -> SchemaPattern {
+        let source = source.into();
+        let regex = Regex::new(&source);
+        SchemaPattern { source, regex }
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.source
+    }
+
+    pub fn regex(&self) -> Result<&Regex, &regex::Error> {
+        self.regex.as_ref()
+    }
+}
+
+impl PartialEq for SchemaPattern {
+    fn eq(&self, other: &SchemaPattern) -> bool {
+        self.source == other.source
+    }
+}
+
+impl From<String> for SchemaPattern {
+    fn from(value: String) -> SchemaPattern {
+        SchemaPattern::new(value)
+    }
+}
+
+impl From<SchemaPattern> for String {
+    fn from(value: SchemaPattern) -> String {
+        value.source
+    }
+}
+
+/// JSON Schema の `format` 。
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum StringFormat {
+    DateTime,
+    Date,
+    Time,
+    Duration,
+    Email,
+    Hostname,
+    Ipv4,
+    Ipv6,
+    Uuid,
+    Uri,
+}
+
+impl StringFormat {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            StringFormat::DateTime => "date-time",
+            StringFormat::Date => "date",
+            StringFormat::Time => "time",
+            StringFormat::Duration => "duration",
+            StringFormat::Email => "email",
+            StringFormat::Hostname => "hostname",
+            StringFormat::Ipv4 => "ipv4",
+            StringFormat::Ipv6 => "ipv6",
+            StringFormat::Uuid => "uuid",
+            StringFormat::Uri => "uri",
+        }
+    }
+
+    /// `value` がこの形式に従っているか。
+    /// 構文の検査が必要なものだけを判定し、それ以外は常に true を返す。
+    fn matches(self, value: &str) -> bool {
+        match self {
+            StringFormat::DateTime => OffsetDateTime::parse(value, &Rfc3339).is_ok(),
+            StringFormat::Date => Date::parse(value, format_description!("[year]-[month]-[day]")).is_ok(),
+            StringFormat::Email => value
+                .split_once('@')
+                .is_some_and(|(l, d)| !l.is_empty() && !d.is_empty()),
+            StringFormat::Ipv4 => value.parse::<Ipv4Addr>().is_ok(),
+            StringFormat::Ipv6 => value.parse::<Ipv6Addr>().is_ok(),
+            StringFormat::Uuid => Uuid::parse_str(value).is_ok(),
+            StringFormat::Time | StringFormat::Duration | StringFormat::Hostname | StringFormat::Uri => true,
+        }
+    }
 }
 
 impl DescribedSchema {
-    pub fn integer(name: impl Into<String>, description: impl Into<String>) -> DescribedSchema {
+    fn new(
+        name: impl Into<String>,
+        description: impl Into<String>,
+        field_type: DescribedSchemaType,
+    ) -> DescribedSchema {
         DescribedSchema {
             name: name.into(),
             description: description.into(),
-            field_type: DescribedSchemaType::Integer,
+            field_type,
             optional: false,
+            omittable: false,
+            default: None,
+            constraints: DescribedSchemaConstraints::default(),
         }
     }
 
+    pub fn integer(name: impl Into<String>, description: impl Into<String>) -> DescribedSchema {
+        DescribedSchema::new(name, description, DescribedSchemaType::Integer)
+    }
+
     pub fn float(name: impl Into<String>, description: impl Into<String>) -> DescribedSchema {
-        DescribedSchema {
-            name: name.into(),
-            description: description.into(),
-            field_type: DescribedSchemaType::Float,
-            optional: false,
-        }
+        DescribedSchema::new(name, description, DescribedSchemaType::Float)
     }
 
     pub fn boolean(name: impl Into<String>, description: impl Into<String>) -> DescribedSchema {
-        DescribedSchema {
-            name: name.into(),
-            description: description.into(),
-            field_type: DescribedSchemaType::Boolean,
-            optional: false,
-        }
+        DescribedSchema::new(name, description, DescribedSchemaType::Boolean)
     }
 
     pub fn string(name: impl Into<String>, description: impl Into<String>) -> DescribedSchema {
-        DescribedSchema {
-            name: name.into(),
-            description: description.into(),
-            field_type: DescribedSchemaType::String,
-            optional: false,
-        }
+        DescribedSchema::new(name, description, DescribedSchemaType::String)
     }
 
     pub fn string_enum(
@@ -62,12 +187,8 @@ impl DescribedSchema {
         description: impl Into<String>,
         variants: impl IntoIterator<Item = impl Into<String>>,
     ) -> DescribedSchema {
-        DescribedSchema {
-            name: name.into(),
-            description: description.into(),
-            field_type: DescribedSchemaType::Enum(variants.into_iter().map(|s| s.into()).collect()),
-            optional: false,
-        }
+        let variants = variants.into_iter().map(|s| s.into()).collect();
+        DescribedSchema::new(name, description, DescribedSchemaType::Enum(variants))
     }
 
     pub fn array(
@@ -75,12 +196,7 @@ impl DescribedSchema {
         description: impl Into<String>,
         item_schema: DescribedSchema,
     ) -> DescribedSchema {
-        DescribedSchema {
-            name: name.into(),
-            description: description.into(),
-            field_type: DescribedSchemaType::Array(Box::new(item_schema)),
-            optional: false,
-        }
+        DescribedSchema::new(name, description, DescribedSchemaType::Array(Box::new(item_schema)))
     }
 
     pub fn object(
@@ -88,16 +204,470 @@ impl DescribedSchema {
         description: impl Into<String>,
         fields: impl IntoIterator<Item = DescribedSchema>,
     ) -> DescribedSchema {
-        DescribedSchema {
-            name: name.into(),
-            description: description.into(),
-            field_type: DescribedSchemaType::Object(fields.into_iter().collect()),
-            optional: false,
-        }
+        DescribedSchema::new(
+            name,
+            description,
+            DescribedSchemaType::Object(fields.into_iter().collect()),
+        )
+    }
+
+    pub fn union(
+        name: impl Into<String>,
+        description: impl Into<String>,
+        variants: impl IntoIterator<Item = DescribedSchema>,
+    ) -> DescribedSchema {
+        DescribedSchema::new(
+            name,
+            description,
+            DescribedSchemaType::Union(variants.into_iter().collect()),
+        )
     }
 
     pub fn as_nullable(mut self) -> DescribedSchema {
         self.optional = true;
         self
     }
+
+    pub fn as_omittable(mut self) -> DescribedSchema {
+        self.omittable = true;
+        self
+    }
+
+    pub fn with_default(mut self, default: impl Into<Value>) -> DescribedSchema {
+        self.default = Some(default.into());
+        self
+    }
+
+    pub fn with_minimum(mut self, minimum: f64) -> DescribedSchema {
+        self.constraints.minimum = Some(minimum);
+        self
+    }
+
+    pub fn with_maximum(mut self, maximum: f64) -> DescribedSchema {
+        self.constraints.maximum = Some(maximum);
+        self
+    }
+
+    pub fn with_format(mut self, format: StringFormat) -> DescribedSchema {
+        self.constraints.format = Some(format);
+        self
+    }
+
+    pub fn with_pattern(mut self, pattern: impl Into<String>) -> DescribedSchema {
+        self.constraints.pattern = Some(SchemaPattern::new(pattern));
+        self
+    }
+
+    pub fn with_min_items(mut self, min_items: usize) -> DescribedSchema {
+        self.constraints.min_items = Some(min_items);
+        self
+    }
+
+    pub fn with_max_items(mut self, max_items: usize) -> DescribedSchema {
+        self.constraints.max_items = Some(max_items);
+        self
+    }
+
+    /// object の省略されたフィールドのうち、 `default` があるものを埋める。
+    pub fn fill_defaults(&self, value: &mut Value) {
+        match (&self.field_type, value) {
+            (DescribedSchemaType::Object(fields), Value::Object(object)) => {
+                for field in fields {
+                    match object.get_mut(&field.name) {
+                        Some(field_value) => field.fill_defaults(field_value),
+                        None => {
+                            if let Some(default) = &field.default {
+                                object.insert(field.name.clone(), default.clone());
+                            }
+                        }
+                    }
+                }
+            }
+            (DescribedSchemaType::Array(item_schema), Value::Array(items)) => {
+                items.iter_mut().for_each(|item| item_schema.fill_defaults(item));
+            }
+            _ => (),
+        }
+    }
+
+    /// `pattern` 制約の正規表現がすべてコンパイルできるか検査する。
+    pub fn check_patterns(&self) -> Result<(), SchemaPatternError> {
+        self.check_patterns_at(&SchemaPath::Root(&self.name))
+    }
+
+    fn check_patterns_at(&self, path: &SchemaPath) -> Result<(), SchemaPatternError> {
+        if let Some(pattern) = &self.constraints.pattern
+            && let Err(source) = pattern.regex()
+        {
+            return Err(SchemaPatternError {
+                path: path.to_string(),
+                pattern: pattern.as_str().to_string(),
+                source: source.clone(),
+            });
+        }
+        match &self.field_type {
+            DescribedSchemaType::Array(item_schema) => item_schema.check_patterns_at(&SchemaPath::Index(path, 0)),
+            DescribedSchemaType::Object(fields) => fields
+                .iter()
+                .try_for_each(|f| f.check_patterns_at(&SchemaPath::Field(path, &f.name))),
+            DescribedSchemaType::Union(variants) => variants.iter().try_for_each(|v| v.check_patterns_at(path)),
+            _ => Ok(()),
+        }
+    }
+
+    /// `value` がこのスキーマに適合するか検証する。
+    /// object のフィールドは nullable か omittable でなければ必須で、定義されていないフィールドは許可しない。
+    pub fn validate(&self, value: &Value) -> Result<(), SchemaValidationError> {
+        self.validate_at(&SchemaPath::Root(&self.name), value)
+    }
+
+    fn validate_at(&self, path: &SchemaPath, value: &Value) -> Result<(), SchemaValidationError> {
+        if value.is_null() {
+            return match self.optional {
+                true => Ok(()),
+                false => Err(SchemaValidationError::new(path, "must not be null")),
+            };
+        }
+
+        match &self.field_type {
+            DescribedSchemaType::Integer if value.is_i64() || value.is_u64() => self.validate_number(path, value),
+            DescribedSchemaType::Integer => Err(SchemaValidationError::new(path, "must be an integer")),
+            DescribedSchemaType::Float if value.is_number() => self.validate_number(path, value),
+            DescribedSchemaType::Float => Err(SchemaValidationError::new(path, "must be a number")),
+            DescribedSchemaType::Boolean if value.is_boolean() => Ok(()),
+            DescribedSchemaType::Boolean => Err(SchemaValidationError::new(path, "must be a boolean")),
+            DescribedSchemaType::String => match value.as_str() {
+                Some(s) => self.validate_string(path, s),
+                None => Err(SchemaValidationError::new(path, "must be a string")),
+            },
+            DescribedSchemaType::Enum(variants) => match value.as_str() {
+                Some(v) if variants.iter().any(|variant| variant == v) => Ok(()),
+                _ => {
+                    let message = format!("must be one of {}", variants.join(", "));
+                    Err(SchemaValidationError::new(path, message))
+                }
+            },
+            DescribedSchemaType::Array(item_schema) => {
+                let Some(items) = value.as_array() else {
+                    return Err(SchemaValidationError::new(path, "must be an array"));
+                };
+                if let Some(min_items) = self.constraints.min_items
+                    && items.len() < min_items
+                {
+                    let message = format!("must have at least {min_items} items");
+                    return Err(SchemaValidationError::new(path, message));
+                }
+                if let Some(max_items) = self.constraints.max_items
+                    && items.len() > max_items
+                {
+                    let message = format!("must have at most {max_items} items");
+                    return Err(SchemaValidationError::new(path, message));
+                }
+                for (index, item) in items.iter().enumerate() {
+                    item_schema.validate_at(&SchemaPath::Index(path, index), item)?;
+                }
+                Ok(())
+            }
+            DescribedSchemaType::Object(fields) => {
+                let Some(object) = value.as_object() else {
+                    return Err(SchemaValidationError::new(path, "must be an object"));
+                };
+                for field in fields {
+                    let field_path = SchemaPath::Field(path, &field.name);
+                    match object.get(&field.name) {
+                        Some(field_value) => field.validate_at(&field_path, field_value)?,
+                        None if field.optional || field.omittable || field.default.is_some() => (),
+                        None => return Err(SchemaValidationError::new(&field_path, "required field is missing")),
+                    }
+                }
+                if let Some(unknown) = object.keys().find(|k| fields.iter().all(|f| &f.name != *k)) {
+                    let field_path = SchemaPath::Field(path, unknown);
+                    return Err(SchemaValidationError::new(&field_path, "unknown field"));
+                }
+                Ok(())
+            }
+            DescribedSchemaType::Union(variants) => match variants.iter().any(|v| v.validate_at(path, value).is_ok()) {
+                true => Ok(()),
+                false => Err(SchemaValidationError::new(path, "must match one of the variants")),
+            },
+        }
+    }
+
+    fn validate_number(&self, path: &SchemaPath, value: &Value) -> Result<(), SchemaValidationError> {
+        let number = value.as_f64().unwrap_or_default();
+        if let Some(minimum) = self.constraints.minimum
+            && number < minimum
+        {
+            return Err(SchemaValidationError::new(path, format!("must be at least {minimum}")));
+        }
+        if let Some(maximum) = self.constraints.maximum
+            && number > maximum
+        {
+            return Err(SchemaValidationError::new(path, format!("must be at most {maximum}")));
+        }
+        Ok(())
+    }
+
+    fn validate_string(&self, path: &SchemaPath, value: &str) -> Result<(), SchemaValidationError> {
+        if let Some(format) = self.constraints.format
+            && !format.matches(value)
+        {
+            let message = format!("must be in {} format", format.as_str());
+            return Err(SchemaValidationError::new(path, message));
+        }
+        if let Some(pattern) = &self.constraints.pattern {
+            let regex = pattern
+                .regex()
+                .map_err(|_| SchemaValidationError::new(path, "invalid pattern"))?;
+            if !regex.is_match(value) {
+                let message = format!("must match pattern {}", pattern.as_str());
+                return Err(SchemaValidationError::new(path, message));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// `DescribedSchema` として記述できる型。
+/// 構造体とユニット列挙型は `#[derive(DescribedSchema)]` で実装できる。
+pub trait DescribeSchema {
+    fn describe_schema(name: impl Into<String>, description: impl Into<String>) -> DescribedSchema;
+}
+
+macro_rules! impl_describe_schema {
+    ($constructor:ident: $($t:ty),*) => {
+        $(
+            impl DescribeSchema for $t {
+                fn describe_schema(name: impl Into<String>, description: impl Into<String>) -> DescribedSchema {
+                    DescribedSchema::$constructor(name, description)
+                }
+            }
+        )*
+    };
+}
+
+impl_describe_schema!(integer: i8, i16, i32, i64, isize, u8, u16, u32, u64, usize);
+impl_describe_schema!(float: f32, f64);
+impl_describe_schema!(boolean: bool);
+impl_describe_schema!(string: String);
+
+impl<T: DescribeSchema> DescribeSchema for Option<T> {
+    fn describe_schema(name: impl Into<String>, description: impl Into<String>) -> DescribedSchema {
+        T::describe_schema(name, description).as_nullable()
+    }
+}
+
+impl<T: DescribeSchema> DescribeSchema for Vec<T> {
+    fn describe_schema(name: impl Into<String>, description: impl Into<String>) -> DescribedSchema {
+        let description = description.into();
+        let item_schema = T::describe_schema("item", description.clone());
+        DescribedSchema::array(name, description, item_schema)
+    }
+}
+
+/// 検証中の位置。
+enum SchemaPath<'a> {
+    Root(&'a str),
+    Field(&'a SchemaPath<'a>, &'a str),
+    Index(&'a SchemaPath<'a>, usize),
+}
+
+impl Display for SchemaPath<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        match self {
+            SchemaPath::Root(name) => write!(f, "{name}"),
+            SchemaPath::Field(parent, name) => write!(f, "{parent}.{name}"),
+            SchemaPath::Index(parent, index) => write!(f, "{parent}[{index}]"),
+        }
+    }
+}
+
+/// `DescribedSchema` に適合しなかった値。
+#[derive(Debug, Clone, PartialEq, Eq, ThisError)]
+#[error("{path}: {message}")]
+pub struct SchemaValidationError {
+    pub path: String,
+    pub message: String,
+}
+
+impl SchemaValidationError {
+    fn new(path: &SchemaPath, message: impl Into<String>) -> SchemaValidationError {
+        SchemaValidationError {
+            path: path.to_string(),
+            message: message.into(),
+        }
+    }
+}
+
+/// コンパイルできない `pattern` 制約。
+#[derive(Debug, Clone, ThisError)]
+#[error("{path}: invalid pattern {pattern}: {source}")]
+pub struct SchemaPatternError {
+    pub path: String,
+    pub pattern: String,
+
+    #[source]
+    pub source: regex::Error,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use serde_json::json;
+
+    fn parameters() -> DescribedSchema {
+        DescribedSchema::object(
+            "parameters",
+            "引数",
+            vec![
+                DescribedSchema::string_enum("mode", "モード", ["generate", "edit"]),
+                DescribedSchema::integer("count", "個数"),
+                DescribedSchema::string("note", "備考").as_nullable(),
+                DescribedSchema::array("urls", "URL", DescribedSchema::string("url", "URL")),
+            ],
+        )
+    }
+
+    fn validate(value: Value) -> Result<(), String> {
+        parameters().validate(&value).map_err(|e| e.to_string())
+    }
+
+    #[test]
+    fn accepts_valid_value() {
+        assert_eq!(validate(json!({ "mode": "edit", "count": 2, "urls": ["a"] })), Ok(()));
+        assert_eq!(
+            validate(json!({ "mode": "edit", "count": 2, "note": null, "urls": [] })),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn rejects_type_mismatch() {
+        assert_eq!(
+            validate(json!({ "mode": "edit", "count": 1.5, "urls": [] })),
+            Err("parameters.count: must be an integer".to_string())
+        );
+        assert_eq!(
+            validate(json!({ "mode": "edit", "count": 1, "urls": ["a", 1] })),
+            Err("parameters.urls[1]: must be a string".to_string())
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_enum_variant() {
+        assert_eq!(
+            validate(json!({ "mode": "delete", "count": 1, "urls": [] })),
+            Err("parameters.mode: must be one of generate, edit".to_string())
+        );
+    }
+
+    #[test]
+    fn rejects_missing_and_unknown_fields() {
+        assert_eq!(
+            validate(json!({ "mode": "edit", "urls": [] })),
+            Err("parameters.count: required field is missing".to_string())
+        );
+        assert_eq!(
+            validate(json!({ "mode": "edit", "count": null, "urls": [] })),
+            Err("parameters.count: must not be null".to_string())
+        );
+        assert_eq!(
+            validate(json!({ "mode": "edit", "count": 1, "urls": [], "extra": true })),
+            Err("parameters.extra: unknown field".to_string())
+        );
+    }
+
+    #[test]
+    fn checks_constraints() {
+        let schema = DescribedSchema::object(
+            "parameters",
+            "引数",
+            vec![
+                DescribedSchema::integer("count", "個数")
+                    .with_minimum(1.0)
+                    .with_maximum(4.0),
+                DescribedSchema::string("code", "通貨コード").with_pattern("^[A-Z]{3}$"),
+                DescribedSchema::string("at", "時刻").with_format(StringFormat::DateTime),
+                DescribedSchema::array("tags", "タグ", DescribedSchema::string("tag", "タグ")).with_min_items(1),
+            ],
+        );
+        let validate = |value: Value| schema.validate(&value).map_err(|e| e.to_string());
+
+        let valid = json!({ "count": 4, "code": "JPY", "at": "2025-04-01T12:00:00+09:00", "tags": ["a"] });
+        assert_eq!(validate(valid), Ok(()));
+        assert_eq!(
+            validate(json!({ "count": 5, "code": "JPY", "at": "2025-04-01T12:00:00+09:00", "tags": ["a"] })),
+            Err("parameters.count: must be at most 4".to_string())
+        );
+        assert_eq!(
+            validate(json!({ "count": 1, "code": "jpy", "at": "2025-04-01T12:00:00+09:00", "tags": ["a"] })),
+            Err("parameters.code: must match pattern ^[A-Z]{3}$".to_string())
+        );
+        assert_eq!(
+            validate(json!({ "count": 1, "code": "JPY", "at": "2025-04-01", "tags": ["a"] })),
+            Err("parameters.at: must be in date-time format".to_string())
+        );
+        assert_eq!(
+            validate(json!({ "count": 1, "code": "JPY", "at": "2025-04-01T12:00:00+09:00", "tags": [] })),
+            Err("parameters.tags: must have at least 1 items".to_string())
+        );
+    }
+
+    #[test]
+    fn detects_invalid_patterns() {
+        let schema = DescribedSchema::object(
+            "parameters",
+            "引数",
+            vec![
+                DescribedSchema::string("code", "通貨コード").with_pattern("^[A-Z]{3}$"),
+                DescribedSchema::array("tags", "タグ", DescribedSchema::string("tag", "タグ").with_pattern("(")),
+            ],
+        );
+        let error = schema.check_patterns().expect_err("should reject invalid pattern");
+        assert_eq!(
+            (error.path.as_str(), error.pattern.as_str()),
+            ("parameters.tags[0]", "(")
+        );
+        assert!(parameters().check_patterns().is_ok());
+    }
+
+    #[test]
+    fn accepts_any_union_variant() {
+        let schema = DescribedSchema::union(
+            "at",
+            "時刻",
+            vec![
+                DescribedSchema::string("datetime", "日時").with_format(StringFormat::DateTime),
+                DescribedSchema::string("date", "日付").with_format(StringFormat::Date),
+            ],
+        );
+        assert_eq!(schema.validate(&json!("2025-04-01T12:00:00+09:00")), Ok(()));
+        assert_eq!(schema.validate(&json!("2025-04-01")), Ok(()));
+        assert_eq!(
+            schema.validate(&json!("tomorrow")).map_err(|e| e.to_string()),
+            Err("at: must match one of the variants".to_string())
+        );
+    }
+
+    #[test]
+    fn omittable_field_may_be_missing_but_not_null() {
+        let schema = DescribedSchema::object(
+            "parameters",
+            "引数",
+            vec![
+                DescribedSchema::integer("count", "個数").as_omittable(),
+                DescribedSchema::integer("scale", "倍率").with_default(2),
+            ],
+        );
+        assert_eq!(schema.validate(&json!({})), Ok(()));
+        assert_eq!(
+            schema.validate(&json!({ "count": null })).map_err(|e| e.to_string()),
+            Err("parameters.count: must not be null".to_string())
+        );
+
+        let mut value = json!({ "count": 1 });
+        schema.fill_defaults(&mut value);
+        assert_eq!(value, json!({ "count": 1, "scale": 2 }));
+    }
 }
@@ -1,41 +1,59 @@
-use std::sync::Arc;
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
 use crate::text::{sanitize_discord_message, sanitize_markdown_for_discord};
 
-use lnb_common::{config::client::ConfigClientDiscord, user_roles::UserRolesGroup};
+use futures::{
+    StreamExt,
+    channel::mpsc::{UnboundedReceiver, unbounded},
+    future::join,
+};
+use lnb_common::{config::client::ConfigClientDiscord, user_roles::SharedUserRolesGroup};
 use lnb_core::{
     error::ClientError,
-    interface::{MessageContext as LnbContext, server::LnbServer},
+    interface::{
+        MessageContext as LnbContext,
+        server::{ConversationProgress, LnbServer},
+    },
     model::{
-        conversation::ConversationUpdate,
-        message::{AssistantMessage, UserMessage, UserMessageContent},
+        conversation::{ConversationPersona, ConversationUpdate},
+        message::{AssistantActions, AssistantMessage, UserMessage, UserMessageContent},
     },
 };
 use tokio::{spawn, sync::RwLock};
 use tracing::{info, warn};
 use twilight_cache_inmemory::{DefaultInMemoryCache, ResourceType};
-use twilight_gateway::{Event, EventTypeFlags, Intents, Shard, ShardId, StreamExt};
-use twilight_http::Client;
+use twilight_gateway::{Event, EventTypeFlags, Intents, Shard, ShardId, StreamExt as _};
+use twilight_http::{Client, request::channel::reaction::RequestReactionType};
 use twilight_model::{
     gateway::payload::incoming::{MessageCreate, Ready},
+    id::{
+        Id,
+        marker::{ChannelMarker, MessageMarker},
+    },
     user::CurrentUser,
 };
 
 const CONTEXT_KEY_PREFIX: &str = "discord";
+const PLACEHOLDER_TEXT: &str = "(考え中...)";
+const PROGRESS_EDIT_INTERVAL: Duration = Duration::from_millis(1500);
 
 #[derive(Debug)]
 pub struct DiscordLnbClientInner<S> {
     client: Client,
-    roles_group: UserRolesGroup,
+    roles_group: SharedUserRolesGroup,
     bot_user: RwLock<Option<CurrentUser>>,
     max_length: usize,
+    persona: ConversationPersona,
     assistant: S,
 }
 
 impl<S: LnbServer> DiscordLnbClientInner<S> {
     pub async fn new(
         config: &ConfigClientDiscord,
-        roles_group: UserRolesGroup,
+        roles_group: SharedUserRolesGroup,
         assistant: S,
     ) -> Result<DiscordLnbClientInner<S>, ClientError> {
         let client = Client::new(config.token.clone());
@@ -44,6 +62,10 @@ impl<S: LnbServer> DiscordLnbClientInner<S> {
             roles_group,
             bot_user: RwLock::new(None),
             max_length: config.max_length,
+            persona: config
+                .persona
+                .clone()
+                .map_or(ConversationPersona::Default, ConversationPersona::Named),
             assistant,
         };
         Ok(inner)
@@ -118,13 +140,13 @@ impl<S: LnbServer> DiscordLnbClientInner<S> {
                     Some(c) => c,
                     None => {
                         info!("conversation has been lost, creating new one");
-                        self.assistant.new_conversation().await?
+                        self.assistant.new_conversation(self.persona.clone()).await?
                     }
                 }
             }
             None => {
                 info!("creating new conversation");
-                self.assistant.new_conversation().await?
+                self.assistant.new_conversation(self.persona.clone()).await?
             }
         };
 
@@ -145,10 +167,25 @@ impl<S: LnbServer> DiscordLnbClientInner<S> {
             .into(),
         ];
         let context = self.create_context(&message).await?;
-        let conversation_update = self
-            .assistant
-            .process_conversation(context, conversation_id, new_messages.clone())
-            .await;
+
+        // 先に仮のリプライを送っておき、生成に合わせて編集する
+        let replied_message = {
+            let response = self
+                .client
+                .create_message(message.channel_id)
+                .reply(message.id)
+                .content(PLACEHOLDER_TEXT)
+                .await
+                .map_err(ClientError::by_communication)?;
+            response.model().await.map_err(ClientError::by_communication)?
+        };
+
+        let (progress_sender, progress_receiver) = unbounded();
+        let processing =
+            self.assistant
+                .process_conversation_stream(context, conversation_id, new_messages.clone(), progress_sender);
+        let editing = self.edit_progressively(replied_message.channel_id, replied_message.id, progress_receiver);
+        let (conversation_update, _) = join(processing, editing).await;
 
         let recovered_update = match conversation_update {
             Ok(update) => update,
@@ -175,23 +212,35 @@ impl<S: LnbServer> DiscordLnbClientInner<S> {
         );
         // TODO: attachments
 
-        // リプライ
-        let mut sanitized_text = sanitize_markdown_for_discord(&assistant_message.text);
-        if sanitized_text.chars().count() > self.max_length {
-            sanitized_text = sanitized_text.chars().take(self.max_length).collect();
-            sanitized_text.push_str("...(omitted)");
-        }
-
-        let replied_message = {
-            let response = self
+        // 公開範囲の指定は Discord では意味を持たないので無視する
+        let actions = recovered_update.actions();
+        if let Some(emoji) = &actions.reaction {
+            let reaction = RequestReactionType::Unicode { name: emoji };
+            if let Err(e) = self
                 .client
-                .create_message(message.channel_id)
-                .reply(message.id)
-                .content(&sanitized_text)
+                .create_reaction(message.channel_id, message.id, &reaction)
+                .await
+            {
+                warn!("failed to react with {emoji}: {e}");
+            }
+        }
+        // 返信がなければ履歴にも残せないので会話は保存しない
+        if actions.no_reply {
+            info!("夏稀: (no reply to {})", message.id);
+            self.client
+                .delete_message(replied_message.channel_id, replied_message.id)
                 .await
                 .map_err(ClientError::by_communication)?;
-            response.model().await.map_err(ClientError::by_communication)?
-        };
+            return Ok(());
+        }
+
+        // リプライを最終的な内容に更新
+        let reply_text = self.format_final_reply_text(&assistant_message.text, actions);
+        self.client
+            .update_message(replied_message.channel_id, replied_message.id)
+            .content(Some(&reply_text))
+            .await
+            .map_err(ClientError::by_communication)?;
 
         // Conversation/history の更新
         let new_history_id = format!("{CONTEXT_KEY_PREFIX}:{}", replied_message.id);
@@ -202,10 +251,58 @@ impl<S: LnbServer> DiscordLnbClientInner<S> {
         Ok(())
     }
 
+    /// 途中経過を受け取ってリプライを編集する。編集は一定間隔に間引く。
+    async fn edit_progressively(
+        &self,
+        channel_id: Id<ChannelMarker>,
+        message_id: Id<MessageMarker>,
+        mut progress_receiver: UnboundedReceiver<ConversationProgress>,
+    ) {
+        let mut last_edited = Instant::now();
+        while let Some(progress) = progress_receiver.next().await {
+            let text = match progress {
+                // 注意書きは応答が終わるまで分からないので、途中の本文は常にスポイラーで隠す
+                ConversationProgress::Text(text) if last_edited.elapsed() >= PROGRESS_EDIT_INTERVAL => {
+                    format!("{PLACEHOLDER_TEXT}\n||{}||", self.format_reply_text(&text))
+                }
+                ConversationProgress::Text(_) => continue,
+                ConversationProgress::ToolCalling(names) => format!("({} を呼び出し中...)", names.join(", ")),
+            };
+
+            let edit_result = self
+                .client
+                .update_message(channel_id, message_id)
+                .content(Some(&text))
+                .await;
+            if let Err(e) = edit_result {
+                warn!("failed to edit reply in progress: {e}");
+            }
+            last_edited = Instant::now();
+        }
+    }
+
+    fn format_reply_text(&self, text: &str) -> String {
+        let mut sanitized_text = sanitize_markdown_for_discord(text);
+        if sanitized_text.chars().count() > self.max_length {
+            sanitized_text = sanitized_text.chars().take(self.max_length).collect();
+            sanitized_text.push_str("...(omitted)");
+        }
+        sanitized_text
+    }
+
+    /// 注意書きが指定されていれば本文をスポイラーで隠す。
+    fn format_final_reply_text(&self, text: &str, actions: &AssistantActions) -> String {
+        let reply_text = self.format_reply_text(text);
+        match &actions.content_warning {
+            Some(warning) => format!("{}\n||{reply_text}||", sanitize_markdown_for_discord(warning)),
+            None => reply_text,
+        }
+    }
+
     async fn create_context(&self, message: &MessageCreate) -> Result<LnbContext, ClientError> {
         let identity = format!("{CONTEXT_KEY_PREFIX}:{}", message.author.id);
 
-        let context = LnbContext::new_user(identity, self.roles_group.get(&message.author.id.to_string()).clone());
+        let context = LnbContext::new_user(identity, self.roles_group.get(&message.author.id.to_string()));
         Ok(context)
     }
 }
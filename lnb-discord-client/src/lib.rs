@@ -6,7 +6,7 @@ use crate::inner::DiscordLnbClientInner;
 use std::sync::Arc;
 
 use futures::{future::BoxFuture, prelude::*};
-use lnb_common::{config::client::ConfigClientDiscord, user_roles::UserRolesGroup};
+use lnb_common::{config::client::ConfigClientDiscord, user_roles::SharedUserRolesGroup};
 use lnb_core::{
     error::ClientError,
     interface::{client::LnbClient, server::LnbServer},
@@ -18,7 +18,7 @@ pub struct DiscordLnbClient<S>(Arc<inner::DiscordLnbClientInner<S>>);
 impl<S: LnbServer> DiscordLnbClient<S> {
     pub async fn new(
         config: &ConfigClientDiscord,
-        roles_group: UserRolesGroup,
+        roles_group: SharedUserRolesGroup,
         assistant: S,
     ) -> Result<DiscordLnbClient<S>, ClientError> {
         let inner_discord = DiscordLnbClientInner::new(config, roles_group, assistant).await?;
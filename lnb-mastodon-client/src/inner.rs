@@ -10,15 +10,15 @@ use lnb_common::{
     config::client::ConfigClientMastodon,
     debug::{debug_option_enabled, debug_option_value},
     math_renderer::MathRendererClient,
-    user_roles::UserRolesGroup,
+    user_roles::SharedUserRolesGroup,
 };
 use lnb_core::{
     APP_USER_AGENT,
     error::ClientError,
     interface::{MessageContext, reminder::RemindableContext, server::LnbServer},
     model::{
-        conversation::{ConversationAttachment, ConversationId, ConversationUpdate},
-        message::{AssistantMessage, Message, UserMessage, UserMessageContent},
+        conversation::{ConversationAttachment, ConversationId, ConversationPersona, ConversationUpdate},
+        message::{AssistantActions, AssistantMessage, AssistantVisibility, Message, UserMessage, UserMessageContent},
     },
 };
 use mastodon_async::{
@@ -42,7 +42,7 @@ const RECONNECT_SLEEP: Duration = Duration::from_secs(120);
 #[derive(Debug)]
 pub struct MastodonLnbClientInner<S> {
     assistant: S,
-    roles_group: UserRolesGroup,
+    roles_group: SharedUserRolesGroup,
     mastodon: Mastodon,
     self_account: Account,
     sensitive_spoiler: String,
@@ -50,12 +50,13 @@ pub struct MastodonLnbClientInner<S> {
     remote_fetch_delay: Duration,
     websocket_endpoint: String,
     math_renderer: MathRendererClient,
+    persona: ConversationPersona,
 }
 
 impl<S: LnbServer> MastodonLnbClientInner<S> {
     pub async fn new(
         config: &ConfigClientMastodon,
-        roles_group: UserRolesGroup,
+        roles_group: SharedUserRolesGroup,
         assistant: S,
     ) -> Result<MastodonLnbClientInner<S>, ClientError> {
         // Mastodon クライアントと自己アカウント情報
@@ -93,6 +94,10 @@ impl<S: LnbServer> MastodonLnbClientInner<S> {
             remote_fetch_delay: Duration::from_secs(config.remote_fetch_delay_seconds as u64),
             websocket_endpoint,
             math_renderer,
+            persona: config
+                .persona
+                .clone()
+                .map_or(ConversationPersona::Default, ConversationPersona::Named),
         })
     }
 
@@ -226,8 +231,27 @@ impl<S: LnbServer> MastodonLnbClientInner<S> {
         };
         let assistant_message = recovered_update.assistant_response();
         let attachments = recovered_update.attachments();
+        let actions = recovered_update.actions();
+
+        // Mastodon には絵文字リアクションがないのでふぁぼで代用する
+        if actions.reaction.is_some()
+            && let Err(e) = self.mastodon.favourite(&status.id).await
+        {
+            warn!("failed to favourite {}: {e}", status.id);
+        }
+        // 返信がなければ履歴にも残せないので会話は保存しない
+        if actions.no_reply {
+            info!("夏稀: (no reply to {})", status.id);
+            return Ok(());
+        }
+
         let replied_status = self
-            .send_reply(ReplyType::Status(Box::new(status)), assistant_message, attachments)
+            .send_reply(
+                ReplyType::Status(Box::new(status)),
+                assistant_message,
+                attachments,
+                actions,
+            )
             .await?;
         info!(
             "夏稀[{}]: {:?} ({} attachment(s))",
@@ -265,7 +289,7 @@ impl<S: LnbServer> MastodonLnbClientInner<S> {
 
                 // 未知の会話
                 debug!("unknown in_reply_to_id, creating new one: {in_reply_to_id}");
-                let id = self.assistant.new_conversation().await?;
+                let id = self.assistant.new_conversation(self.persona.clone()).await?;
                 let ancestors = self.get_ancestor(&mentioned_status.id).await;
                 let current = self.transform_status(mentioned_status);
                 Ok((id, ancestors.into_iter().chain(once(current)).collect()))
@@ -290,14 +314,14 @@ impl<S: LnbServer> MastodonLnbClientInner<S> {
 
                     sleep(self.remote_fetch_delay).await;
 
-                    let id = self.assistant.new_conversation().await?;
+                    let id = self.assistant.new_conversation(self.persona.clone()).await?;
                     let ancestors = self.get_ancestor(&mentioned_status.id).await;
                     let current = self.transform_status(mentioned_status);
                     Ok((id, ancestors.into_iter().chain(once(current)).collect()))
                 } else {
                     // もはや新規会話とみなすしかない
                     debug!("creating new conversation");
-                    let id = self.assistant.new_conversation().await?;
+                    let id = self.assistant.new_conversation(self.persona.clone()).await?;
                     let user_message = self.transform_status(mentioned_status);
                     Ok((id, vec![user_message]))
                 }
@@ -384,10 +408,11 @@ impl<S: LnbServer> MastodonLnbClientInner<S> {
         reply_type: ReplyType,
         assistant_message: &AssistantMessage,
         attachments: &[ConversationAttachment],
+        actions: &AssistantActions,
     ) -> Result<Status, ClientError> {
         // リプライ構築
-        // 公開範囲は最大 unlisted でリプライ元に合わせる
-        // CW はリプライ元があったらそのまま、ないときは要そぎぎなら付与
+        // 公開範囲は最大 unlisted でリプライ元に合わせ、指定があればそれより狭める
+        // CW はリプライ元があったらそのまま、ないときは指定されたものか要そぎぎなら付与
         let (mut filtered_text, math_formulae) = process_markdown_for_mastodon(&assistant_message.text);
         if filtered_text.chars().count() > self.max_length {
             filtered_text = filtered_text.chars().take(self.max_length).collect();
@@ -396,6 +421,7 @@ impl<S: LnbServer> MastodonLnbClientInner<S> {
         let reply_text = format!("@{} {filtered_text}", reply_type.acct());
         let reply_spoiler = reply_type
             .present_spoiler()
+            .or(actions.content_warning.as_deref())
             .or(assistant_message
                 .is_sensitive
                 .then_some(self.sensitive_spoiler.as_str()))
@@ -423,7 +449,7 @@ impl<S: LnbServer> MastodonLnbClientInner<S> {
 
         let reply_status = NewStatus {
             status: Some(reply_text),
-            visibility: Some(reply_type.visilibity()),
+            visibility: Some(narrow_visibility(reply_type.visilibity(), actions.visibility)),
             in_reply_to_id: reply_type.in_reply_to_id(),
             spoiler_text: reply_spoiler,
             media_ids: Some(attachment_ids),
@@ -486,8 +512,14 @@ impl<S: LnbServer> MastodonLnbClientInner<S> {
         let remind_requester = serde_json::from_str(&requester).map_err(ClientError::by_external)?;
         let assistant_message = update.assistant_response();
         let attachments = update.attachments();
+        // 親投稿がないのでリアクションと返信の省略は無視する
         let replied_status = self
-            .send_reply(ReplyType::Remind(remind_requester), assistant_message, attachments)
+            .send_reply(
+                ReplyType::Remind(remind_requester),
+                assistant_message,
+                attachments,
+                update.actions(),
+            )
             .await?;
         info!(
             "夏稀[{}]: {:?} ({} attachment(s))",
@@ -514,7 +546,7 @@ impl<S: LnbServer> MastodonLnbClientInner<S> {
             .map_err(ClientError::by_external)?,
         };
 
-        let mut context = MessageContext::new_user(identity, self.roles_group.get(&status.account.acct).clone());
+        let mut context = MessageContext::new_user(identity, self.roles_group.get(&status.account.acct));
         context.set(remindable).map_err(ClientError::by_external)?;
         Ok(context)
     }
@@ -580,6 +612,31 @@ pub enum MastodonClientError {
     UnsupportedImageType(String),
 }
 
+/// `requested` が `base` より狭い場合のみそれを採用する。
+fn narrow_visibility(base: Visibility, requested: Option<AssistantVisibility>) -> Visibility {
+    fn openness(visibility: Visibility) -> u8 {
+        match visibility {
+            Visibility::Direct => 0,
+            Visibility::Private => 1,
+            Visibility::Unlisted => 2,
+            Visibility::Public => 3,
+        }
+    }
+
+    let requested = match requested {
+        Some(AssistantVisibility::Public) => Visibility::Public,
+        Some(AssistantVisibility::Unlisted) => Visibility::Unlisted,
+        Some(AssistantVisibility::Private) => Visibility::Private,
+        Some(AssistantVisibility::Direct) => Visibility::Direct,
+        None => return base,
+    };
+    if openness(requested) < openness(base) {
+        requested
+    } else {
+        base
+    }
+}
+
 fn get_default_headers() -> HeaderMap {
     let mut headers = HeaderMap::new();
 
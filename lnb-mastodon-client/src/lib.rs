@@ -6,7 +6,7 @@ use crate::inner::MastodonLnbClientInner;
 use std::sync::Arc;
 
 use futures::{future::BoxFuture, prelude::*};
-use lnb_common::{config::client::ConfigClientMastodon, user_roles::UserRolesGroup};
+use lnb_common::{config::client::ConfigClientMastodon, user_roles::SharedUserRolesGroup};
 use lnb_core::{
     error::{ClientError, ReminderError},
     interface::{client::LnbClient, reminder::Remindable, server::LnbServer},
@@ -22,7 +22,7 @@ pub struct MastodonLnbClient<S>(Arc<MastodonLnbClientInner<S>>);
 impl<S: LnbServer> MastodonLnbClient<S> {
     pub async fn new(
         config: &ConfigClientMastodon,
-        roles_group: UserRolesGroup,
+        roles_group: SharedUserRolesGroup,
         assistant: S,
     ) -> Result<MastodonLnbClient<S>, ClientError> {
         let inner = MastodonLnbClientInner::new(config, roles_group, assistant).await?;
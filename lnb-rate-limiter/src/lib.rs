@@ -3,7 +3,10 @@ mod rate;
 
 pub use rate::{Rate, RateFilter};
 
-use std::{collections::HashMap, sync::Arc};
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock},
+};
 
 use time::UtcDateTime;
 use tokio::sync::Mutex;
@@ -16,20 +19,33 @@ pub enum Rated {
 
 #[derive(Debug, Clone)]
 pub struct RateLimiter {
-    default_rate: Rate,
-    filters: Arc<[RateFilter]>,
+    rules: Arc<RwLock<RateRules>>,
     buckets: Arc<Mutex<HashMap<String, bucket::Bucket>>>,
 }
 
+#[derive(Debug, Clone)]
+struct RateRules {
+    default_rate: Rate,
+    filters: Vec<RateFilter>,
+}
+
 impl RateLimiter {
     pub fn new(default_rate: Rate, filters: impl IntoIterator<Item = RateFilter>) -> RateLimiter {
         RateLimiter {
-            default_rate,
-            filters: filters.into_iter().collect(),
+            rules: Arc::new(RwLock::new(RateRules {
+                default_rate,
+                filters: filters.into_iter().collect(),
+            })),
             buckets: Arc::new(Mutex::new(HashMap::new())),
         }
     }
 
+    /// `source` の規則に差し替える。これまでの bucket はそのまま引き継ぐ。
+    pub fn replace_rules(&self, source: &RateLimiter) {
+        let rules = source.rules.read().expect("poisoned").clone();
+        *self.rules.write().expect("poisoned") = rules;
+    }
+
     pub async fn export_buckets(&self) -> HashMap<String, bucket::Bucket> {
         self.buckets.lock().await.clone()
     }
@@ -42,7 +58,7 @@ impl RateLimiter {
     pub async fn check(&self, now: UtcDateTime, key: impl Into<String>) -> Rated {
         let key = key.into();
         let (rate_duration, rate_count) = match self.find_rate(&key) {
-            Rate::Limited { duration, count } => (*duration, *count),
+            Rate::Limited { duration, count } => (duration, count),
             Rate::Unlimited => return Rated::Success,
             Rate::Prohibited => return Rated::Failure,
         };
@@ -60,10 +76,40 @@ impl RateLimiter {
         }
     }
 
-    fn find_rate(&self, key: &str) -> &Rate {
-        self.filters
+    fn find_rate(&self, key: &str) -> Rate {
+        let rules = self.rules.read().expect("poisoned");
+        rules
+            .filters
             .iter()
             .find_map(|f| f.matches(key))
-            .unwrap_or(&self.default_rate)
+            .unwrap_or(&rules.default_rate)
+            .clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use time::{Duration, macros::utc_datetime};
+
+    use super::{Rate, RateLimiter, Rated};
+
+    #[tokio::test]
+    async fn replacing_rules_keeps_buckets() {
+        let now = utc_datetime!(2025-04-01 00:00);
+        let limited = |count| Rate::Limited {
+            duration: Duration::seconds(60),
+            count,
+        };
+        let rate_limiter = RateLimiter::new(limited(1), []);
+        assert_eq!(rate_limiter.check(now, "user").await, Rated::Success);
+        assert_eq!(rate_limiter.check(now, "user").await, Rated::Failure);
+
+        // 使用済みの 1 回は残っている
+        rate_limiter.replace_rules(&RateLimiter::new(limited(2), []));
+        assert_eq!(rate_limiter.check(now, "user").await, Rated::Success);
+        assert_eq!(rate_limiter.check(now, "user").await, Rated::Failure);
+
+        rate_limiter.replace_rules(&RateLimiter::new(Rate::Prohibited, []));
+        assert_eq!(rate_limiter.check(now, "another").await, Rated::Failure);
     }
 }
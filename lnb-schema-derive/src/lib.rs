@@ -0,0 +1,219 @@
+//! `lnb_core::model::schema::DescribeSchema` の derive マクロ。
+
+mod case;
+
+use crate::case::RenameRule;
+
+use proc_macro::TokenStream;
+use proc_macro2::{Span, TokenStream as TokenStream2};
+use quote::quote;
+use syn::{
+    Attribute, Data, DataEnum, DataStruct, DeriveInput, Error, Expr, ExprLit, Fields, Ident, Lit, LitStr, Meta,
+    parse_macro_input,
+};
+
+/// 構造体から object の、ユニット列挙型から string enum の `DescribedSchema` を生成する。
+///
+/// - 各フィールドの説明には doc comment を使う
+/// - `Option<T>` は nullable になる
+/// - `#[serde(rename_all = "...")]` と `#[serde(rename = "...")]` に従って名前を変える
+/// - `#[serde(default)]` のフィールドは省略可能になる
+/// - `#[schema(minimum = 1, format = "date-time", ...)]` で制約を付けられる
+#[proc_macro_derive(DescribedSchema, attributes(schema))]
+pub fn derive_described_schema(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand(input).unwrap_or_else(Error::into_compile_error).into()
+}
+
+fn expand(input: DeriveInput) -> Result<TokenStream2, Error> {
+    let rename_all = serde_rename_all(&input.attrs)?;
+    let body = match &input.data {
+        Data::Struct(data) => expand_struct(data, rename_all)?,
+        Data::Enum(data) => expand_enum(data, rename_all)?,
+        Data::Union(_) => return Err(Error::new_spanned(&input.ident, "union is not supported")),
+    };
+
+    let ident = &input.ident;
+    let (impl_generics, type_generics, where_clause) = input.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics ::lnb_core::model::schema::DescribeSchema for #ident #type_generics #where_clause {
+            fn describe_schema(
+                name: impl Into<String>,
+                description: impl Into<String>,
+            ) -> ::lnb_core::model::schema::DescribedSchema {
+                #body
+            }
+        }
+    })
+}
+
+fn expand_struct(data: &DataStruct, rename_all: Option<RenameRule>) -> Result<TokenStream2, Error> {
+    let Fields::Named(fields) = &data.fields else {
+        return Err(Error::new_spanned(&data.fields, "only named fields are supported"));
+    };
+
+    let mut field_schemas = vec![];
+    for field in &fields.named {
+        let ident = field.ident.as_ref().expect("named field should have an ident");
+        let name = match serde_rename(&field.attrs)? {
+            Some(renamed) => renamed,
+            None => rename_all.map_or_else(|| ident.to_string(), |r| r.apply_to_field(&ident.to_string())),
+        };
+        let Some(description) = doc_comment(&field.attrs) else {
+            return Err(Error::new_spanned(
+                ident,
+                "field must have a doc comment as its description",
+            ));
+        };
+        let field_type = &field.ty;
+        let modifiers = schema_modifiers(&field.attrs)?;
+        let omittable = serde_flag(&field.attrs, "default")?.then(|| quote! { .as_omittable() });
+        field_schemas.push(quote! {
+            <#field_type as ::lnb_core::model::schema::DescribeSchema>::describe_schema(#name, #description)
+                #omittable #(#modifiers)*
+        });
+    }
+
+    Ok(quote! {
+        ::lnb_core::model::schema::DescribedSchema::object(name, description, vec![#(#field_schemas),*])
+    })
+}
+
+fn expand_enum(data: &DataEnum, rename_all: Option<RenameRule>) -> Result<TokenStream2, Error> {
+    let mut variants = vec![];
+    for variant in &data.variants {
+        if !matches!(variant.fields, Fields::Unit) {
+            return Err(Error::new_spanned(variant, "only unit variants are supported"));
+        }
+        let name = match serde_rename(&variant.attrs)? {
+            Some(renamed) => renamed,
+            None => rename_all.map_or_else(
+                || variant.ident.to_string(),
+                |r| r.apply_to_variant(&variant.ident.to_string()),
+            ),
+        };
+        variants.push(name);
+    }
+
+    Ok(quote! {
+        ::lnb_core::model::schema::DescribedSchema::string_enum(name, description, [#(#variants),*])
+    })
+}
+
+/// doc comment を行ごとに trim して結合する。
+fn doc_comment(attrs: &[Attribute]) -> Option<String> {
+    let lines: Vec<_> = attrs
+        .iter()
+        .filter(|a| a.path().is_ident("doc"))
+        .filter_map(|a| match &a.meta {
+            Meta::NameValue(nv) => match &nv.value {
+                Expr::Lit(lit) => match &lit.lit {
+                    Lit::Str(s) => Some(s.value().trim().to_string()),
+                    _ => None,
+                },
+                _ => None,
+            },
+            _ => None,
+        })
+        .collect();
+    let joined = lines.join("\n").trim().to_string();
+    (!joined.is_empty()).then_some(joined)
+}
+
+/// `#[schema(...)]` を `DescribedSchema` のメソッド呼び出しに変換する。
+fn schema_modifiers(attrs: &[Attribute]) -> Result<Vec<TokenStream2>, Error> {
+    let mut modifiers = vec![];
+    for attr in attrs.iter().filter(|a| a.path().is_ident("schema")) {
+        attr.parse_nested_meta(|meta| {
+            let Some(key) = meta.path.get_ident().map(|i| i.to_string()) else {
+                return Err(meta.error("unsupported schema attribute"));
+            };
+            let modifier = match key.as_str() {
+                "minimum" | "maximum" => {
+                    let method = Ident::new(&format!("with_{key}"), Span::call_site());
+                    let value: Lit = meta.value()?.parse()?;
+                    quote! { .#method(#value as f64) }
+                }
+                "min_items" | "max_items" => {
+                    let method = Ident::new(&format!("with_{key}"), Span::call_site());
+                    let value: Lit = meta.value()?.parse()?;
+                    quote! { .#method(#value) }
+                }
+                "pattern" => {
+                    let value: LitStr = meta.value()?.parse()?;
+                    quote! { .with_pattern(#value) }
+                }
+                "format" => {
+                    let value: LitStr = meta.value()?.parse()?;
+                    let variant = Ident::new(&case::kebab_to_pascal(&value.value()), value.span());
+                    quote! { .with_format(::lnb_core::model::schema::StringFormat::#variant) }
+                }
+                "default" => {
+                    let value: Lit = meta.value()?.parse()?;
+                    quote! { .with_default(#value) }
+                }
+                _ => return Err(meta.error("unsupported schema attribute")),
+            };
+            modifiers.push(modifier);
+            Ok(())
+        })?;
+    }
+    Ok(modifiers)
+}
+
+fn serde_rename_all(attrs: &[Attribute]) -> Result<Option<RenameRule>, Error> {
+    let Some(lit) = serde_attribute(attrs, "rename_all")? else {
+        return Ok(None);
+    };
+    match RenameRule::parse(&lit.value()) {
+        Some(rule) => Ok(Some(rule)),
+        None => Err(Error::new_spanned(lit, "unsupported rename_all rule")),
+    }
+}
+
+fn serde_rename(attrs: &[Attribute]) -> Result<Option<String>, Error> {
+    Ok(serde_attribute(attrs, "rename")?.map(|lit| lit.value()))
+}
+
+/// `#[serde(key)]` または `#[serde(key = "...")]` が指定されているか。
+fn serde_flag(attrs: &[Attribute], key: &str) -> Result<bool, Error> {
+    let mut found = false;
+    visit_serde_attributes(attrs, |meta_key, _| found |= meta_key == key)?;
+    Ok(found)
+}
+
+/// `#[serde(key = "...")]` の値を取り出す。
+fn serde_attribute(attrs: &[Attribute], key: &str) -> Result<Option<LitStr>, Error> {
+    let mut found = None;
+    visit_serde_attributes(attrs, |meta_key, value| {
+        if meta_key == key {
+            found = value;
+        }
+    })?;
+    Ok(found)
+}
+
+/// serde の属性を列挙する。値が文字列リテラルでないものは `None` として渡す。
+fn visit_serde_attributes(attrs: &[Attribute], mut visitor: impl FnMut(&str, Option<LitStr>)) -> Result<(), Error> {
+    for attr in attrs.iter().filter(|a| a.path().is_ident("serde")) {
+        attr.parse_nested_meta(|meta| {
+            let key = meta.path.get_ident().map(|i| i.to_string()).unwrap_or_default();
+            let value = if meta.input.peek(syn::Token![=]) {
+                match meta.value()?.parse::<Expr>()? {
+                    Expr::Lit(ExprLit { lit: Lit::Str(s), .. }) => Some(s),
+                    _ => None,
+                }
+            } else if meta.input.peek(syn::token::Paren) {
+                let content;
+                syn::parenthesized!(content in meta.input);
+                content.parse::<TokenStream2>()?;
+                None
+            } else {
+                None
+            };
+            visitor(&key, value);
+            Ok(())
+        })?;
+    }
+    Ok(())
+}
@@ -0,0 +1,92 @@
+use lnb_core::model::schema::{DescribeSchema, DescribedSchema, StringFormat};
+use serde::Deserialize;
+
+/// 引数
+#[allow(dead_code)]
+#[derive(Debug, Deserialize, DescribedSchema)]
+struct Parameters {
+    /// 動作モード。
+    mode: Mode,
+
+    /// 複数行の
+    /// 説明。
+    prompt: String,
+
+    /// 個数。
+    #[schema(minimum = 1, maximum = 4)]
+    count: Option<usize>,
+
+    /// URL のリスト。
+    #[serde(rename = "urls")]
+    #[schema(min_items = 1)]
+    input_urls: Vec<String>,
+
+    /// 入れ子。
+    nested: Nested,
+
+    /// 日時。
+    #[serde(default)]
+    #[schema(format = "date-time", pattern = "\\+09:00$")]
+    at: String,
+
+    /// 倍率。
+    #[schema(default = 2)]
+    scale: Option<u32>,
+}
+
+#[allow(dead_code)]
+#[derive(Debug, Deserialize, DescribedSchema)]
+#[serde(rename_all = "camelCase")]
+struct Nested {
+    /// 倍率。
+    scale_factor: f64,
+
+    /// 有効かどうか。
+    enabled: bool,
+}
+
+#[allow(dead_code)]
+#[derive(Debug, Deserialize, DescribedSchema)]
+#[serde(rename_all = "snake_case")]
+enum Mode {
+    Generate,
+    EditExisting,
+}
+
+#[test]
+fn derives_object_schema() {
+    let expected = DescribedSchema::object(
+        "parameters",
+        "引数",
+        vec![
+            DescribedSchema::string_enum("mode", "動作モード。", ["generate", "edit_existing"]),
+            DescribedSchema::string("prompt", "複数行の\n説明。"),
+            DescribedSchema::integer("count", "個数。")
+                .as_nullable()
+                .with_minimum(1.0)
+                .with_maximum(4.0),
+            DescribedSchema::array(
+                "urls",
+                "URL のリスト。",
+                DescribedSchema::string("item", "URL のリスト。"),
+            )
+            .with_min_items(1),
+            DescribedSchema::object(
+                "nested",
+                "入れ子。",
+                vec![
+                    DescribedSchema::float("scaleFactor", "倍率。"),
+                    DescribedSchema::boolean("enabled", "有効かどうか。"),
+                ],
+            ),
+            DescribedSchema::string("at", "日時。")
+                .as_omittable()
+                .with_format(StringFormat::DateTime)
+                .with_pattern("\\+09:00$"),
+            DescribedSchema::integer("scale", "倍率。")
+                .as_nullable()
+                .with_default(2),
+        ],
+    );
+    assert_eq!(Parameters::describe_schema("parameters", "引数"), expected);
+}
@@ -3,12 +3,22 @@ mod simple;
 
 pub use interception::{BangCommandInterception, BangCommandResponse, fn_command};
 
-pub async fn initialize_bang_command() -> BangCommandInterception {
+use lnb_common::config::assistant::ConfigAssistant;
+
+pub async fn initialize_bang_command(assistant_config: &ConfigAssistant) -> BangCommandInterception {
     let interception = BangCommandInterception::new();
     interception.register_command("ping", fn_command(simple::ping)).await;
     interception
         .register_command("change", fn_command(simple::change))
         .await;
 
+    let personas: Vec<_> = assistant_config.personas.keys().cloned().collect();
+    interception
+        .register_command(
+            "persona",
+            fn_command(move |_ctx, rest_text| simple::persona(&personas, rest_text)),
+        )
+        .await;
+
     interception
 }
@@ -8,7 +8,7 @@ use lnb_core::{
         interception::{Interception, InterceptionStatus},
     },
     model::{
-        conversation::{ConversationModel, IncompleteConversation},
+        conversation::{ConversationModel, ConversationPersona, IncompleteConversation},
         message::{AssistantMessage, UserMessageContent},
     },
 };
@@ -83,6 +83,9 @@ impl BangCommandInterception {
         if let Some(model_override) = result_status.model_override {
             incomplete.set_model_override(model_override);
         }
+        if let Some(persona_override) = result_status.persona_override {
+            incomplete.set_persona_override(persona_override);
+        }
         Ok(result_status.status)
     }
 
@@ -99,6 +102,7 @@ impl BangCommandInterception {
 pub struct BangCommandResponse {
     pub status: InterceptionStatus,
     pub model_override: Option<ConversationModel>,
+    pub persona_override: Option<ConversationPersona>,
 }
 
 /// `BangCommandInterception` から呼び出されるコマンドの実装。
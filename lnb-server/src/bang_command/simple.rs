@@ -3,7 +3,10 @@ use crate::bang_command::BangCommandResponse;
 use lnb_core::{
     error::LlmError,
     interface::{MessageContext, interception::InterceptionStatus},
-    model::{conversation::ConversationModel, message::AssistantMessage},
+    model::{
+        conversation::{ConversationModel, ConversationPersona},
+        message::AssistantMessage,
+    },
 };
 
 pub fn ping(_ctx: &MessageContext, _rest_text: &str) -> Result<BangCommandResponse, LlmError> {
@@ -25,6 +28,7 @@ pub fn change(_ctx: &MessageContext, rest_text: &str) -> Result<BangCommandRespo
                 ..Default::default()
             }),
             model_override: Some(ConversationModel::Default),
+            ..Default::default()
         })
     } else {
         Ok(BangCommandResponse {
@@ -34,6 +38,32 @@ pub fn change(_ctx: &MessageContext, rest_text: &str) -> Result<BangCommandRespo
                 ..Default::default()
             }),
             model_override: Some(ConversationModel::Specified(rest_text.to_string())),
+            ..Default::default()
         })
     }
 }
+
+pub fn persona(personas: &[String], rest_text: &str) -> Result<BangCommandResponse, LlmError> {
+    let (text, persona_override) = if rest_text.is_empty() || rest_text == "default" {
+        (
+            "persona restored to default".to_string(),
+            Some(ConversationPersona::Default),
+        )
+    } else if personas.iter().any(|p| p == rest_text) {
+        (
+            format!("persona changed to {rest_text}"),
+            Some(ConversationPersona::Named(rest_text.to_string())),
+        )
+    } else {
+        (format!("unknown persona: {rest_text}"), None)
+    };
+    Ok(BangCommandResponse {
+        status: InterceptionStatus::Complete(AssistantMessage {
+            text,
+            skip_llm: true,
+            ..Default::default()
+        }),
+        persona_override,
+        ..Default::default()
+    })
+}
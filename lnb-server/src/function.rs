@@ -1,6 +1,7 @@
 mod daily_private;
 mod exchange_rate;
 mod get_illust_url;
+mod http_tool;
 mod image_generator;
 mod local_info;
 mod math_renderer;
@@ -9,6 +10,7 @@ mod self_info;
 pub use daily_private::DailyPrivate;
 pub use exchange_rate::ExchangeRate;
 pub use get_illust_url::GetIllustUrl;
+pub use http_tool::HttpTool;
 pub use image_generator::ImageGenerator;
 use lnb_rate_limiter::RateLimiter;
 pub use local_info::LocalInfo;
@@ -3,7 +3,7 @@ use crate::function::ConfigurableFunction;
 use std::collections::HashMap;
 
 use futures::{FutureExt, TryFutureExt, future::BoxFuture};
-use lnb_common::config::tools::ConfigToolsExchangeRate;
+use lnb_common::{cassette::CassetteClient, config::tools::ConfigToolsExchangeRate};
 use lnb_core::{
     APP_USER_AGENT, RFC3339_NUMOFFSET,
     context::Context,
@@ -12,10 +12,14 @@ use lnb_core::{
         MessageContext,
         function::{Function, FunctionDescriptor, FunctionResponse},
     },
-    model::{conversation::IncompleteConversation, message::MessageToolCalling, schema::DescribedSchema},
+    model::{
+        conversation::IncompleteConversation,
+        message::MessageToolCalling,
+        schema::{DescribeSchema, DescribedSchema},
+    },
 };
 use lnb_rate_limiter::RateLimiter;
-use reqwest::{Client, ClientBuilder};
+use reqwest::ClientBuilder;
 use serde::Deserialize;
 use serde_json::json;
 use time::{OffsetDateTime, format_description::BorrowedFormatItem, macros::format_description};
@@ -27,7 +31,7 @@ const API_RESPONSE_DATETIME: &[BorrowedFormatItem<'static>] = format_description
 
 #[derive(Debug)]
 pub struct ExchangeRate {
-    client: Client,
+    client: CassetteClient,
     token_endpoint: String,
 }
 
@@ -44,6 +48,9 @@ impl ConfigurableFunction for ExchangeRate {
             .user_agent(APP_USER_AGENT)
             .build()
             .map_err(FunctionError::by_external)?;
+        let client = CassetteClient::new(client, "exchange_rate")
+            .map_err(FunctionError::by_external)?
+            .with_secrets([config.token.clone()]);
         let token_endpoint = format!("{}/v6/{}", config.endpoint, config.token);
         Ok(ExchangeRate { client, token_endpoint })
     }
@@ -55,18 +62,7 @@ impl Function for ExchangeRate {
         FunctionDescriptor {
             name: "exchange_rate".to_string(),
             description: "為替相場を取得します。同じ計算元の通貨から複数の計算先を一度に取得できます。".to_string(),
-            parameters: DescribedSchema::object(
-                "parameters",
-                "引数",
-                vec![
-                    DescribedSchema::string("base_code", "為替の計算元になる ISO 4217 通貨コード。"),
-                    DescribedSchema::array(
-                        "target_codes",
-                        "為替の計算先になる ISO 4217 通貨コードのリスト。",
-                        DescribedSchema::string("code", "通貨コード"),
-                    ),
-                ],
-            ),
+            parameters: RequestParameters::describe_schema("parameters", "引数"),
         }
     }
 
@@ -87,12 +83,10 @@ impl Function for ExchangeRate {
 
 impl ExchangeRate {
     async fn get_exchange_rate(&self, parameters: RequestParameters) -> Result<FunctionResponse, FunctionError> {
-        let response = self
+        let request = self
             .client
-            .get(format!("{}/latest/{}", self.token_endpoint, parameters.base_code))
-            .send()
-            .map_err(FunctionError::by_external)
-            .await?;
+            .get(format!("{}/latest/{}", self.token_endpoint, parameters.base_code.0));
+        let response = self.client.send(request).map_err(FunctionError::by_external).await?;
         let api_response: ExchangeRateApiResponse = response.json().map_err(FunctionError::by_serialization).await?;
 
         let updated_at = OffsetDateTime::parse(&api_response.time_last_update_utc, API_RESPONSE_DATETIME)
@@ -102,7 +96,7 @@ impl ExchangeRate {
         let target_rates: HashMap<_, _> = parameters
             .target_codes
             .into_iter()
-            .flat_map(|c| api_response.conversion_rates.get(&c).map(|&r| (c, r)))
+            .flat_map(|CurrencyCode(c)| api_response.conversion_rates.get(&c).map(|&r| (c, r)))
             .collect();
 
         Ok(FunctionResponse {
@@ -116,10 +110,25 @@ impl ExchangeRate {
     }
 }
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Deserialize, DescribedSchema)]
 struct RequestParameters {
-    base_code: String,
-    target_codes: Vec<String>,
+    /// 為替の計算元になる ISO 4217 通貨コード。
+    base_code: CurrencyCode,
+
+    /// 為替の計算先になる ISO 4217 通貨コードのリスト。
+    #[schema(min_items = 1)]
+    target_codes: Vec<CurrencyCode>,
+}
+
+/// ISO 4217 通貨コード。
+#[derive(Debug, Clone, Deserialize)]
+#[serde(transparent)]
+struct CurrencyCode(String);
+
+impl DescribeSchema for CurrencyCode {
+    fn describe_schema(name: impl Into<String>, description: impl Into<String>) -> DescribedSchema {
+        DescribedSchema::string(name, description).with_pattern("^[A-Z]{3}$")
+    }
 }
 
 #[derive(Debug, Clone, Deserialize)]
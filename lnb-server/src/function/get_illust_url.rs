@@ -9,11 +9,15 @@ use lnb_core::{
         MessageContext,
         function::{Function, FunctionDescriptor, FunctionResponse},
     },
-    model::{conversation::IncompleteConversation, message::MessageToolCalling, schema::DescribedSchema},
+    model::{
+        conversation::IncompleteConversation,
+        message::MessageToolCalling,
+        schema::{DescribeSchema, DescribedSchema},
+    },
 };
 use lnb_rate_limiter::RateLimiter;
 use rand::{rng, seq::IndexedRandom};
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use serde_json::json;
 use sqlx::{SqlitePool, prelude::FromRow};
 
@@ -47,11 +51,7 @@ impl Function for GetIllustUrl {
                 自画像・自撮りを要求された場合もこれを利用する。
             "#
             .to_string(),
-            parameters: DescribedSchema::object(
-                "parameters",
-                "引数",
-                vec![DescribedSchema::integer("count", "要求したいイラストの URL の数")],
-            ),
+            parameters: RequestParameters::describe_schema("parameters", "引数"),
         }
     }
 
@@ -62,8 +62,11 @@ impl Function for GetIllustUrl {
         _incomplete: &'a IncompleteConversation,
         tool_calling: MessageToolCalling,
     ) -> BoxFuture<'a, Result<FunctionResponse, FunctionError>> {
-        let count = tool_calling.arguments["count"].as_u64().unwrap_or(1) as usize;
-        async move { self.get_illust_infos(count).await }.boxed()
+        let parameters: RequestParameters = match serde_json::from_value(tool_calling.arguments) {
+            Ok(p) => p,
+            Err(err) => return async { Err(FunctionError::by_serialization(err)) }.boxed(),
+        };
+        async move { self.get_illust_infos(parameters.count).await }.boxed()
     }
 }
 
@@ -86,6 +89,12 @@ impl GetIllustUrl {
     }
 }
 
+#[derive(Debug, Clone, Deserialize, DescribedSchema)]
+struct RequestParameters {
+    /// 要求したいイラストの URL の数
+    count: usize,
+}
+
 #[derive(Debug, Serialize, FromRow)]
 pub struct SqliteRowIllust {
     pub url: String,
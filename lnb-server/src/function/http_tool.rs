@@ -0,0 +1,329 @@
+use crate::{function::ConfigurableFunction, mcp::convert_input_schema};
+
+use std::{collections::HashMap, env};
+
+use futures::{FutureExt, TryFutureExt, future::BoxFuture};
+use lnb_common::{
+    cassette::CassetteClient,
+    config::tools::{ConfigToolsHttpMethod, ConfigToolsHttpTool},
+};
+use lnb_core::{
+    APP_USER_AGENT,
+    context::Context,
+    error::FunctionError,
+    interface::{
+        MessageContext,
+        function::{Function, FunctionDescriptor, FunctionResponse},
+    },
+    model::{conversation::IncompleteConversation, message::MessageToolCalling},
+};
+use lnb_rate_limiter::{RateLimiter, Rated};
+use reqwest::{ClientBuilder, Method, header::CONTENT_TYPE};
+use serde::Serialize;
+use serde_json::Value;
+use time::UtcDateTime;
+use upon::{Engine, Template};
+
+/// 設定で定義された HTTP function 。
+#[derive(Debug)]
+pub struct HttpTool {
+    client: CassetteClient,
+    descriptor: FunctionDescriptor,
+    method: Method,
+    engine: Engine<'static>,
+    url: Template<'static>,
+    headers: Vec<(String, Template<'static>)>,
+    body: Option<Template<'static>>,
+    secrets: HashMap<String, String>,
+    extract: Option<String>,
+    rate_limiter: Option<RateLimiter>,
+}
+
+impl ConfigurableFunction for HttpTool {
+    const NAME: &'static str = stringify!(HttpTool);
+
+    type Configuration = ConfigToolsHttpTool;
+
+    async fn configure(
+        config: &ConfigToolsHttpTool,
+        rate_limiter: Option<RateLimiter>,
+    ) -> Result<HttpTool, FunctionError> {
+        let descriptor = FunctionDescriptor {
+            name: config.name.clone(),
+            description: config.description.clone(),
+            parameters: convert_input_schema(&config.parameters).map_err(FunctionError::by_serialization)?,
+        };
+
+        // URL のクエリ用と JSON の文字列リテラル用
+        let mut engine = Engine::new();
+        engine.add_function("urlencode", |s: &str| {
+            url::form_urlencoded::byte_serialize(s.as_bytes()).collect::<String>()
+        });
+        engine.add_function("json", |s: &str| Value::String(s.to_string()).to_string());
+
+        let url = engine
+            .compile(config.url.clone())
+            .map_err(FunctionError::by_serialization)?;
+        let headers = config
+            .headers
+            .iter()
+            .map(|(name, value)| Ok((name.clone(), engine.compile(value.clone())?)))
+            .collect::<Result<_, upon::Error>>()
+            .map_err(FunctionError::by_serialization)?;
+        let body = config
+            .body
+            .clone()
+            .map(|b| engine.compile(b))
+            .transpose()
+            .map_err(FunctionError::by_serialization)?;
+
+        let secrets = config
+            .secrets
+            .iter()
+            .map(|(name, variable)| match env::var(variable) {
+                Ok(value) => Ok((name.clone(), value)),
+                Err(e) => Err(FunctionError::by_external(format!("secret {name} ({variable}): {e}"))),
+            })
+            .collect::<Result<HashMap<_, _>, _>>()?;
+
+        // URL やボディに埋め込まれうるので、記録からは伏せる
+        let client = ClientBuilder::new()
+            .user_agent(APP_USER_AGENT)
+            .build()
+            .map_err(FunctionError::by_external)?;
+        let client = CassetteClient::new(client, &format!("http_tool_{}", config.name))
+            .map_err(FunctionError::by_external)?
+            .with_secrets(secrets.values().cloned());
+
+        Ok(HttpTool {
+            client,
+            descriptor,
+            method: http_method(config.method),
+            engine,
+            url,
+            headers,
+            body,
+            secrets,
+            extract: config.extract.clone(),
+            rate_limiter,
+        })
+    }
+}
+
+impl Function for HttpTool {
+    fn get_descriptor(&self) -> FunctionDescriptor {
+        self.descriptor.clone()
+    }
+
+    fn call<'a>(
+        &'a self,
+        _ctx: &'a Context,
+        message_ctx: &'a MessageContext,
+        _incomplete: &'a IncompleteConversation,
+        tool_calling: MessageToolCalling,
+    ) -> BoxFuture<'a, Result<FunctionResponse, FunctionError>> {
+        async move { self.execute(message_ctx, tool_calling.arguments).await }.boxed()
+    }
+}
+
+impl HttpTool {
+    async fn execute(&self, message_ctx: &MessageContext, arguments: Value) -> Result<FunctionResponse, FunctionError> {
+        if !self.ensure_in_rate(message_ctx.identity()).await {
+            return Err(FunctionError::by_external("rate limit exceeded"));
+        }
+
+        let template_context = TemplateContext {
+            args: &arguments,
+            secrets: &self.secrets,
+        };
+        let mut request = self
+            .client
+            .request(self.method.clone(), self.render(&self.url, &template_context)?);
+        for (name, value) in &self.headers {
+            request = request.header(name, self.render(value, &template_context)?);
+        }
+        request = match &self.body {
+            Some(body) => request
+                .header(CONTENT_TYPE, "application/json")
+                .body(self.render(body, &template_context)?),
+            None if self.method == Method::GET => request,
+            None => request.json(&arguments),
+        };
+
+        let response = self.client.send(request).map_err(FunctionError::by_external).await?;
+        let response = response.error_for_status().map_err(FunctionError::by_external)?;
+        let text = response.text().map_err(FunctionError::by_external).await?;
+
+        // JSON でなければ本文をそのまま返す
+        let Ok(response_json) = serde_json::from_str::<Value>(&text) else {
+            return Ok(FunctionResponse {
+                result: Value::String(text),
+                ..Default::default()
+            });
+        };
+        let result = match &self.extract {
+            Some(pointer) => response_json
+                .pointer(pointer)
+                .cloned()
+                .ok_or_else(|| FunctionError::by_serialization(format!("{pointer} not found in response")))?,
+            None => response_json,
+        };
+
+        Ok(FunctionResponse {
+            result,
+            ..Default::default()
+        })
+    }
+
+    fn render(&self, template: &Template<'static>, context: &TemplateContext) -> Result<String, FunctionError> {
+        template
+            .render(&self.engine, context)
+            .to_string()
+            .map_err(FunctionError::by_serialization)
+    }
+
+    async fn ensure_in_rate(&self, identity: Option<&str>) -> bool {
+        let Some(rate_limiter) = &self.rate_limiter else {
+            return true;
+        };
+        let Some(identity) = identity else {
+            return false;
+        };
+        let rated = rate_limiter.check(UtcDateTime::now(), identity).await;
+        matches!(rated, Rated::Success)
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct TemplateContext<'a> {
+    args: &'a Value,
+    secrets: &'a HashMap<String, String>,
+}
+
+fn http_method(method: ConfigToolsHttpMethod) -> Method {
+    match method {
+        ConfigToolsHttpMethod::Get => Method::GET,
+        ConfigToolsHttpMethod::Post => Method::POST,
+        ConfigToolsHttpMethod::Put => Method::PUT,
+        ConfigToolsHttpMethod::Patch => Method::PATCH,
+        ConfigToolsHttpMethod::Delete => Method::DELETE,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_server::{MockResponse, serve};
+
+    use lnb_core::model::user_role::UserRole;
+    use serde_json::json;
+
+    fn create_config(method: ConfigToolsHttpMethod, url: String) -> ConfigToolsHttpTool {
+        ConfigToolsHttpTool {
+            name: "search".to_string(),
+            description: "検索する".to_string(),
+            parameters: json!({
+                "type": "object",
+                "properties": { "query": { "type": "string" } },
+                "required": ["query"],
+            }),
+            method,
+            url,
+            headers: HashMap::new(),
+            secrets: HashMap::new(),
+            body: None,
+            extract: None,
+            rate_limit: None,
+        }
+    }
+
+    async fn execute(config: &ConfigToolsHttpTool, arguments: Value) -> Result<FunctionResponse, FunctionError> {
+        let tool = HttpTool::configure(config, None).await.expect("should configure");
+        let message_ctx = MessageContext::new_user("test:user", UserRole::Normal);
+        tool.execute(&message_ctx, arguments).await
+    }
+
+    #[tokio::test]
+    async fn escapes_template_arguments() {
+        let (endpoint, handle) = serve(vec![MockResponse::json(json!({ "ok": true }))]).await;
+        let config = ConfigToolsHttpTool {
+            body: Some(r#"{"query": {{ args.query | json }} }"#.to_string()),
+            ..create_config(
+                ConfigToolsHttpMethod::Post,
+                format!("{endpoint}/search?q={{{{ args.query | urlencode }}}}"),
+            )
+        };
+
+        let response = execute(&config, json!({ "query": "夏 & \"冬\"" }))
+            .await
+            .expect("should succeed");
+        assert_eq!(response.result, json!({ "ok": true }));
+
+        let request = &handle.await.expect("should receive request")[0];
+        assert_eq!(request.method, "POST");
+        assert_eq!(request.target, "/search?q=%E5%A4%8F+%26+%22%E5%86%AC%22");
+        assert_eq!(request.json(), json!({ "query": "夏 & \"冬\"" }));
+    }
+
+    #[tokio::test]
+    async fn extracts_pointer_from_response() {
+        let response_body = json!({ "items": [{ "title": "夏稀" }] });
+        let (endpoint, _handle) = serve(vec![
+            MockResponse::json(response_body.clone()),
+            MockResponse::json(response_body),
+        ])
+        .await;
+        let config = ConfigToolsHttpTool {
+            extract: Some("/items/0/title".to_string()),
+            ..create_config(ConfigToolsHttpMethod::Get, format!("{endpoint}/items"))
+        };
+        let response = execute(&config, json!({ "query": "" })).await.expect("should succeed");
+        assert_eq!(response.result, json!("夏稀"));
+
+        let missing = ConfigToolsHttpTool {
+            extract: Some("/items/1/title".to_string()),
+            ..config
+        };
+        let result = execute(&missing, json!({ "query": "" })).await;
+        assert!(matches!(result, Err(FunctionError::Serialization(_))), "{result:?}");
+    }
+
+    #[tokio::test]
+    async fn passes_non_json_response_through() {
+        let (endpoint, _handle) = serve(vec![MockResponse::text("text/plain", "晴れのち曇り")]).await;
+        let config = ConfigToolsHttpTool {
+            extract: Some("/ignored".to_string()),
+            ..create_config(ConfigToolsHttpMethod::Get, format!("{endpoint}/weather"))
+        };
+
+        let response = execute(&config, json!({ "query": "" })).await.expect("should succeed");
+        assert_eq!(response.result, json!("晴れのち曇り"));
+    }
+
+    #[tokio::test]
+    async fn reads_secrets_from_env() {
+        // SAFETY: このテスト専用の変数で、ほかのテストからは読み書きしない
+        unsafe { env::set_var("LNB_HTTP_TOOL_TEST_TOKEN", "s3cr3t") };
+        let (endpoint, handle) = serve(vec![MockResponse::json(json!({}))]).await;
+        let config = ConfigToolsHttpTool {
+            headers: HashMap::from([("Authorization".to_string(), "Bearer {{ secrets.token }}".to_string())]),
+            secrets: HashMap::from([("token".to_string(), "LNB_HTTP_TOOL_TEST_TOKEN".to_string())]),
+            ..create_config(
+                ConfigToolsHttpMethod::Get,
+                format!("{endpoint}/private?key={{{{ secrets.token }}}}"),
+            )
+        };
+
+        execute(&config, json!({ "query": "" })).await.expect("should succeed");
+        let request = &handle.await.expect("should receive request")[0];
+        assert_eq!(request.header("authorization"), Some("Bearer s3cr3t"));
+        assert_eq!(request.target, "/private?key=s3cr3t");
+
+        let missing = ConfigToolsHttpTool {
+            secrets: HashMap::from([("token".to_string(), "LNB_HTTP_TOOL_TEST_MISSING".to_string())]),
+            ..config
+        };
+        let result = HttpTool::configure(&missing, None).await;
+        assert!(matches!(result, Err(FunctionError::External(_))));
+    }
+}
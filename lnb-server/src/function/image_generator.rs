@@ -3,7 +3,7 @@ use crate::function::ConfigurableFunction;
 use async_openai::types::images::{Image, ImagesResponse};
 use base64::prelude::*;
 use futures::{FutureExt, TryFutureExt, future::BoxFuture};
-use lnb_common::{config::tools::ConfigToolsImageGenerator, extension::ContextExt};
+use lnb_common::{cassette::CassetteClient, config::tools::ConfigToolsImageGenerator, extension::ContextExt};
 use lnb_core::{
     APP_USER_AGENT,
     context::Context,
@@ -15,11 +15,11 @@ use lnb_core::{
     model::{
         conversation::{ConversationAttachment, IncompleteConversation},
         message::MessageToolCalling,
-        schema::DescribedSchema,
+        schema::{DescribeSchema, DescribedSchema},
     },
 };
 use lnb_rate_limiter::{RateLimiter, Rated};
-use reqwest::{Client as ReqwestClient, ClientBuilder, Response, header::HeaderMap, multipart::Form};
+use reqwest::{ClientBuilder, Response, header::HeaderMap, multipart::Form};
 use serde::{Deserialize, Serialize, de::DeserializeOwned};
 use serde_json::{Value, json};
 use tempfile::NamedTempFile;
@@ -33,7 +33,7 @@ pub const LOW_MODERATION_SCOPE: &str = "image_generator:low_moderation";
 
 #[derive(Debug)]
 pub struct ImageGenerator {
-    http_client: ReqwestClient,
+    http_client: CassetteClient,
     generate_endpoint: Url,
     edit_endpoint: Url,
     model: String,
@@ -55,11 +55,12 @@ impl ConfigurableFunction for ImageGenerator {
                 "Authorization",
                 format!("Bearer {}", config.token).parse().expect("should pass header"),
             );
-            ClientBuilder::new()
+            let client = ClientBuilder::new()
                 .user_agent(APP_USER_AGENT)
                 .default_headers(headers)
                 .build()
-                .map_err(FunctionError::by_external)?
+                .map_err(FunctionError::by_external)?;
+            CassetteClient::new(client, "image_generator").map_err(FunctionError::by_external)?
         };
         let generate_endpoint =
             Url::parse(&format!("{}/images/generations", config.endpoint)).map_err(FunctionError::by_serialization)?;
@@ -85,26 +86,7 @@ impl Function for ImageGenerator {
                 生成された画像は返答のメッセージに直接添付されます。
             "#
             .to_string(),
-            parameters: DescribedSchema::object(
-                "parameters",
-                "引数",
-                vec![
-                    DescribedSchema::string_enum(
-                        "mode",
-                        "動作モードの指定。新しい画像の生成は generate を、既存画像からの編集は edit を指定する。",
-                        ["generate", "edit"],
-                    ),
-                    DescribedSchema::string(
-                        "prompt",
-                        "GPT-Image, DALL-E などの画像生成モデルに入力するプロンプト文。",
-                    ),
-                    DescribedSchema::array(
-                        "input_image_urls",
-                        "edit mode の場合にユーザーから提供される画像の URL のリスト。 generate mode の場合は空にする。",
-                        DescribedSchema::string("url", "提供された画像の URL。"),
-                    ),
-                ],
-            ),
+            parameters: GenerationParameters::describe_schema("parameters", "引数"),
         }
     }
 
@@ -165,8 +147,7 @@ impl ImageGenerator {
             Image::Url { url, revised_prompt } => {
                 let image_response = self
                     .http_client
-                    .get(url)
-                    .send()
+                    .send(self.http_client.get(url))
                     .map_err(FunctionError::by_external)
                     .await?;
                 let image_bytes = image_response.bytes().map_err(FunctionError::by_external).await?;
@@ -218,11 +199,10 @@ impl ImageGenerator {
             "user": message_ctx.hashed_identity(),
         });
 
+        let request = self.http_client.post(self.generate_endpoint.clone()).json(&request);
         let raw_response = self
             .http_client
-            .post(self.generate_endpoint.clone())
-            .json(&request)
-            .send()
+            .send(request)
             .map_err(FunctionError::by_external)
             .await?;
 
@@ -256,11 +236,10 @@ impl ImageGenerator {
             }
             f
         };
+        let request = self.http_client.post(self.edit_endpoint.clone()).multipart(form);
         let raw_response = self
             .http_client
-            .post(self.edit_endpoint.clone())
-            .multipart(form)
-            .send()
+            .send(request)
             .map_err(FunctionError::by_external)
             .await?;
 
@@ -275,8 +254,7 @@ impl ImageGenerator {
         debug!("downloading image from {url}");
         let image_bytes = self
             .http_client
-            .get(url)
-            .send()
+            .send(self.http_client.get(url))
             .map_err(FunctionError::by_external)
             .await?
             .bytes()
@@ -338,14 +316,19 @@ async fn deserialize_openai_response<T: DeserializeOwned>(response: Response) ->
     }
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, DescribedSchema)]
 struct GenerationParameters {
+    /// 動作モードの指定。新しい画像の生成は generate を、既存画像からの編集は edit を指定する。
     mode: GenerationMode,
+
+    /// GPT-Image, DALL-E などの画像生成モデルに入力するプロンプト文。
     prompt: String,
+
+    /// edit mode の場合にユーザーから提供される画像の URL のリスト。 generate mode の場合は空にする。
     input_image_urls: Vec<String>,
 }
 
-#[derive(Debug, Clone, Copy, Deserialize)]
+#[derive(Debug, Clone, Copy, Deserialize, DescribedSchema)]
 #[serde(rename_all = "snake_case")]
 enum GenerationMode {
     Generate,
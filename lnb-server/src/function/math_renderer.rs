@@ -12,7 +12,7 @@ use lnb_core::{
     model::{
         conversation::{ConversationAttachment, IncompleteConversation},
         message::MessageToolCalling,
-        schema::DescribedSchema,
+        schema::{DescribeSchema, DescribedSchema},
     },
 };
 use lnb_rate_limiter::RateLimiter;
@@ -47,14 +47,7 @@ impl Function for MathRenderer {
                 生成された画像は返答のメッセージに直接添付されます。
             "#
             .to_string(),
-            parameters: DescribedSchema::object(
-                "parameters",
-                "引数",
-                vec![
-                    DescribedSchema::string("formula", "LaTeX 記法の数式。\\[ \\] や $ $ で囲む必要はありません。"),
-                    DescribedSchema::boolean("display_mode", "数式をディスプレイモードでレンダリングするかどうか。"),
-                ],
-            ),
+            parameters: RenderingParameters::describe_schema("parameters", "引数"),
         }
     }
 
@@ -95,8 +88,11 @@ impl MathRenderer {
     }
 }
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Deserialize, DescribedSchema)]
 struct RenderingParameters {
+    /// LaTeX 記法の数式。\[ \] や $ $ で囲む必要はありません。
     formula: String,
+
+    /// 数式をディスプレイモードでレンダリングするかどうか。
     display_mode: bool,
 }
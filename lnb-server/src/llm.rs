@@ -1,5 +1,11 @@
+mod cassette;
 mod claude;
+mod fallback;
 mod openai;
+mod prompt_tool;
+mod scripted;
+
+use crate::natsuki::LlmCache;
 
 use std::{collections::HashMap, sync::LazyLock};
 
@@ -7,41 +13,72 @@ use lnb_common::config::llm::{ConfigLlmBackend, ConfigLlmModel};
 use lnb_core::{
     error::LlmError,
     interface::llm::ArcLlm,
-    model::schema::{DescribedSchema, DescribedSchemaType},
+    model::{
+        message::AssistantVisibility,
+        schema::{DescribeSchema, DescribedSchema, DescribedSchemaType},
+    },
 };
 use serde_json::{Value, json};
 
-// MEMO: proc macro で serde のついでに作った方が面白い
-pub static ASSISTANT_RESPONSE_SCHEMA: LazyLock<DescribedSchema> = LazyLock::new(|| {
-    DescribedSchema::object(
-        "response",
-        "response as assistant",
-        vec![
-            DescribedSchema::string(
-                "text",
-                "ユーザーへの主要な回答内容。夏稀としてふるまって回答してください。",
-            ),
-            DescribedSchema::string("language", "`text` フィールドに対応する IETF BCP47 言語タグ。"),
-            DescribedSchema::boolean("sensitive", "`text` フィールドが性的な話題を含むかどうか。"),
-        ],
-    )
-});
+pub static ASSISTANT_RESPONSE_SCHEMA: LazyLock<DescribedSchema> =
+    LazyLock::new(|| AssistantResponse::describe_schema("response", "response as assistant"));
 
-pub async fn create_llm(config: ConfigLlmModel) -> Result<ArcLlm, LlmError> {
-    match config.backend {
-        ConfigLlmBackend::Openai => openai::create_openai_llm(config.config).await,
+/// `text` フィールドの説明を差し替えた `ASSISTANT_RESPONSE_SCHEMA` 。
+pub fn assistant_response_schema(text_description: &str) -> DescribedSchema {
+    let mut schema = ASSISTANT_RESPONSE_SCHEMA.clone();
+    if let DescribedSchemaType::Object(fields) = &mut schema.field_type
+        && let Some(text_field) = fields.iter_mut().find(|f| f.name == "text")
+    {
+        text_field.description = text_description.to_string();
     }
+    schema
+}
+
+/// structured output で要求する応答の形。スキーマの生成にのみ使い、応答は `LlmAssistantResponse` として読む。
+#[allow(dead_code)]
+#[derive(DescribedSchema)]
+struct AssistantResponse {
+    /// ユーザーへの主要な回答内容。夏稀としてふるまって回答してください。
+    text: String,
+
+    /// `text` フィールドに対応する IETF BCP47 言語タグ。
+    language: String,
+
+    /// `text` フィールドが性的な話題を含むかどうか。
+    sensitive: bool,
+
+    /// 元の投稿にリアクションとして付ける絵文字 1 文字。不要なら null 。
+    reaction: Option<String>,
+
+    /// 返信を折りたたむ場合の注意書き。不要なら null 。
+    content_warning: Option<String>,
+
+    /// 返信の公開範囲の希望。元の投稿より広くはならない。指定しない場合は null 。
+    visibility: Option<AssistantVisibility>,
+
+    /// リアクションだけで済ませて返信を送らない場合は true 。
+    no_reply: bool,
+}
+
+pub async fn create_llm(name: &str, config: ConfigLlmModel, llm_cache: &LlmCache) -> Result<ArcLlm, LlmError> {
+    let llm = match config.backend {
+        ConfigLlmBackend::Openai => openai::create_openai_llm(name, config.config).await?,
+        ConfigLlmBackend::Claude => claude::create_claude_llm(config.config).await?,
+        ConfigLlmBackend::Fallback => fallback::create_fallback_llm(config.config, llm_cache.clone()).await?,
+        ConfigLlmBackend::Scripted => scripted::create_scripted_llm(config.config).await?,
+    };
+    cassette::wrap_cassette(name, llm)
 }
 
 fn convert_json_schema(schema: &DescribedSchema) -> Value {
     let type_value = |s| if schema.optional { json!([s, "null"]) } else { json!(s) };
-    match &schema.field_type {
+    let mut converted = match &schema.field_type {
         DescribedSchemaType::Integer => json!({
             "type": type_value("integer"),
             "description": schema.description,
         }),
         DescribedSchemaType::Float => json!({
-            "type": type_value("float"),
+            "type": type_value("number"),
             "description": schema.description,
         }),
         DescribedSchemaType::Boolean => json!({
@@ -52,11 +89,17 @@ fn convert_json_schema(schema: &DescribedSchema) -> Value {
             "type": type_value("string"),
             "description": schema.description,
         }),
-        DescribedSchemaType::Enum(variants) => json!({
-            "type": type_value("string"),
-            "description": schema.description,
-            "enum": variants,
-        }),
+        DescribedSchemaType::Enum(variants) => {
+            let mut variants: Vec<_> = variants.iter().map(|v| json!(v)).collect();
+            if schema.optional {
+                variants.push(Value::Null);
+            }
+            json!({
+                "type": type_value("string"),
+                "description": schema.description,
+                "enum": variants,
+            })
+        }
         DescribedSchemaType::Array(item_type) => json!({
             "type": type_value("array"),
             "description": schema.description,
@@ -67,13 +110,42 @@ fn convert_json_schema(schema: &DescribedSchema) -> Value {
                 .iter()
                 .map(|f| (f.name.clone(), convert_json_schema(f)))
                 .collect();
-            let keys: Vec<_> = properties.keys().cloned().collect();
+            let required: Vec<_> = fields
+                .iter()
+                .filter(|f| !f.omittable && f.default.is_none())
+                .map(|f| f.name.clone())
+                .collect();
             json!({
                 "type": type_value("object"),
                 "properties": properties,
-                "required": keys,
+                "required": required,
                 "additionalProperties": false,
             })
         }
+        DescribedSchemaType::Union(variants) => {
+            let mut any_of: Vec<_> = variants.iter().map(convert_json_schema).collect();
+            if schema.optional {
+                any_of.push(json!({ "type": "null" }));
+            }
+            json!({
+                "anyOf": any_of,
+                "description": schema.description,
+            })
+        }
+    };
+
+    let constraints = &schema.constraints;
+    let keywords = [
+        ("minimum", constraints.minimum.map(Value::from)),
+        ("maximum", constraints.maximum.map(Value::from)),
+        ("format", constraints.format.map(|f| Value::from(f.as_str()))),
+        ("pattern", constraints.pattern.as_ref().map(|p| Value::from(p.as_str()))),
+        ("minItems", constraints.min_items.map(Value::from)),
+        ("maxItems", constraints.max_items.map(Value::from)),
+        ("default", schema.default.clone()),
+    ];
+    if let Value::Object(object) = &mut converted {
+        object.extend(keywords.into_iter().filter_map(|(k, v)| Some((k.to_string(), v?))));
     }
+    converted
 }
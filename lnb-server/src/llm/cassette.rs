@@ -0,0 +1,280 @@
+use crate::llm::scripted::ScriptedResponse;
+
+use std::sync::Arc;
+
+use futures::{FutureExt, channel::mpsc::UnboundedSender, future::BoxFuture};
+use lnb_common::cassette::{Cassette, CassetteMode};
+use lnb_core::{
+    error::LlmError,
+    interface::{
+        function::FunctionDescriptor,
+        llm::{ArcLlm, Llm, LlmDelta, LlmResponse, LlmUpdate},
+    },
+    model::{conversation::IncompleteConversation, message::Message},
+};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// カセットのデバッグオプションが指定されていれば `llm` をカセット経由にする。
+pub fn wrap_cassette(name: &str, llm: ArcLlm) -> Result<ArcLlm, LlmError> {
+    let Some(cassette) = Cassette::open(&format!("llm-{name}")).map_err(LlmError::by_backend)? else {
+        return Ok(llm);
+    };
+    Ok(Arc::new(CassetteLlm(Arc::new(CassetteLlmInner { llm, cassette }))))
+}
+
+/// 送受信を記録する、または記録した応答を再生するバックエンド。
+/// 応答は `scripted` バックエンドの fixture と同じ形式で記録される。
+#[derive(Clone)]
+pub struct CassetteLlm(Arc<CassetteLlmInner>);
+
+impl Llm for CassetteLlm {
+    fn send_conversation<'a>(
+        &'a self,
+        conversation: &'a IncompleteConversation,
+        function_descriptors: &'a [&'a FunctionDescriptor],
+    ) -> BoxFuture<'a, Result<LlmResponse, LlmError>> {
+        let cloned = self.0.clone();
+        async move { cloned.send_conversation(conversation, function_descriptors, None).await }.boxed()
+    }
+
+    fn send_conversation_stream<'a>(
+        &'a self,
+        conversation: &'a IncompleteConversation,
+        function_descriptors: &'a [&'a FunctionDescriptor],
+        delta_sender: UnboundedSender<LlmDelta>,
+    ) -> BoxFuture<'a, Result<LlmResponse, LlmError>> {
+        let cloned = self.0.clone();
+        async move {
+            cloned
+                .send_conversation(conversation, function_descriptors, Some(delta_sender))
+                .await
+        }
+        .boxed()
+    }
+
+    fn probe(&self) -> BoxFuture<'_, Result<(), LlmError>> {
+        // 再生中は実際のバックエンドに触れない
+        match self.0.cassette.mode() {
+            CassetteMode::Record => self.0.llm.probe(),
+            CassetteMode::Replay => async { Ok(()) }.boxed(),
+        }
+    }
+}
+
+struct CassetteLlmInner {
+    llm: ArcLlm,
+    cassette: Cassette,
+}
+
+impl CassetteLlmInner {
+    async fn send_conversation(
+        &self,
+        conversation: &IncompleteConversation,
+        function_descriptors: &[&FunctionDescriptor],
+        delta_sender: Option<UnboundedSender<LlmDelta>>,
+    ) -> Result<LlmResponse, LlmError> {
+        let request = RecordedLlmRequest {
+            messages: conversation.llm_sending_messages().collect(),
+            functions: function_descriptors.iter().map(|d| d.name.as_str()).collect(),
+        };
+        let key = request.key()?;
+        match self.cassette.mode() {
+            CassetteMode::Record => {
+                let result = match delta_sender {
+                    Some(ds) => {
+                        self.llm
+                            .send_conversation_stream(conversation, function_descriptors, ds)
+                            .await
+                    }
+                    None => self.llm.send_conversation(conversation, function_descriptors).await,
+                };
+
+                let recorded = match &result {
+                    Ok(response) => RecordedLlmResult::Response(response.clone().into()),
+                    Err(e) => RecordedLlmResult::from_error(e),
+                };
+                self.cassette
+                    .record(&key, &request, &recorded)
+                    .map_err(LlmError::by_backend)?;
+                result
+            }
+            CassetteMode::Replay => {
+                let recorded: RecordedLlmResult = self.cassette.replay(&key).map_err(LlmError::by_backend)?;
+                let response: LlmResponse = match recorded {
+                    RecordedLlmResult::Response(response) => response.into(),
+                    RecordedLlmResult::Error { kind, error } => return Err(kind.into_error(error)),
+                };
+
+                // 断片までは記録していないので本文をまとめて流す
+                if let (Some(ds), LlmUpdate::Finished(r) | LlmUpdate::LengthCut(r)) = (&delta_sender, &response.update)
+                {
+                    ds.unbounded_send(LlmDelta::Text(r.text.clone())).ok();
+                }
+                Ok(response)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct RecordedLlmRequest<'a> {
+    messages: Vec<&'a Message>,
+    functions: Vec<&'a str>,
+}
+
+impl RecordedLlmRequest<'_> {
+    /// 同時に進む会話の記録が混ざらないよう、リクエストの内容から決める。
+    fn key(&self) -> Result<String, LlmError> {
+        let serialized = serde_json::to_vec(self).map_err(LlmError::by_format)?;
+        Ok(format!("send_conversation:{:x}", Sha256::digest(serialized)))
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+enum RecordedLlmResult {
+    Response(ScriptedResponse),
+    Error {
+        #[serde(default)]
+        kind: RecordedLlmErrorKind,
+        error: String,
+    },
+}
+
+impl RecordedLlmResult {
+    fn from_error(error: &LlmError) -> RecordedLlmResult {
+        let (kind, error) = match error {
+            LlmError::Communication(e) => (RecordedLlmErrorKind::Communication, e.to_string()),
+            LlmError::Backend(e) => (RecordedLlmErrorKind::Backend, e.to_string()),
+            LlmError::NoChoice => (RecordedLlmErrorKind::NoChoice, String::new()),
+            LlmError::ResponseFormat(e) => (RecordedLlmErrorKind::ResponseFormat, e.to_string()),
+            LlmError::ExpectationMismatch(message) => (RecordedLlmErrorKind::ExpectationMismatch, message.clone()),
+        };
+        RecordedLlmResult::Error { kind, error }
+    }
+}
+
+/// 再生時に同じ種類のエラーを返すための `LlmError` の種類。
+/// fallback はエラーの種類で次のモデルを試すかどうかを決める。
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum RecordedLlmErrorKind {
+    Communication,
+    #[default]
+    Backend,
+    NoChoice,
+    ResponseFormat,
+    ExpectationMismatch,
+}
+
+impl RecordedLlmErrorKind {
+    fn into_error(self, error: String) -> LlmError {
+        match self {
+            RecordedLlmErrorKind::Communication => LlmError::by_communication(error),
+            RecordedLlmErrorKind::Backend => LlmError::by_backend(error),
+            RecordedLlmErrorKind::NoChoice => LlmError::NoChoice,
+            RecordedLlmErrorKind::ResponseFormat => LlmError::by_format(error),
+            RecordedLlmErrorKind::ExpectationMismatch => LlmError::ExpectationMismatch(error),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CassetteLlm, CassetteLlmInner};
+    use crate::llm::scripted::{ScriptedLlm, ScriptedResponse, ScriptedUpdate};
+
+    use std::sync::Arc;
+
+    use lnb_common::cassette::{Cassette, CassetteMode};
+    use lnb_core::{
+        error::LlmError,
+        interface::llm::{ArcLlm, Llm, LlmAssistantResponse, LlmUpdate},
+        model::{
+            conversation::{Conversation, IncompleteConversation},
+            message::{Message, UserMessageContent},
+        },
+    };
+    use tempfile::TempDir;
+
+    fn open(directory: &TempDir, mode: CassetteMode, llm: ArcLlm) -> CassetteLlm {
+        let cassette = Cassette::open_in(mode, directory.path(), "llm-test").expect("should open");
+        CassetteLlm(Arc::new(CassetteLlmInner { llm, cassette }))
+    }
+
+    fn finished(text: &str) -> ScriptedResponse {
+        ScriptedResponse {
+            update: ScriptedUpdate::Finished(LlmAssistantResponse {
+                text: text.to_string(),
+                language: None,
+                sensitive: None,
+                actions: Default::default(),
+            }),
+            usage: Default::default(),
+            answered_model: None,
+            response_id: None,
+            reasoning_summary: None,
+        }
+    }
+
+    fn conversation(text: &str) -> IncompleteConversation {
+        let mut incomplete = IncompleteConversation::start(Conversation::new_now(None));
+        incomplete.extend_messages([Message::new_user(
+            [UserMessageContent::Text(text.to_string())],
+            None,
+            None,
+            false,
+        )]);
+        incomplete
+    }
+
+    async fn replied_text(llm: &CassetteLlm, conversation: &IncompleteConversation) -> String {
+        match llm
+            .send_conversation(conversation, &[])
+            .await
+            .expect("should reply")
+            .update
+        {
+            LlmUpdate::Finished(finished) => finished.text,
+            otherwise => panic!("should be finished: {otherwise:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn replays_by_request_content() {
+        let directory = TempDir::new().expect("should create directory");
+        let (first, second) = (conversation("first"), conversation("second"));
+
+        let scripted = ScriptedLlm::new(vec![finished("to first"), finished("to second")]);
+        let recording = open(&directory, CassetteMode::Record, Arc::new(scripted));
+        assert_eq!(replied_text(&recording, &first).await, "to first");
+        assert_eq!(replied_text(&recording, &second).await, "to second");
+
+        // 記録と異なる順に届いても、それぞれのリクエストに対する応答を返す
+        let replaying = open(&directory, CassetteMode::Replay, Arc::new(ScriptedLlm::new(vec![])));
+        assert_eq!(replied_text(&replaying, &second).await, "to second");
+        assert_eq!(replied_text(&replaying, &first).await, "to first");
+    }
+
+    #[tokio::test]
+    async fn replays_error_kind() {
+        let directory = TempDir::new().expect("should create directory");
+        let request = conversation("first");
+
+        // 応答を使い切った scripted は ExpectationMismatch を返す
+        let recording = open(&directory, CassetteMode::Record, Arc::new(ScriptedLlm::new(vec![])));
+        let recorded = recording.send_conversation(&request, &[]).await;
+        assert!(
+            matches!(recorded, Err(LlmError::ExpectationMismatch(_))),
+            "{recorded:?}"
+        );
+
+        let replaying = open(&directory, CassetteMode::Replay, Arc::new(ScriptedLlm::new(vec![])));
+        let replayed = replaying.send_conversation(&request, &[]).await;
+        assert!(
+            matches!(&replayed, Err(LlmError::ExpectationMismatch(m)) if m.starts_with("scripted responses exhausted")),
+            "{replayed:?}"
+        );
+    }
+}
@@ -1 +1,588 @@
+use crate::llm::{convert_json_schema, prompt_tool};
 
+use std::sync::Arc;
+
+use futures::{FutureExt, TryFutureExt, future::BoxFuture};
+use lnb_core::{
+    APP_USER_AGENT,
+    error::LlmError,
+    interface::{
+        function::FunctionDescriptor,
+        llm::{ArcLlm, Llm, LlmAssistantResponse, LlmResponse, LlmUpdate, LlmUsage},
+    },
+    model::{
+        conversation::IncompleteConversation,
+        message::{AssistantActions, Message, MessageToolCalling, UserMessageContent},
+    },
+};
+use reqwest::{Client, ClientBuilder, header::HeaderMap};
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use tracing::warn;
+
+const ANTHROPIC_VERSION: &str = "2023-06-01";
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ClaudeModelConfig {
+    pub endpoint: String,
+    pub token: String,
+    pub model: String,
+    pub tool: bool,
+    pub max_token: usize,
+}
+
+pub async fn create_claude_llm(config_value: Value) -> Result<ArcLlm, LlmError> {
+    let config: ClaudeModelConfig = serde_json::from_value(config_value).map_err(LlmError::by_format)?;
+    Ok(Arc::new(ClaudeBackend::new(config).await?))
+}
+
+/// Anthropic Messages API を利用したバックエンド。
+#[derive(Debug, Clone)]
+pub struct ClaudeBackend(Arc<ClaudeBackendInner>);
+
+impl ClaudeBackend {
+    pub async fn new(config: ClaudeModelConfig) -> Result<ClaudeBackend, LlmError> {
+        let client = {
+            let mut headers = HeaderMap::new();
+            headers.insert("x-api-key", config.token.parse().map_err(LlmError::by_format)?);
+            headers.insert(
+                "anthropic-version",
+                ANTHROPIC_VERSION.parse().expect("should pass header"),
+            );
+
+            ClientBuilder::new()
+                .user_agent(APP_USER_AGENT)
+                .default_headers(headers)
+                .build()
+                .map_err(LlmError::by_communication)?
+        };
+
+        Ok(ClaudeBackend(Arc::new(ClaudeBackendInner {
+            client,
+            api_root: config.endpoint,
+            model: config.model,
+            enable_tool: config.tool,
+            max_token: config.max_token,
+        })))
+    }
+}
+
+impl Llm for ClaudeBackend {
+    fn send_conversation<'a>(
+        &'a self,
+        conversation: &'a IncompleteConversation,
+        function_descriptors: &'a [&'a FunctionDescriptor],
+    ) -> BoxFuture<'a, Result<LlmResponse, LlmError>> {
+        let cloned = self.0.clone();
+        async move { cloned.send_conversation(conversation, function_descriptors).await }.boxed()
+    }
+
+    fn probe(&self) -> BoxFuture<'_, Result<(), LlmError>> {
+        let cloned = self.0.clone();
+        async move { cloned.probe().await }.boxed()
+    }
+}
+
+#[derive(Debug)]
+struct ClaudeBackendInner {
+    client: Client,
+    api_root: String,
+    model: String,
+    enable_tool: bool,
+    max_token: usize,
+}
+
+impl ClaudeBackendInner {
+    async fn send_conversation(
+        &self,
+        conversation: &IncompleteConversation,
+        function_descriptors: &[&FunctionDescriptor],
+    ) -> Result<LlmResponse, LlmError> {
+        let (system, messages) = if self.enable_tool {
+            transform_messages(conversation.llm_sending_messages())?
+        } else {
+            let emulated =
+                prompt_tool::emulate_messages(conversation.llm_sending_messages(), function_descriptors, true);
+            transform_messages(emulated.iter().map(|m| m.as_ref()))?
+        };
+        let tools = if self.enable_tool {
+            transform_tools(function_descriptors)
+        } else {
+            vec![]
+        };
+
+        let request = ClaudeRequest {
+            model: &self.model,
+            max_tokens: self.max_token,
+            system,
+            messages,
+            tools,
+        };
+        let response_value = self.call_api("/messages", &request).await?;
+        let response: ClaudeResponse = serde_json::from_value(response_value).map_err(LlmError::by_format)?;
+        let usage = response.usage.as_ref().map(transform_usage).unwrap_or_default();
+        let update = transform_response(response)?;
+        let response = LlmResponse::new(update, usage);
+        if self.enable_tool {
+            Ok(response)
+        } else {
+            Ok(prompt_tool::extract_tool_calls(response, None))
+        }
+    }
+
+    async fn probe(&self) -> Result<(), LlmError> {
+        let api_url = format!("{}/models/{}", self.api_root, self.model);
+        let response = self
+            .client
+            .get(api_url)
+            .send()
+            .map_err(LlmError::by_communication)
+            .await?;
+        if !response.status().is_success() {
+            let response_body: Value = response.json().map_err(LlmError::by_format).await?;
+            let message = match response_body.pointer("/error/message") {
+                Some(Value::String(s)) => s.as_str(),
+                _ => "unknown error",
+            };
+            return Err(LlmError::Backend(message.to_string().into()));
+        }
+        Ok(())
+    }
+
+    async fn call_api<T: Serialize>(&self, endpoint: &str, body: &T) -> Result<Value, LlmError> {
+        let api_url = format!("{}{endpoint}", self.api_root);
+        let response = self
+            .client
+            .post(api_url)
+            .json(body)
+            .send()
+            .map_err(LlmError::by_communication)
+            .await?;
+
+        let response_status = response.status();
+        let response_body: Value = response.json().map_err(LlmError::by_format).await?;
+        if response_status.is_success() {
+            Ok(response_body)
+        } else {
+            let message = match response_body.pointer("/error/message") {
+                Some(Value::String(s)) => s.as_str(),
+                _ => "unknown error",
+            };
+            Err(LlmError::Backend(message.to_string().into()))
+        }
+    }
+}
+
+fn transform_tools(descriptors: &[&FunctionDescriptor]) -> Vec<Value> {
+    descriptors
+        .iter()
+        .map(|d| {
+            json!({
+                "name": d.name,
+                "description": d.description,
+                "input_schema": convert_json_schema(&d.parameters),
+            })
+        })
+        .collect()
+}
+
+/// system prompt と messages に分解する。
+/// Messages API は同じ role が連続すると content block を結合した扱いになるので、こちらで明示的に結合しておく。
+fn transform_messages<'a>(
+    messages: impl Iterator<Item = &'a Message>,
+) -> Result<(Option<String>, Vec<ClaudeMessage>), LlmError> {
+    let mut system_texts = vec![];
+    let mut claude_messages: Vec<ClaudeMessage> = vec![];
+    for message in messages {
+        let (role, blocks) = match message {
+            Message::System(system_message) => {
+                system_texts.push(system_message.0.as_str());
+                continue;
+            }
+            Message::User(user_message) => {
+                let blocks = user_message
+                    .contents
+                    .iter()
+                    .map(|umc| match umc {
+                        UserMessageContent::Text(text) => json!({
+                            "type": "text",
+                            "text": text,
+                        }),
+                        UserMessageContent::ImageUrl(url) => json!({
+                            "type": "image",
+                            "source": {
+                                "type": "url",
+                                "url": url.to_string(),
+                            },
+                        }),
+                    })
+                    .collect();
+                (ClaudeRole::User, blocks)
+            }
+            Message::Assistant(assistant_message) => {
+                // 空の text block は拒否される
+                if assistant_message.text.is_empty() {
+                    continue;
+                }
+                let blocks = vec![json!({
+                    "type": "text",
+                    "text": assistant_message.text,
+                })];
+                (ClaudeRole::Assistant, blocks)
+            }
+            Message::FunctionCalls(function_calls_message) => {
+                let blocks = function_calls_message
+                    .0
+                    .iter()
+                    .map(|c| {
+                        json!({
+                            "type": "tool_use",
+                            "id": c.id,
+                            "name": c.name,
+                            "input": c.arguments,
+                        })
+                    })
+                    .collect();
+                (ClaudeRole::Assistant, blocks)
+            }
+            Message::FunctionResponse(function_response_message) => {
+                let result_str =
+                    serde_json::to_string(&function_response_message.result).map_err(LlmError::by_format)?;
+                let blocks = vec![json!({
+                    "type": "tool_result",
+                    "tool_use_id": function_response_message.id,
+                    "content": result_str,
+                })];
+                (ClaudeRole::User, blocks)
+            }
+        };
+
+        match claude_messages.last_mut() {
+            Some(last) if last.role == role => last.content.extend(blocks),
+            _ => claude_messages.push(ClaudeMessage { role, content: blocks }),
+        }
+    }
+
+    let system = (!system_texts.is_empty()).then(|| system_texts.join("\n\n"));
+    Ok((system, claude_messages))
+}
+
+fn transform_usage(usage: &ClaudeUsage) -> LlmUsage {
+    // input_tokens にはキャッシュ分が含まれないので足しておく
+    LlmUsage {
+        prompt_tokens: usage.input_tokens + usage.cache_read_input_tokens + usage.cache_creation_input_tokens,
+        completion_tokens: usage.output_tokens,
+        reasoning_tokens: 0,
+        cached_tokens: usage.cache_read_input_tokens,
+    }
+}
+
+fn transform_response(response: ClaudeResponse) -> Result<LlmUpdate, LlmError> {
+    let mut texts = vec![];
+    let mut tool_uses = vec![];
+    for block in response.content {
+        match block {
+            ClaudeContentBlock::Text { text } => texts.push(text),
+            ClaudeContentBlock::ToolUse { id, name, input } => tool_uses.push(MessageToolCalling {
+                id,
+                name,
+                arguments: input,
+            }),
+            ClaudeContentBlock::Unknown => (),
+        }
+    }
+
+    match response.stop_reason {
+        // end_turn, stop_sequence
+        Some(ClaudeStopReason::EndTurn | ClaudeStopReason::StopSequence) => {
+            if texts.is_empty() {
+                warn!("no text block detected");
+                return Err(LlmError::ExpectationMismatch("no content value".to_string()));
+            }
+            Ok(LlmUpdate::Finished(LlmAssistantResponse {
+                text: texts.concat(),
+                language: None,
+                sensitive: None,
+                actions: AssistantActions::default(),
+            }))
+        }
+
+        // max_tokens
+        Some(ClaudeStopReason::MaxTokens) => {
+            // max_tokens で tool_use がカットされることがあるので、明示的に拒否する
+            if !tool_uses.is_empty() {
+                return Err(LlmError::ExpectationMismatch(
+                    "tool calling was cut by length".to_string(),
+                ));
+            }
+            Ok(LlmUpdate::LengthCut(LlmAssistantResponse {
+                text: texts.concat(),
+                language: None,
+                sensitive: None,
+                actions: AssistantActions::default(),
+            }))
+        }
+
+        // tool_use
+        Some(ClaudeStopReason::ToolUse) => {
+            if tool_uses.is_empty() {
+                warn!("no tool_use block detected");
+                return Err(LlmError::ExpectationMismatch("no tool_use block".to_string()));
+            }
+            Ok(LlmUpdate::ToolCalling(tool_uses))
+        }
+
+        // refusal
+        Some(ClaudeStopReason::Refusal) => Ok(LlmUpdate::Filtered),
+
+        // other invalid values
+        Some(ClaudeStopReason::Unknown) => Err(LlmError::ExpectationMismatch("unknown stop reason".to_string())),
+        None => Err(LlmError::NoChoice),
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct ClaudeRequest<'a> {
+    model: &'a str,
+    max_tokens: usize,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    system: Option<String>,
+
+    messages: Vec<ClaudeMessage>,
+
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    tools: Vec<Value>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum ClaudeRole {
+    User,
+    Assistant,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct ClaudeMessage {
+    role: ClaudeRole,
+    content: Vec<Value>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct ClaudeResponse {
+    content: Vec<ClaudeContentBlock>,
+    stop_reason: Option<ClaudeStopReason>,
+    usage: Option<ClaudeUsage>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct ClaudeUsage {
+    input_tokens: u64,
+    output_tokens: u64,
+
+    #[serde(default)]
+    cache_read_input_tokens: u64,
+
+    #[serde(default)]
+    cache_creation_input_tokens: u64,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "snake_case", tag = "type")]
+enum ClaudeContentBlock {
+    Text {
+        text: String,
+    },
+    ToolUse {
+        id: String,
+        name: String,
+        input: Value,
+    },
+
+    #[serde(other)]
+    Unknown,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum ClaudeStopReason {
+    EndTurn,
+    StopSequence,
+    MaxTokens,
+    ToolUse,
+    Refusal,
+
+    #[serde(other)]
+    Unknown,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ClaudeBackend, ClaudeModelConfig};
+    use crate::mock_server::{MockResponse, serve};
+
+    use lnb_core::{
+        error::LlmError,
+        interface::{
+            function::FunctionDescriptor,
+            llm::{Llm, LlmUpdate},
+        },
+        model::{
+            conversation::{Conversation, IncompleteConversation},
+            message::{Message, UserMessageContent},
+            schema::DescribedSchema,
+        },
+    };
+    use serde_json::json;
+
+    async fn create_backend(endpoint: String, tool: bool) -> ClaudeBackend {
+        ClaudeBackend::new(ClaudeModelConfig {
+            endpoint,
+            token: "test-token".to_string(),
+            model: "claude-test".to_string(),
+            tool,
+            max_token: 256,
+        })
+        .await
+        .expect("should create backend")
+    }
+
+    fn create_conversation() -> IncompleteConversation {
+        let mut incomplete = IncompleteConversation::start(Conversation::new_now(Some(Message::new_system("system"))));
+        incomplete.extend_messages([Message::new_user(
+            [UserMessageContent::Text("hello".to_string())],
+            None,
+            None,
+            false,
+        )]);
+        incomplete
+    }
+
+    #[tokio::test]
+    async fn claude_finishes_with_text() {
+        let (endpoint, handle) = serve(vec![MockResponse::json(json!({
+            "content": [{ "type": "text", "text": "hi" }],
+            "stop_reason": "end_turn",
+        }))])
+        .await;
+        let backend = create_backend(endpoint, true).await;
+
+        let update = backend
+            .send_conversation(&create_conversation(), &[])
+            .await
+            .expect("should succeed")
+            .update;
+        let LlmUpdate::Finished(response) = update else {
+            panic!("should be finished: {update:?}");
+        };
+        assert_eq!(response.text, "hi");
+
+        let request = handle.await.expect("should receive request")[0].json();
+        assert_eq!(request["system"], "system");
+        assert_eq!(request["messages"][0]["role"], "user");
+        assert_eq!(request["messages"][0]["content"][0]["text"], "hello");
+        assert!(request.get("tools").is_none());
+    }
+
+    #[tokio::test]
+    async fn claude_requests_tool_calling() {
+        let (endpoint, handle) = serve(vec![MockResponse::json(json!({
+            "content": [
+                { "type": "text", "text": "let me check" },
+                { "type": "tool_use", "id": "toolu_01", "name": "local_info", "input": {} },
+            ],
+            "stop_reason": "tool_use",
+        }))])
+        .await;
+        let backend = create_backend(endpoint, true).await;
+        let descriptor = FunctionDescriptor {
+            name: "local_info".to_string(),
+            description: "local info".to_string(),
+            parameters: DescribedSchema::object("parameters", "引数", vec![]),
+        };
+
+        let update = backend
+            .send_conversation(&create_conversation(), &[&descriptor])
+            .await
+            .expect("should succeed")
+            .update;
+        let LlmUpdate::ToolCalling(calls) = update else {
+            panic!("should be tool calling: {update:?}");
+        };
+        assert_eq!(calls.len(), 1);
+        assert_eq!(calls[0].id, "toolu_01");
+        assert_eq!(calls[0].name, "local_info");
+
+        let request = handle.await.expect("should receive request")[0].json();
+        assert_eq!(request["tools"][0]["name"], "local_info");
+        assert_eq!(request["tools"][0]["input_schema"]["type"], "object");
+    }
+
+    #[tokio::test]
+    async fn claude_emulates_tool_calling_without_native_tools() {
+        let (endpoint, handle) = serve(vec![MockResponse::json(json!({
+            "content": [{ "type": "text", "text": "```tool_call\n{\"name\": \"local_info\"}\n```" }],
+            "stop_reason": "end_turn",
+        }))])
+        .await;
+        let backend = create_backend(endpoint, false).await;
+        let descriptor = FunctionDescriptor {
+            name: "local_info".to_string(),
+            description: "local info".to_string(),
+            parameters: DescribedSchema::object("parameters", "引数", vec![]),
+        };
+
+        let update = backend
+            .send_conversation(&create_conversation(), &[&descriptor])
+            .await
+            .expect("should succeed")
+            .update;
+        let LlmUpdate::ToolCalling(calls) = update else {
+            panic!("should be tool calling: {update:?}");
+        };
+        assert_eq!(calls.len(), 1);
+        assert_eq!(calls[0].name, "local_info");
+
+        let request = handle.await.expect("should receive request")[0].json();
+        assert!(request.get("tools").is_none());
+        let system = request["system"].as_str().expect("should have system prompt");
+        assert!(system.contains("- local_info: local info"), "{system}");
+    }
+
+    #[tokio::test]
+    async fn claude_rejects_tool_use_without_blocks() {
+        let (endpoint, _handle) = serve(vec![MockResponse::json(json!({
+            "content": [{ "type": "text", "text": "let me check" }],
+            "stop_reason": "tool_use",
+        }))])
+        .await;
+        let backend = create_backend(endpoint, true).await;
+
+        let result = backend.send_conversation(&create_conversation(), &[]).await;
+        assert!(matches!(result, Err(LlmError::ExpectationMismatch(_))), "{result:?}");
+    }
+
+    #[tokio::test]
+    async fn claude_probe_looks_up_model() {
+        let (endpoint, handle) = serve(vec![
+            MockResponse::json(json!({ "type": "model", "id": "claude-test" })),
+            MockResponse::json(json!({
+                "type": "error",
+                "error": { "type": "not_found_error", "message": "model: claude-test" },
+            }))
+            .with_status(404),
+        ])
+        .await;
+        let backend = create_backend(endpoint, true).await;
+
+        backend.probe().await.expect("should succeed");
+        let result = backend.probe().await;
+        assert!(
+            matches!(&result, Err(LlmError::Backend(e)) if e.to_string() == "model: claude-test"),
+            "{result:?}"
+        );
+
+        let requests = handle.await.expect("should receive request");
+        assert_eq!(requests[0].target, "/models/claude-test");
+    }
+}
@@ -0,0 +1,223 @@
+use crate::natsuki::LlmCache;
+
+use std::sync::Arc;
+
+use futures::{FutureExt, channel::mpsc::UnboundedSender, future::BoxFuture};
+use lnb_common::config::llm::ConfigLlmBackend;
+use lnb_core::{
+    error::LlmError,
+    interface::{
+        function::FunctionDescriptor,
+        llm::{ArcLlm, Llm, LlmDelta, LlmResponse, LlmUpdate, LlmUsage},
+    },
+    model::conversation::IncompleteConversation,
+};
+use serde::Deserialize;
+use serde_json::Value;
+use tracing::{info, warn};
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct FallbackModelConfig {
+    pub models: Vec<String>,
+}
+
+pub async fn create_fallback_llm(config_value: Value, llm_cache: LlmCache) -> Result<ArcLlm, LlmError> {
+    let config: FallbackModelConfig = serde_json::from_value(config_value).map_err(LlmError::by_format)?;
+    if config.models.is_empty() {
+        return Err(LlmError::ExpectationMismatch("no fallback model specified".to_string()));
+    }
+
+    // 入れ子にすると循環し得るので禁止する
+    for model in &config.models {
+        match llm_cache.backend_of(model) {
+            Some(ConfigLlmBackend::Fallback) => {
+                let message = format!("fallback model {model} cannot be nested");
+                return Err(LlmError::ExpectationMismatch(message));
+            }
+            Some(_) => (),
+            None => {
+                let message = format!("undefined fallback model: {model}");
+                return Err(LlmError::ExpectationMismatch(message));
+            }
+        }
+    }
+
+    Ok(Arc::new(FallbackLlm(Arc::new(FallbackLlmInner {
+        llm_cache,
+        models: config.models,
+    }))))
+}
+
+/// 複数のモデルを順に試すバックエンド。
+/// 通信エラー・バックエンドエラー・フィルタリングの場合は次のモデルで再試行する。
+#[derive(Clone)]
+pub struct FallbackLlm(Arc<FallbackLlmInner>);
+
+impl Llm for FallbackLlm {
+    fn send_conversation<'a>(
+        &'a self,
+        conversation: &'a IncompleteConversation,
+        function_descriptors: &'a [&'a FunctionDescriptor],
+    ) -> BoxFuture<'a, Result<LlmResponse, LlmError>> {
+        let cloned = self.0.clone();
+        async move { cloned.send_conversation(conversation, function_descriptors, None).await }.boxed()
+    }
+
+    fn send_conversation_stream<'a>(
+        &'a self,
+        conversation: &'a IncompleteConversation,
+        function_descriptors: &'a [&'a FunctionDescriptor],
+        delta_sender: UnboundedSender<LlmDelta>,
+    ) -> BoxFuture<'a, Result<LlmResponse, LlmError>> {
+        let cloned = self.0.clone();
+        async move {
+            cloned
+                .send_conversation(conversation, function_descriptors, Some(delta_sender))
+                .await
+        }
+        .boxed()
+    }
+}
+
+struct FallbackLlmInner {
+    llm_cache: LlmCache,
+    models: Vec<String>,
+}
+
+impl FallbackLlmInner {
+    async fn send_conversation(
+        &self,
+        conversation: &IncompleteConversation,
+        function_descriptors: &[&FunctionDescriptor],
+        delta_sender: Option<UnboundedSender<LlmDelta>>,
+    ) -> Result<LlmResponse, LlmError> {
+        let mut last_result = Err(LlmError::Backend("no fallback model available".into()));
+        let mut attempted = false;
+
+        // フィルタされた分も消費はしているので、試したモデルごとに記録する
+        let mut discarded_usages: Vec<(String, LlmUsage)> = vec![];
+        for model in &self.models {
+            let llm = match self.llm_cache.get_by_name(model).await {
+                Ok(llm) => llm,
+                Err(e) => {
+                    warn!("fallback model {model} unavailable: {e}");
+                    continue;
+                }
+            };
+
+            let result = match &delta_sender {
+                Some(ds) => {
+                    // 前のモデルが途中まで生成していた分は捨てる
+                    if attempted {
+                        ds.unbounded_send(LlmDelta::Restart).ok();
+                    }
+                    llm.send_conversation_stream(conversation, function_descriptors, ds.clone())
+                        .await
+                }
+                None => llm.send_conversation(conversation, function_descriptors).await,
+            };
+            attempted = true;
+
+            let response = match result {
+                Ok(response) => {
+                    let mut all_discarded_usages = discarded_usages.clone();
+                    all_discarded_usages.extend(response.discarded_usages);
+                    LlmResponse {
+                        answered_model: Some(response.answered_model.unwrap_or_else(|| model.clone())),
+                        discarded_usages: all_discarded_usages,
+                        ..response
+                    }
+                }
+                Err(e @ (LlmError::Communication(_) | LlmError::Backend(_))) => {
+                    warn!("fallback model {model} failed, trying next: {e}");
+                    last_result = Err(e);
+                    continue;
+                }
+                Err(e) => return Err(e),
+            };
+            if matches!(response.update, LlmUpdate::Filtered) {
+                warn!("fallback model {model} filtered response, trying next");
+                discarded_usages.push((model.clone(), response.usage));
+                last_result = Ok(response);
+                continue;
+            }
+
+            info!("fallback model {model} answered");
+            return Ok(response);
+        }
+
+        last_result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::natsuki::LlmCache;
+
+    use std::collections::HashMap;
+
+    use lnb_common::config::llm::{ConfigLlm, ConfigLlmBackend, ConfigLlmModel};
+    use lnb_core::{
+        interface::llm::{LlmUpdate, LlmUsage},
+        model::{
+            conversation::{Conversation, IncompleteConversation},
+            message::{Message, UserMessageContent},
+        },
+    };
+    use serde_json::{Value, json};
+
+    fn model(backend: ConfigLlmBackend, config: Value) -> ConfigLlmModel {
+        ConfigLlmModel {
+            backend,
+            config,
+            price: None,
+            context: None,
+            limits: None,
+        }
+    }
+
+    fn scripted(fixture: &str) -> ConfigLlmModel {
+        let fixture_path = format!("{}/fixtures/scripted/{fixture}.json", env!("CARGO_MANIFEST_DIR"));
+        model(ConfigLlmBackend::Scripted, json!({ "fixture": fixture_path }))
+    }
+
+    #[tokio::test]
+    async fn fallback_attributes_usage_to_each_model() {
+        let config = ConfigLlm {
+            default: "chain".to_string(),
+            models: HashMap::from([
+                ("first".to_string(), scripted("filtered")),
+                ("second".to_string(), scripted("finished")),
+                (
+                    "chain".to_string(),
+                    model(ConfigLlmBackend::Fallback, json!({ "models": ["first", "second"] })),
+                ),
+            ]),
+            initialization: Default::default(),
+            limits: Default::default(),
+            role_limits: Default::default(),
+            routes: vec![],
+        };
+        let llm_cache = LlmCache::new(&config).expect("should build LlmCache");
+        let llm = llm_cache.get_by_name("chain").await.expect("should create");
+
+        let mut conversation = IncompleteConversation::start(Conversation::new_now(None));
+        conversation.extend_messages([Message::new_user(
+            [UserMessageContent::Text("こんにちは".to_string())],
+            None,
+            None,
+            false,
+        )]);
+        let response = llm.send_conversation(&conversation, &[]).await.expect("should answer");
+
+        assert!(matches!(response.update, LlmUpdate::Finished(_)));
+        assert_eq!(response.answered_model.as_deref(), Some("second"));
+        assert_eq!(response.usage.prompt_tokens, 120);
+        let filtered_usage = LlmUsage {
+            prompt_tokens: 100,
+            completion_tokens: 5,
+            ..Default::default()
+        };
+        assert_eq!(response.discarded_usages, vec![("first".to_string(), filtered_usage)]);
+    }
+}
@@ -5,7 +5,7 @@ pub use chat_completion::ChatCompletionBackend;
 pub use responses::ResponsesBackend;
 use serde_json::Value;
 
-use crate::llm::{ASSISTANT_RESPONSE_SCHEMA, convert_json_schema};
+use crate::llm::{ASSISTANT_RESPONSE_SCHEMA, assistant_response_schema, convert_json_schema};
 
 use std::sync::{Arc, LazyLock};
 
@@ -14,16 +14,55 @@ use async_openai::{
     config::OpenAIConfig,
     types::responses::{ReasoningEffort, ResponseFormatJsonSchema},
 };
-use lnb_core::{APP_USER_AGENT, error::LlmError, interface::llm::ArcLlm};
+use lnb_core::{
+    APP_USER_AGENT,
+    error::LlmError,
+    interface::llm::{ArcLlm, LlmAssistantResponse},
+    model::{
+        conversation::IncompleteConversation,
+        message::AssistantActions,
+        schema::{DescribedSchema, DescribedSchemaType, StringFormat},
+    },
+};
 use serde::Deserialize;
+use tracing::warn;
 
 static RESPONSE_JSON_SCHEMA: LazyLock<ResponseFormatJsonSchema> = LazyLock::new(|| ResponseFormatJsonSchema {
     name: "response".into(),
     description: Some("response from assistant".into()),
     schema: Some(convert_json_schema(&ASSISTANT_RESPONSE_SCHEMA)),
-    strict: Some(true),
+    strict: Some(supports_strict_mode(&ASSISTANT_RESPONSE_SCHEMA)),
 });
 
+/// `conversation` のペルソナに合わせた応答スキーマ。
+fn response_json_schema(conversation: &IncompleteConversation) -> ResponseFormatJsonSchema {
+    let Some(text_description) = conversation.response_description() else {
+        return RESPONSE_JSON_SCHEMA.clone();
+    };
+    let schema = assistant_response_schema(text_description);
+    ResponseFormatJsonSchema {
+        schema: Some(convert_json_schema(&schema)),
+        strict: Some(supports_strict_mode(&schema)),
+        ..RESPONSE_JSON_SCHEMA.clone()
+    }
+}
+
+/// structured output の応答を解釈する。解釈できなければそのまま本文として扱う。
+fn parse_structured_response(content: String) -> LlmAssistantResponse {
+    match serde_json::from_str(&content) {
+        Ok(response) => response,
+        Err(e) => {
+            warn!("failed to parse structured response: {e}");
+            LlmAssistantResponse {
+                text: content,
+                language: None,
+                sensitive: None,
+                actions: AssistantActions::default(),
+            }
+        }
+    }
+}
+
 #[derive(Debug, Clone, Deserialize)]
 pub struct OpenaiModelConfig {
     pub api: OpenaiModelConfigApi,
@@ -34,6 +73,10 @@ pub struct OpenaiModelConfig {
     pub structured: bool,
     pub max_token: usize,
     pub reasoning: Option<ReasoningEffort>,
+
+    /// Responses API で応答をサーバー側に保存させ、 `previous_response_id` で連鎖させる。
+    #[serde(default)]
+    pub store: bool,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
@@ -43,11 +86,11 @@ pub enum OpenaiModelConfigApi {
     Responses,
 }
 
-pub async fn create_openai_llm(config_value: Value) -> Result<ArcLlm, LlmError> {
+pub async fn create_openai_llm(name: &str, config_value: Value) -> Result<ArcLlm, LlmError> {
     let config: OpenaiModelConfig = serde_json::from_value(config_value).map_err(LlmError::by_format)?;
     match config.api {
         OpenaiModelConfigApi::ChatCompletion => Ok(Arc::new(ChatCompletionBackend::new(config).await?)),
-        OpenaiModelConfigApi::Responses => Ok(Arc::new(ResponsesBackend::new(config).await?)),
+        OpenaiModelConfigApi::Responses => Ok(Arc::new(ResponsesBackend::new(name, config).await?)),
     }
 }
 
@@ -61,3 +104,18 @@ async fn create_openai_client(token: &str, endpoint: &str) -> Result<Client<Open
     let client = Client::with_config(config).with_http_client(http_client);
     Ok(client)
 }
+
+/// strict mode で表現できるスキーマかどうか。
+/// strict mode では全フィールドが必須で、 `default` と一部の `format` は使えないので、含む場合は strict mode を諦める。
+fn supports_strict_mode(schema: &DescribedSchema) -> bool {
+    if schema.omittable || schema.default.is_some() || schema.constraints.format == Some(StringFormat::Uri) {
+        return false;
+    }
+    match &schema.field_type {
+        DescribedSchemaType::Array(item_schema) => supports_strict_mode(item_schema),
+        DescribedSchemaType::Object(fields) | DescribedSchemaType::Union(fields) => {
+            fields.iter().all(supports_strict_mode)
+        }
+        _ => true,
+    }
+}
@@ -1,6 +1,9 @@
 use crate::llm::{
     convert_json_schema,
-    openai::{OpenaiModelConfig, RESPONSE_JSON_SCHEMA, create_openai_client},
+    openai::{
+        OpenaiModelConfig, create_openai_client, parse_structured_response, response_json_schema, supports_strict_mode,
+    },
+    prompt_tool::{self, TextDeltaFilter},
 };
 
 use std::sync::Arc;
@@ -8,26 +11,30 @@ use std::sync::Arc;
 use async_openai::{
     Client,
     config::OpenAIConfig,
-    types::chat::{
-        ChatChoice, ChatCompletionMessageToolCall, ChatCompletionMessageToolCalls,
-        ChatCompletionRequestAssistantMessage, ChatCompletionRequestMessage,
-        ChatCompletionRequestMessageContentPartImage, ChatCompletionRequestMessageContentPartText,
-        ChatCompletionRequestToolMessage, ChatCompletionRequestToolMessageContent, ChatCompletionRequestUserMessage,
-        ChatCompletionRequestUserMessageContent, ChatCompletionRequestUserMessageContentPart, ChatCompletionTool,
-        ChatCompletionTools, CreateChatCompletionRequest, FinishReason, FunctionCall, FunctionObject, ImageUrl,
-        ReasoningEffort, ResponseFormat,
+    types::{
+        chat::{
+            ChatChoice, ChatCompletionMessageToolCall, ChatCompletionMessageToolCalls,
+            ChatCompletionRequestAssistantMessage, ChatCompletionRequestMessage,
+            ChatCompletionRequestMessageContentPartImage, ChatCompletionRequestMessageContentPartText,
+            ChatCompletionRequestToolMessage, ChatCompletionRequestToolMessageContent,
+            ChatCompletionRequestUserMessage, ChatCompletionRequestUserMessageContent,
+            ChatCompletionRequestUserMessageContentPart, ChatCompletionStreamOptions, ChatCompletionTool,
+            ChatCompletionTools, CompletionUsage, CreateChatCompletionRequest, FinishReason, FunctionCall,
+            FunctionObject, ImageUrl, ReasoningEffort, ResponseFormat,
+        },
+        responses::ResponseFormatJsonSchema,
     },
 };
-use futures::{FutureExt, TryFutureExt, future::BoxFuture};
+use futures::{FutureExt, TryFutureExt, TryStreamExt, channel::mpsc::UnboundedSender, future::BoxFuture};
 use lnb_core::{
     error::LlmError,
     interface::{
         function::FunctionDescriptor,
-        llm::{Llm, LlmAssistantResponse, LlmUpdate},
+        llm::{Llm, LlmAssistantResponse, LlmDelta, LlmResponse, LlmUpdate, LlmUsage},
     },
     model::{
         conversation::IncompleteConversation,
-        message::{Message, MessageToolCalling, UserMessageContent},
+        message::{AssistantActions, Message, MessageToolCalling, UserMessageContent},
     },
 };
 use tracing::warn;
@@ -55,10 +62,30 @@ impl Llm for ChatCompletionBackend {
         &'a self,
         conversation: &'a IncompleteConversation,
         function_descriptors: &'a [&'a FunctionDescriptor],
-    ) -> BoxFuture<'a, Result<LlmUpdate, LlmError>> {
+    ) -> BoxFuture<'a, Result<LlmResponse, LlmError>> {
         let cloned = self.0.clone();
         async move { cloned.send_conversation(conversation, function_descriptors).await }.boxed()
     }
+
+    fn send_conversation_stream<'a>(
+        &'a self,
+        conversation: &'a IncompleteConversation,
+        function_descriptors: &'a [&'a FunctionDescriptor],
+        delta_sender: UnboundedSender<LlmDelta>,
+    ) -> BoxFuture<'a, Result<LlmResponse, LlmError>> {
+        let cloned = self.0.clone();
+        async move {
+            cloned
+                .send_conversation_stream(conversation, function_descriptors, delta_sender)
+                .await
+        }
+        .boxed()
+    }
+
+    fn probe(&self) -> BoxFuture<'_, Result<(), LlmError>> {
+        let cloned = self.0.clone();
+        async move { cloned.probe().await }.boxed()
+    }
 }
 
 #[derive(Debug)]
@@ -72,16 +99,48 @@ struct ChatCompletionBackendInner {
 }
 
 impl ChatCompletionBackendInner {
+    async fn probe(&self) -> Result<(), LlmError> {
+        self.client
+            .models()
+            .retrieve(&self.model)
+            .map_err(LlmError::by_backend)
+            .await?;
+        Ok(())
+    }
+
     async fn send_conversation(
         &self,
         conversation: &IncompleteConversation,
         function_descriptors: &[&FunctionDescriptor],
-    ) -> Result<LlmUpdate, LlmError> {
-        let messages: Result<_, _> = conversation.llm_sending_messages().map(transform_message).collect();
-        if self.structured {
-            self.send_conversation_structured(messages?, function_descriptors).await
+    ) -> Result<LlmResponse, LlmError> {
+        let messages = self.transform_messages(conversation, function_descriptors)?;
+        let response = if self.structured {
+            self.send_conversation_structured(messages, function_descriptors, response_json_schema(conversation))
+                .await?
+        } else {
+            self.send_conversation_normal(messages, function_descriptors).await?
+        };
+
+        if self.enable_tool {
+            Ok(response)
+        } else {
+            Ok(prompt_tool::extract_tool_calls(response, None))
+        }
+    }
+
+    /// tool calling が無効な場合はツールをプロンプトで表現する。
+    fn transform_messages(
+        &self,
+        conversation: &IncompleteConversation,
+        function_descriptors: &[&FunctionDescriptor],
+    ) -> Result<Vec<ChatCompletionRequestMessage>, LlmError> {
+        if self.enable_tool {
+            conversation.llm_sending_messages().map(transform_message).collect()
         } else {
-            self.send_conversation_normal(messages?, function_descriptors).await
+            prompt_tool::emulate_messages(conversation.llm_sending_messages(), function_descriptors, true)
+                .iter()
+                .map(|m| transform_message(m))
+                .collect()
         }
     }
 
@@ -89,7 +148,7 @@ impl ChatCompletionBackendInner {
         &self,
         messages: Vec<ChatCompletionRequestMessage>,
         function_descriptors: &[&FunctionDescriptor],
-    ) -> Result<LlmUpdate, LlmError> {
+    ) -> Result<LlmResponse, LlmError> {
         let request = CreateChatCompletionRequest {
             model: self.model.clone(),
             messages,
@@ -100,39 +159,146 @@ impl ChatCompletionBackendInner {
         };
 
         let openai_response = self.client.chat().create(request).map_err(LlmError::by_backend).await?;
+        let usage = transform_usage(openai_response.usage);
         let Some(first_choice) = openai_response.choices.into_iter().next() else {
             return Err(LlmError::NoChoice);
         };
 
-        transform_choice(first_choice)
+        let update = transform_choice(first_choice, self.structured)?;
+        Ok(LlmResponse::new(update, usage))
     }
 
     async fn send_conversation_structured(
         &self,
         messages: Vec<ChatCompletionRequestMessage>,
         function_descriptors: &[&FunctionDescriptor],
-    ) -> Result<LlmUpdate, LlmError> {
+        json_schema: ResponseFormatJsonSchema,
+    ) -> Result<LlmResponse, LlmError> {
         let request = CreateChatCompletionRequest {
             model: self.model.clone(),
             messages,
             tools: self.enable_tool.then(|| transform_tools(function_descriptors)),
-            response_format: Some(ResponseFormat::JsonSchema {
-                json_schema: RESPONSE_JSON_SCHEMA.clone(),
-            }),
+            response_format: Some(ResponseFormat::JsonSchema { json_schema }),
             max_completion_tokens: Some(self.max_token as u32),
             reasoning_effort: self.reasoning.clone(),
             ..Default::default()
         };
 
         let openai_response = self.client.chat().create(request).map_err(LlmError::by_backend).await?;
+        let usage = transform_usage(openai_response.usage);
         let Some(first_choice) = openai_response.choices.into_iter().next() else {
             return Err(LlmError::NoChoice);
         };
 
-        transform_choice(first_choice)
+        let update = transform_choice(first_choice, self.structured)?;
+        Ok(LlmResponse::new(update, usage))
+    }
+
+    async fn send_conversation_stream(
+        &self,
+        conversation: &IncompleteConversation,
+        function_descriptors: &[&FunctionDescriptor],
+        delta_sender: UnboundedSender<LlmDelta>,
+    ) -> Result<LlmResponse, LlmError> {
+        let messages = self.transform_messages(conversation, function_descriptors)?;
+        let request = CreateChatCompletionRequest {
+            model: self.model.clone(),
+            messages,
+            tools: self.enable_tool.then(|| transform_tools(function_descriptors)),
+            response_format: self.structured.then(|| ResponseFormat::JsonSchema {
+                json_schema: response_json_schema(conversation),
+            }),
+            max_completion_tokens: Some(self.max_token as u32),
+            reasoning_effort: self.reasoning.clone(),
+            stream_options: Some(ChatCompletionStreamOptions {
+                include_usage: Some(true),
+                include_obfuscation: None,
+            }),
+            ..Default::default()
+        };
+
+        let mut stream = self
+            .client
+            .chat()
+            .create_stream(request)
+            .map_err(LlmError::by_backend)
+            .await?;
+
+        let mut content: Option<String> = None;
+        let mut tool_calls: Vec<StreamedToolCall> = vec![];
+        let mut finish_reason = None;
+        let mut usage = LlmUsage::default();
+        let mut text_filter = TextDeltaFilter::default();
+        while let Some(chunk) = stream.try_next().map_err(LlmError::by_backend).await? {
+            // usage は choices が空の最後の chunk に入ってくる
+            if chunk.usage.is_some() {
+                usage = transform_usage(chunk.usage);
+            }
+            let Some(choice) = chunk.choices.into_iter().next() else {
+                continue;
+            };
+
+            if let Some(text) = choice.delta.content {
+                // structured output の断片は JSON なので流さない
+                if !self.structured {
+                    let sendable = if self.enable_tool {
+                        Some(text.clone())
+                    } else {
+                        text_filter.push(&text)
+                    };
+                    if let Some(sendable) = sendable {
+                        delta_sender.unbounded_send(LlmDelta::Text(sendable)).ok();
+                    }
+                }
+                content.get_or_insert_default().push_str(&text);
+            }
+            for chunk in choice.delta.tool_calls.into_iter().flatten() {
+                let index = chunk.index as usize;
+                if tool_calls.len() <= index {
+                    tool_calls.resize_with(index + 1, Default::default);
+                }
+
+                let (name, arguments) = match chunk.function {
+                    Some(f) => (f.name, f.arguments.unwrap_or_default()),
+                    None => (None, String::new()),
+                };
+                let streamed = &mut tool_calls[index];
+                streamed.id.push_str(chunk.id.as_deref().unwrap_or_default());
+                streamed.name.push_str(name.as_deref().unwrap_or_default());
+                streamed.arguments.push_str(&arguments);
+                delta_sender
+                    .unbounded_send(LlmDelta::ToolCalling {
+                        index,
+                        id: chunk.id,
+                        name,
+                        arguments,
+                    })
+                    .ok();
+            }
+            if choice.finish_reason.is_some() {
+                finish_reason = choice.finish_reason;
+            }
+        }
+
+        let tool_calls = (!tool_calls.is_empty()).then_some(tool_calls);
+        let update = transform_finish(finish_reason, content, tool_calls, self.structured)?;
+        let response = LlmResponse::new(update, usage);
+        if self.enable_tool {
+            Ok(response)
+        } else {
+            Ok(prompt_tool::extract_tool_calls(response, Some(&delta_sender)))
+        }
     }
 }
 
+/// ストリーミングで受け取った tool calling を結合したもの。
+#[derive(Debug, Default)]
+struct StreamedToolCall {
+    id: String,
+    name: String,
+    arguments: String,
+}
+
 fn transform_tools(descriptors: &[&FunctionDescriptor]) -> Vec<ChatCompletionTools> {
     descriptors
         .iter()
@@ -142,64 +308,115 @@ fn transform_tools(descriptors: &[&FunctionDescriptor]) -> Vec<ChatCompletionToo
                     name: d.name.clone(),
                     description: Some(d.description.clone()),
                     parameters: Some(convert_json_schema(&d.parameters)),
-                    strict: Some(true),
+                    strict: Some(supports_strict_mode(&d.parameters)),
                 },
             })
         })
         .collect()
 }
 
-fn transform_choice(choice: ChatChoice) -> Result<LlmUpdate, LlmError> {
-    match choice.finish_reason {
+fn transform_usage(usage: Option<CompletionUsage>) -> LlmUsage {
+    let Some(usage) = usage else {
+        return LlmUsage::default();
+    };
+    let reasoning_tokens = usage
+        .completion_tokens_details
+        .and_then(|d| d.reasoning_tokens)
+        .unwrap_or_default();
+    let cached_tokens = usage
+        .prompt_tokens_details
+        .and_then(|d| d.cached_tokens)
+        .unwrap_or_default();
+
+    LlmUsage {
+        prompt_tokens: usage.prompt_tokens as u64,
+        completion_tokens: usage.completion_tokens as u64,
+        reasoning_tokens: reasoning_tokens as u64,
+        cached_tokens: cached_tokens as u64,
+    }
+}
+
+fn transform_choice(choice: ChatChoice, structured: bool) -> Result<LlmUpdate, LlmError> {
+    let tool_calls = match choice.message.tool_calls {
+        Some(calls) => {
+            let streamed_calls: Result<_, _> = calls
+                .into_iter()
+                .map(|c| {
+                    let ChatCompletionMessageToolCalls::Function(fc) = c else {
+                        return Err(LlmError::ExpectationMismatch("tool call not met".to_string()));
+                    };
+                    Ok(StreamedToolCall {
+                        id: fc.id,
+                        name: fc.function.name,
+                        arguments: fc.function.arguments,
+                    })
+                })
+                .collect();
+            Some(streamed_calls?)
+        }
+        None => None,
+    };
+    transform_finish(choice.finish_reason, choice.message.content, tool_calls, structured)
+}
+
+fn transform_finish(
+    finish_reason: Option<FinishReason>,
+    content: Option<String>,
+    tool_calls: Option<Vec<StreamedToolCall>>,
+    structured: bool,
+) -> Result<LlmUpdate, LlmError> {
+    match finish_reason {
         // stop
         Some(FinishReason::Stop) => {
-            let Some(text) = choice.message.content else {
-                warn!("no content value detected; actual value: {choice:?}");
+            let Some(text) = content else {
+                warn!("no content value detected; actual tool calls: {tool_calls:?}");
                 return Err(LlmError::ExpectationMismatch("no content value".to_string()));
             };
+            if structured {
+                return Ok(LlmUpdate::Finished(parse_structured_response(text)));
+            }
             Ok(LlmUpdate::Finished(LlmAssistantResponse {
                 text,
                 language: None,
                 sensitive: None,
+                actions: AssistantActions::default(),
             }))
         }
 
         // max_length
         Some(FinishReason::Length) => {
             // max_token で tool_calls がカットされることがあるので、明示的に拒否する
-            if matches!(&choice.message.tool_calls, Some(v) if !v.is_empty()) {
+            if matches!(&tool_calls, Some(v) if !v.is_empty()) {
                 return Err(LlmError::ExpectationMismatch(
                     "tool calling was cut by length".to_string(),
                 ));
             }
 
-            let Some(text) = choice.message.content else {
-                warn!("no content value detected; actual value: {choice:?}");
+            let Some(text) = content else {
+                warn!("no content value detected");
                 return Err(LlmError::ExpectationMismatch("no content value".to_string()));
             };
             Ok(LlmUpdate::LengthCut(LlmAssistantResponse {
                 text,
                 language: None,
                 sensitive: None,
+                actions: AssistantActions::default(),
             }))
         }
 
         // tool_calls
         Some(FinishReason::ToolCalls) => {
-            let Some(tool_calls) = choice.message.tool_calls else {
+            let Some(tool_calls) = tool_calls else {
                 // OpenRouter がたまに空で返してくるので見なかったことにする
                 return Ok(LlmUpdate::ToolCalling(vec![]));
             };
             let converted_calls: Result<_, _> = tool_calls
                 .into_iter()
                 .map(|c| {
-                    let ChatCompletionMessageToolCalls::Function(fc) = c else {
-                        return Err(LlmError::ExpectationMismatch("tool call not met".to_string()));
-                    };
-                    let arguments = serde_json::from_str(&fc.function.arguments).map_err(LlmError::by_format)?;
+                    let arguments = serde_json::from_str(&c.arguments).map_err(LlmError::by_format)?;
                     Ok(MessageToolCalling {
-                        id: fc.id,
-                        name: fc.function.name,
+                        id: c.id,
+                        name: c.name,
                         arguments,
                     })
                 })
@@ -1,31 +1,38 @@
-use crate::llm::{convert_json_schema, openai::OpenaiModelConfig};
+use crate::llm::{
+    convert_json_schema,
+    openai::{OpenaiModelConfig, parse_structured_response, response_json_schema, supports_strict_mode},
+    prompt_tool::{self, TextDeltaFilter},
+};
 
 use std::sync::Arc;
 
 use async_openai::types::responses::ReasoningEffort;
-use futures::{FutureExt, TryFutureExt, future::BoxFuture};
+use futures::{FutureExt, TryFutureExt, TryStreamExt, channel::mpsc::UnboundedSender, future::BoxFuture};
 use lnb_core::{
     APP_USER_AGENT,
     error::LlmError,
     interface::{
         function::FunctionDescriptor,
-        llm::{Llm, LlmAssistantResponse, LlmUpdate},
+        llm::{Llm, LlmAssistantResponse, LlmDelta, LlmResponse, LlmUpdate, LlmUsage},
     },
     model::{
         conversation::IncompleteConversation,
-        message::{Message, MessageToolCalling, UserMessageContent},
+        message::{AssistantActions, Message, MessageToolCalling, UserMessageContent},
     },
 };
-use reqwest::{Client, ClientBuilder, header::HeaderMap};
+use reqwest::{Client, ClientBuilder, Response, header::HeaderMap};
 use serde::{Deserialize, Serialize};
 use serde_json::{Value, json};
+use thiserror::Error as ThisError;
+use tracing::warn;
 
 /// OpenAI Responses API を利用したバックエンド。
+/// `store` が有効なら応答をサーバー側に保存させ、連鎖が途切れていなければ `previous_response_id` に続けて新しい分だけ送る。
 #[derive(Debug, Clone)]
 pub struct ResponsesBackend(Arc<ResponsesBackendInner>);
 
 impl ResponsesBackend {
-    pub async fn new(config: OpenaiModelConfig) -> Result<ResponsesBackend, LlmError> {
+    pub async fn new(name: &str, config: OpenaiModelConfig) -> Result<ResponsesBackend, LlmError> {
         let client = {
             let mut headers = HeaderMap::new();
             headers.insert(
@@ -41,11 +48,15 @@ impl ResponsesBackend {
         };
 
         Ok(ResponsesBackend(Arc::new(ResponsesBackendInner {
+            name: name.to_string(),
             client,
             api_root: config.endpoint.clone(),
             model: config.model.clone(),
+            enable_tool: config.tool,
+            structured: config.structured,
             max_token: config.max_token,
             reasoning: config.reasoning,
+            store: config.store,
         })))
     }
 }
@@ -55,20 +66,45 @@ impl Llm for ResponsesBackend {
         &'a self,
         conversation: &'a IncompleteConversation,
         function_descriptors: &'a [&'a FunctionDescriptor],
-    ) -> BoxFuture<'a, Result<LlmUpdate, LlmError>> {
+    ) -> BoxFuture<'a, Result<LlmResponse, LlmError>> {
         let cloned = self.0.clone();
         async move { cloned.send_conversation(conversation, function_descriptors).await }.boxed()
     }
+
+    fn send_conversation_stream<'a>(
+        &'a self,
+        conversation: &'a IncompleteConversation,
+        function_descriptors: &'a [&'a FunctionDescriptor],
+        delta_sender: UnboundedSender<LlmDelta>,
+    ) -> BoxFuture<'a, Result<LlmResponse, LlmError>> {
+        let cloned = self.0.clone();
+        async move {
+            cloned
+                .send_conversation_stream(conversation, function_descriptors, delta_sender)
+                .await
+        }
+        .boxed()
+    }
+
+    fn probe(&self) -> BoxFuture<'_, Result<(), LlmError>> {
+        let cloned = self.0.clone();
+        async move { cloned.probe().await }.boxed()
+    }
 }
 
 #[derive(Debug)]
 #[allow(dead_code)]
 struct ResponsesBackendInner {
+    /// 応答の連鎖を記録するモデル名。
+    name: String,
     client: Client,
     api_root: String,
     model: String,
+    enable_tool: bool,
+    structured: bool,
     max_token: usize,
     reasoning: Option<ReasoningEffort>,
+    store: bool,
 }
 
 impl ResponsesBackendInner {
@@ -76,32 +112,225 @@ impl ResponsesBackendInner {
         &self,
         conversation: &IncompleteConversation,
         function_descriptors: &[&FunctionDescriptor],
-    ) -> Result<LlmUpdate, LlmError> {
+    ) -> Result<LlmResponse, LlmError> {
+        let response_value = self
+            .send_chained_or_full(conversation, function_descriptors, false, |request| async move {
+                self.call_api("/responses", &request).await
+            })
+            .await?;
+        let response = transform_response(&response_value, self.structured)?;
+        if self.enable_tool {
+            Ok(response)
+        } else {
+            Ok(prompt_tool::extract_tool_calls(response, None))
+        }
+    }
+
+    async fn send_conversation_stream(
+        &self,
+        conversation: &IncompleteConversation,
+        function_descriptors: &[&FunctionDescriptor],
+        delta_sender: UnboundedSender<LlmDelta>,
+    ) -> Result<LlmResponse, LlmError> {
+        let response = self
+            .send_chained_or_full(conversation, function_descriptors, true, |request| async move {
+                self.open_stream(&request).await
+            })
+            .await?;
+
+        // chunk の境界は文字の途中にもなりうるので、イベント単位に切り出してからデコードする
+        let mut byte_stream = response.bytes_stream();
+        let mut buffer = vec![];
+        let mut text_filter = TextDeltaFilter::default();
+        while let Some(bytes) = byte_stream.try_next().map_err(LlmError::by_communication).await? {
+            buffer.extend_from_slice(&bytes);
+            while let Some(event_bytes) = take_sse_event(&mut buffer) {
+                let event_text = String::from_utf8(event_bytes).map_err(LlmError::by_format)?;
+                let Some(event) = parse_sse_event(&event_text)? else {
+                    continue;
+                };
+
+                match event {
+                    // structured output の断片は JSON なので流さない
+                    ResponsesStreamEvent::OutputTextDelta { .. } if self.structured => (),
+                    ResponsesStreamEvent::OutputTextDelta { delta } => {
+                        let sendable = if self.enable_tool {
+                            Some(delta)
+                        } else {
+                            text_filter.push(&delta)
+                        };
+                        if let Some(sendable) = sendable {
+                            delta_sender.unbounded_send(LlmDelta::Text(sendable)).ok();
+                        }
+                    }
+                    ResponsesStreamEvent::OutputItemAdded { output_index, item } => {
+                        if let ResponsesOutput::FunctionCall(call) = item {
+                            delta_sender
+                                .unbounded_send(LlmDelta::ToolCalling {
+                                    index: output_index,
+                                    id: Some(call.call_id),
+                                    name: Some(call.name),
+                                    arguments: call.arguments,
+                                })
+                                .ok();
+                        }
+                    }
+                    ResponsesStreamEvent::FunctionCallArgumentsDelta { output_index, delta } => {
+                        delta_sender
+                            .unbounded_send(LlmDelta::ToolCalling {
+                                index: output_index,
+                                id: None,
+                                name: None,
+                                arguments: delta,
+                            })
+                            .ok();
+                    }
+                    ResponsesStreamEvent::Completed { response } | ResponsesStreamEvent::Incomplete { response } => {
+                        let response = transform_response(&response, self.structured)?;
+                        if !self.enable_tool {
+                            return Ok(prompt_tool::extract_tool_calls(response, Some(&delta_sender)));
+                        }
+                        return Ok(response);
+                    }
+                    ResponsesStreamEvent::Failed { response } => {
+                        let message = match response.pointer("/error/message") {
+                            Some(Value::String(s)) => s.as_str(),
+                            _ => "unknown error",
+                        };
+                        return Err(LlmError::Backend(message.to_string().into()));
+                    }
+                    ResponsesStreamEvent::Error { message } => {
+                        return Err(LlmError::Backend(message.into()));
+                    }
+                    ResponsesStreamEvent::Unknown => (),
+                }
+            }
+        }
+
+        Err(LlmError::ExpectationMismatch(
+            "stream ended without completion".to_string(),
+        ))
+    }
+
+    /// 応答の連鎖が途切れていなければ新しい `Message` だけを送る。
+    /// 連鎖が使えないか、保存期限切れなどで `previous_response_id` を拒否された場合は全履歴を送り直す。
+    async fn send_chained_or_full<T, F, Fut>(
+        &self,
+        conversation: &IncompleteConversation,
+        function_descriptors: &[&FunctionDescriptor],
+        stream: bool,
+        send: F,
+    ) -> Result<T, LlmError>
+    where
+        F: Fn(Value) -> Fut,
+        Fut: Future<Output = Result<T, LlmError>>,
+    {
+        let text_format = self.structured.then(|| structured_text_format(conversation));
+        let chained = self.store.then(|| conversation.chained_messages(&self.name)).flatten();
+        if let Some((previous_response_id, messages)) = chained {
+            let request = self.build_request(
+                messages,
+                Some(previous_response_id),
+                function_descriptors,
+                text_format.as_ref(),
+                stream,
+            )?;
+            match send(request).await {
+                Err(LlmError::Backend(e)) if e.downcast_ref::<ApiError>().is_some_and(ApiError::is_chain_rejection) => {
+                    warn!("chained request rejected, resending full history: {e}");
+                }
+                result => return result,
+            }
+        }
+
+        let request = self.build_request(
+            conversation.llm_sending_messages(),
+            None,
+            function_descriptors,
+            text_format.as_ref(),
+            stream,
+        )?;
+        send(request).await
+    }
+
+    fn build_request<'a>(
+        &self,
+        messages: impl IntoIterator<Item = &'a Message>,
+        previous_response_id: Option<&str>,
+        function_descriptors: &[&FunctionDescriptor],
+        text_format: Option<&Value>,
+        stream: bool,
+    ) -> Result<Value, LlmError> {
         let input: Vec<_> = {
-            let unflat: Result<Vec<_>, _> = conversation.llm_sending_messages().map(transform_message).collect();
+            let unflat: Result<Vec<_>, _> = if self.enable_tool {
+                messages.into_iter().map(transform_message).collect()
+            } else {
+                // 連鎖している場合、ツールの説明は送信済み
+                prompt_tool::emulate_messages(messages, function_descriptors, previous_response_id.is_none())
+                    .iter()
+                    .map(|m| transform_message(m))
+                    .collect()
+            };
             unflat?.into_iter().flatten().collect()
         };
 
-        // web search を入れる
-        let mut tools = transform_tools(function_descriptors);
-        tools.push(json!({"type": "web_search_preview"}));
+        // ネイティブのツールが使える場合だけ web search も入れる
+        let tools = if self.enable_tool {
+            let mut tools = transform_tools(function_descriptors);
+            tools.push(json!({"type": "web_search_preview"}));
+            tools
+        } else {
+            vec![]
+        };
 
         let reasoning = self.reasoning.clone().map(|r| {
             json!({
                 "effort": r,
+                "summary": "auto",
             })
         });
 
-        let request = json!({
+        Ok(json!({
             "model": self.model,
             "input": input,
+            "previous_response_id": previous_response_id,
             "tools": tools,
-            "store": false,
+            "store": self.store,
+            "stream": stream,
             "reasoning": reasoning,
-        });
-        let response_value = self.call_api("/responses", &request).await?;
-        let output_objects = response_value["output"].as_array().ok_or(LlmError::NoChoice)?;
-        transform_choice(output_objects)
+            "text": text_format.map(|f| json!({ "format": f })),
+        }))
+    }
+
+    async fn open_stream(&self, request: &Value) -> Result<Response, LlmError> {
+        let api_url = format!("{}/responses", self.api_root);
+        let response = self
+            .client
+            .post(api_url)
+            .json(request)
+            .send()
+            .map_err(LlmError::by_communication)
+            .await?;
+        if !response.status().is_success() {
+            let response_body: Value = response.json().map_err(LlmError::by_format).await?;
+            return Err(LlmError::by_backend(ApiError::from_body(&response_body)));
+        }
+        Ok(response)
+    }
+
+    async fn probe(&self) -> Result<(), LlmError> {
+        let api_url = format!("{}/models/{}", self.api_root, self.model);
+        let response = self
+            .client
+            .get(api_url)
+            .send()
+            .map_err(LlmError::by_communication)
+            .await?;
+        if !response.status().is_success() {
+            let response_body: Value = response.json().map_err(LlmError::by_format).await?;
+            return Err(LlmError::by_backend(ApiError::from_body(&response_body)));
+        }
+        Ok(())
     }
 
     async fn call_api<T: Serialize>(&self, endpoint: &str, body: &T) -> Result<Value, LlmError> {
@@ -119,15 +348,37 @@ impl ResponsesBackendInner {
         if response_status.is_success() {
             Ok(response_body)
         } else {
-            let message = match response_body.pointer("/error/message") {
-                Some(Value::String(s)) => s.as_str(),
-                _ => "unknown error",
-            };
-            Err(LlmError::Backend(message.to_string().into()))
+            Err(LlmError::by_backend(ApiError::from_body(&response_body)))
         }
     }
 }
 
+/// API がエラー応答の `error` で返した内容。
+#[derive(Debug, ThisError)]
+#[error("{message}")]
+struct ApiError {
+    message: String,
+    code: Option<String>,
+    param: Option<String>,
+}
+
+impl ApiError {
+    fn from_body(body: &Value) -> ApiError {
+        let field = |name: &str| body["error"][name].as_str().map(str::to_string);
+        ApiError {
+            message: field("message").unwrap_or_else(|| "unknown error".to_string()),
+            code: field("code"),
+            param: field("param"),
+        }
+    }
+
+    /// `previous_response_id` の応答が見つからないか使えないことを示すかどうか。
+    fn is_chain_rejection(&self) -> bool {
+        self.param.as_deref() == Some("previous_response_id")
+            || self.code.as_deref() == Some("previous_response_not_found")
+    }
+}
+
 fn transform_tools(descriptors: &[&FunctionDescriptor]) -> Vec<Value> {
     descriptors
         .iter()
@@ -137,6 +388,7 @@ fn transform_tools(descriptors: &[&FunctionDescriptor]) -> Vec<Value> {
                 "name": d.name,
                 "description": d.description,
                 "parameters": convert_json_schema(&d.parameters),
+                "strict": supports_strict_mode(&d.parameters),
             })
         })
         .collect()
@@ -200,7 +452,78 @@ fn transform_message(message: &Message) -> Result<Vec<Value>, LlmError> {
     Ok(message)
 }
 
-fn transform_choice(outputs: &[Value]) -> Result<LlmUpdate, LlmError> {
+/// structured output で要求する `text.format` 。
+fn structured_text_format(conversation: &IncompleteConversation) -> Value {
+    let mut format = serde_json::to_value(response_json_schema(conversation)).expect("should serialize");
+    format["type"] = json!("json_schema");
+    format
+}
+
+/// バッファの先頭から完結した SSE のイベントを 1 つ切り出す。
+/// 区切りの空行は LF と CRLF のどちらも受け付ける。
+fn take_sse_event(buffer: &mut Vec<u8>) -> Option<Vec<u8>> {
+    let find = |separator: &[u8]| {
+        buffer
+            .windows(separator.len())
+            .position(|w| w == separator)
+            .map(|p| (p, separator.len()))
+    };
+    let (event_end, separator_len) = match (find(b"\n\n"), find(b"\r\n\r\n")) {
+        (Some(lf), Some(crlf)) => lf.min(crlf),
+        (lf, crlf) => lf.or(crlf)?,
+    };
+    let event: Vec<_> = buffer.drain(..event_end + separator_len).collect();
+    Some(event)
+}
+
+/// SSE のイベント 1 つ分をパースする。`data` を持たないものは無視する。
+fn parse_sse_event(event_text: &str) -> Result<Option<ResponsesStreamEvent>, LlmError> {
+    let data: Vec<_> = event_text
+        .lines()
+        .filter_map(|l| l.strip_prefix("data:"))
+        .map(|d| d.trim_start())
+        .collect();
+    if data.is_empty() {
+        return Ok(None);
+    }
+
+    let event = serde_json::from_str(&data.join("\n")).map_err(LlmError::by_format)?;
+    Ok(Some(event))
+}
+
+fn transform_response(response: &Value, structured: bool) -> Result<LlmResponse, LlmError> {
+    let output_objects = response["output"].as_array().ok_or(LlmError::NoChoice)?;
+    let update = transform_choice(output_objects, structured)?;
+    Ok(LlmResponse {
+        response_id: response["id"].as_str().map(|id| id.to_string()),
+        reasoning_summary: transform_reasoning_summary(output_objects),
+        ..LlmResponse::new(update, transform_usage(&response["usage"]))
+    })
+}
+
+/// `reasoning` 出力の要約をまとめる。
+fn transform_reasoning_summary(outputs: &[Value]) -> Option<String> {
+    let summaries: Vec<_> = outputs
+        .iter()
+        .filter(|o| o["type"] == "reasoning")
+        .filter_map(|o| o["summary"].as_array())
+        .flatten()
+        .filter_map(|s| s["text"].as_str())
+        .collect();
+    (!summaries.is_empty()).then(|| summaries.join("\n\n"))
+}
+
+fn transform_usage(usage: &Value) -> LlmUsage {
+    let tokens = |pointer: &str| usage.pointer(pointer).and_then(Value::as_u64).unwrap_or_default();
+    LlmUsage {
+        prompt_tokens: tokens("/input_tokens"),
+        completion_tokens: tokens("/output_tokens"),
+        reasoning_tokens: tokens("/output_tokens_details/reasoning_tokens"),
+        cached_tokens: tokens("/input_tokens_details/cached_tokens"),
+    }
+}
+
+fn transform_choice(outputs: &[Value], structured: bool) -> Result<LlmUpdate, LlmError> {
     let outputs = {
         let objects: Result<Vec<ResponsesOutput>, _> =
             outputs.iter().map(|v| serde_json::from_value(v.clone())).collect();
@@ -240,10 +563,15 @@ fn transform_choice(outputs: &[Value]) -> Result<LlmUpdate, LlmError> {
     let first_content = message.content.into_iter().next().expect("should have an item");
     match first_content {
         ResponsesMessageContent::OutputText { text } => {
-            let response = LlmAssistantResponse {
-                text,
-                language: None,
-                sensitive: None,
+            let response = if structured {
+                parse_structured_response(text)
+            } else {
+                LlmAssistantResponse {
+                    text,
+                    language: None,
+                    sensitive: None,
+                    actions: AssistantActions::default(),
+                }
             };
             match message.status.as_str() {
                 "completed" => Ok(LlmUpdate::Finished(response)),
@@ -269,6 +597,34 @@ enum ResponsesOutput {
     Unknown,
 }
 
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type")]
+enum ResponsesStreamEvent {
+    #[serde(rename = "response.output_text.delta")]
+    OutputTextDelta { delta: String },
+
+    #[serde(rename = "response.output_item.added")]
+    OutputItemAdded { output_index: usize, item: ResponsesOutput },
+
+    #[serde(rename = "response.function_call_arguments.delta")]
+    FunctionCallArgumentsDelta { output_index: usize, delta: String },
+
+    #[serde(rename = "response.completed")]
+    Completed { response: Value },
+
+    #[serde(rename = "response.incomplete")]
+    Incomplete { response: Value },
+
+    #[serde(rename = "response.failed")]
+    Failed { response: Value },
+
+    #[serde(rename = "error")]
+    Error { message: String },
+
+    #[serde(other)]
+    Unknown,
+}
+
 #[derive(Debug, Clone, Deserialize)]
 struct ResponsesMessage {
     status: String,
@@ -295,3 +651,243 @@ enum ResponsesMessageContent {
     #[serde(other)]
     Unknown,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::ResponsesBackend;
+    use crate::{
+        llm::openai::{OpenaiModelConfig, OpenaiModelConfigApi},
+        mock_server::{MockResponse, serve},
+    };
+
+    use futures::{StreamExt, channel::mpsc::unbounded};
+    use lnb_core::{
+        error::LlmError,
+        interface::llm::{Llm, LlmDelta, LlmUpdate},
+        model::{
+            conversation::{Conversation, IncompleteConversation},
+            message::{AssistantMessage, Message, UserMessageContent},
+        },
+    };
+    use serde_json::{Value, json};
+
+    async fn create_backend(endpoint: String, structured: bool, store: bool) -> ResponsesBackend {
+        ResponsesBackend::new(
+            "test",
+            OpenaiModelConfig {
+                api: OpenaiModelConfigApi::Responses,
+                endpoint,
+                token: "test-token".to_string(),
+                model: "gpt-test".to_string(),
+                tool: true,
+                structured,
+                max_token: 256,
+                reasoning: None,
+                store,
+            },
+        )
+        .await
+        .expect("should create backend")
+    }
+
+    fn create_conversation() -> IncompleteConversation {
+        let mut incomplete = IncompleteConversation::start(Conversation::new_now(Some(Message::new_system("system"))));
+        incomplete.extend_messages([Message::new_user(
+            [UserMessageContent::Text("hello".to_string())],
+            None,
+            None,
+            false,
+        )]);
+        incomplete
+    }
+
+    /// "resp_prev" に続けて新しい `UserMessage` を送る状態の会話。
+    fn create_chained_conversation() -> IncompleteConversation {
+        let mut incomplete = create_conversation();
+        incomplete.set_response_chain("test", "resp_prev");
+        incomplete.push_assistant(AssistantMessage {
+            text: "hi".to_string(),
+            ..Default::default()
+        });
+        incomplete.extend_messages([Message::new_user(
+            [UserMessageContent::Text("again".to_string())],
+            None,
+            None,
+            false,
+        )]);
+        incomplete
+    }
+
+    fn error_response(status: u16, error: Value) -> MockResponse {
+        MockResponse::json(json!({ "error": error })).with_status(status)
+    }
+
+    fn sse_event(event: Value) -> String {
+        format!(
+            "event: {}\r\ndata: {event}\r\n\r\n",
+            event["type"].as_str().expect("should have type")
+        )
+    }
+
+    fn completed_event(text: &str) -> Value {
+        json!({
+            "type": "response.completed",
+            "response": {
+                "id": "resp_01",
+                "output": [{
+                    "type": "message",
+                    "status": "completed",
+                    "content": [{ "type": "output_text", "text": text }],
+                }],
+            },
+        })
+    }
+
+    /// `events` を 1 本の SSE にして、それぞれのバイト位置で分割した chunk 列にする。
+    fn split_stream(events: &[Value], split_at: &[usize]) -> Vec<Vec<u8>> {
+        let body: String = events.iter().cloned().map(sse_event).collect();
+        let bytes = body.into_bytes();
+        let mut chunks = vec![];
+        let mut start = 0;
+        for end in split_at.iter().copied().chain([bytes.len()]) {
+            chunks.push(bytes[start..end].to_vec());
+            start = end;
+        }
+        chunks
+    }
+
+    #[tokio::test]
+    async fn responses_stream_decodes_characters_split_across_chunks() {
+        let events = [
+            json!({ "type": "response.output_text.delta", "delta": "こんにちは" }),
+            completed_event("こんにちは"),
+        ];
+        // "こ" の 2 バイト目と、イベント区切りの CRLF の間で分割する
+        let first_event_len = sse_event(events[0].clone()).len();
+        let delta_start = sse_event(events[0].clone()).find("こ").expect("should contain text");
+        let chunks = split_stream(&events, &[delta_start + 2, first_event_len - 2]);
+        let (endpoint, _handle) = serve(vec![MockResponse::chunked("text/event-stream", chunks)]).await;
+        let backend = create_backend(endpoint, false, false).await;
+
+        let (delta_sender, delta_receiver) = unbounded();
+        let response = backend
+            .send_conversation_stream(&create_conversation(), &[], delta_sender)
+            .await
+            .expect("should succeed");
+        let LlmUpdate::Finished(finished) = response.update else {
+            panic!("should be finished: {:?}", response.update);
+        };
+        assert_eq!(finished.text, "こんにちは");
+
+        let deltas: Vec<_> = delta_receiver.collect().await;
+        assert!(
+            matches!(&deltas[..], [LlmDelta::Text(t)] if t == "こんにちは"),
+            "{deltas:?}"
+        );
+    }
+
+    #[tokio::test]
+    async fn responses_stream_withholds_structured_deltas() {
+        let structured_text = json!({ "text": "hi", "language": null, "sensitive": null }).to_string();
+        let events = [
+            json!({ "type": "response.output_text.delta", "delta": structured_text }),
+            completed_event(&structured_text),
+        ];
+        let chunks = split_stream(&events, &[]);
+        let (endpoint, handle) = serve(vec![MockResponse::chunked("text/event-stream", chunks)]).await;
+        let backend = create_backend(endpoint, true, false).await;
+
+        let (delta_sender, delta_receiver) = unbounded();
+        let response = backend
+            .send_conversation_stream(&create_conversation(), &[], delta_sender)
+            .await
+            .expect("should succeed");
+        let LlmUpdate::Finished(finished) = response.update else {
+            panic!("should be finished: {:?}", response.update);
+        };
+        assert_eq!(finished.text, "hi");
+
+        let deltas: Vec<_> = delta_receiver.collect().await;
+        assert!(deltas.is_empty(), "{deltas:?}");
+
+        let request = handle.await.expect("should receive request")[0].json();
+        assert_eq!(request["text"]["format"]["type"], "json_schema");
+    }
+
+    #[tokio::test]
+    async fn responses_chain_falls_back_when_previous_response_is_rejected() {
+        let (endpoint, handle) = serve(vec![
+            error_response(
+                400,
+                json!({
+                    "message": "Previous response with id 'resp_prev' not found.",
+                    "type": "invalid_request_error",
+                    "param": "previous_response_id",
+                    "code": "previous_response_not_found",
+                }),
+            ),
+            MockResponse::json(completed_event("hi again")["response"].clone()),
+        ])
+        .await;
+        let backend = create_backend(endpoint, false, true).await;
+
+        let response = backend
+            .send_conversation(&create_chained_conversation(), &[])
+            .await
+            .expect("should succeed");
+        assert!(matches!(response.update, LlmUpdate::Finished(f) if f.text == "hi again"));
+
+        let requests = handle.await.expect("should receive requests");
+        let (chained, full) = (requests[0].json(), requests[1].json());
+        assert_eq!(chained["previous_response_id"], "resp_prev");
+        assert_eq!(chained["store"], true);
+        assert_eq!(chained["input"].as_array().map(|i| i.len()), Some(1));
+        assert_eq!(full["previous_response_id"], Value::Null);
+        assert_eq!(full["input"].as_array().map(|i| i.len()), Some(4));
+    }
+
+    #[tokio::test]
+    async fn responses_chain_passes_other_errors_through() {
+        // 送り直せばモックサーバーへの接続に失敗して別のエラーになる
+        let errors = [
+            json!({ "message": "server is overloaded", "type": "server_error", "param": null, "code": null }),
+            // 文面に previous_response を含んでも、別の引数についてのエラーなら送り直さない
+            json!({
+                "message": "Invalid 'input': previous_response items must not be empty.",
+                "type": "invalid_request_error",
+                "param": "input",
+                "code": "invalid_value",
+            }),
+        ];
+        for error in errors {
+            let message = error["message"].as_str().expect("should have message").to_string();
+            let (endpoint, handle) = serve(vec![error_response(400, error)]).await;
+            let backend = create_backend(endpoint, false, true).await;
+
+            let result = backend.send_conversation(&create_chained_conversation(), &[]).await;
+            assert!(
+                matches!(&result, Err(LlmError::Backend(e)) if e.to_string() == message),
+                "{result:?}"
+            );
+            assert_eq!(handle.await.expect("should receive request").len(), 1);
+        }
+    }
+
+    #[tokio::test]
+    async fn responses_without_store_sends_full_history() {
+        let (endpoint, handle) = serve(vec![MockResponse::json(
+            completed_event("hi again")["response"].clone(),
+        )])
+        .await;
+        let backend = create_backend(endpoint, false, false).await;
+
+        backend
+            .send_conversation(&create_chained_conversation(), &[])
+            .await
+            .expect("should succeed");
+
+        let request = handle.await.expect("should receive request")[0].json();
+        assert_eq!(request["previous_response_id"], Value::Null);
+        assert_eq!(request["store"], false);
+    }
+}
@@ -0,0 +1,283 @@
+use crate::llm::convert_json_schema;
+
+use std::{borrow::Cow, sync::LazyLock};
+
+use futures::channel::mpsc::UnboundedSender;
+use lnb_core::{
+    interface::{
+        function::FunctionDescriptor,
+        llm::{LlmDelta, LlmResponse, LlmUpdate},
+    },
+    model::message::{Message, MessageToolCalling, UserMessageContent},
+};
+use regex::Regex;
+use serde::Deserialize;
+use serde_json::{Value, json};
+use tracing::warn;
+use uuid::Uuid;
+
+const TOOL_CALL_TAG: &str = "tool_call";
+const TOOL_RESULT_TAG: &str = "tool_result";
+
+static TOOL_CALL_BLOCK: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?s)```tool_call[ \t]*\r?\n(.*?)```").expect("should compile"));
+
+/// モデルが出力する呼び出しブロックの中身。
+#[derive(Debug, Deserialize)]
+struct PromptToolCall {
+    name: String,
+
+    #[serde(default)]
+    arguments: Option<Value>,
+}
+
+/// tool calling の代わりに送る `Message` 列を作る。
+/// 呼び出しと結果はテキストに置き換え、 `with_instruction` なら先頭の `SystemMessage` の直後にツールの説明を挟む。
+pub fn emulate_messages<'a>(
+    messages: impl IntoIterator<Item = &'a Message>,
+    function_descriptors: &[&FunctionDescriptor],
+    with_instruction: bool,
+) -> Vec<Cow<'a, Message>> {
+    let mut emulated: Vec<_> = messages.into_iter().map(textualize).collect();
+    if with_instruction && !function_descriptors.is_empty() {
+        let system_count = emulated
+            .iter()
+            .take_while(|m| matches!(m.as_ref(), Message::System(_)))
+            .count();
+        emulated.insert(system_count, Cow::Owned(describe_tools(function_descriptors)));
+    }
+    emulated
+}
+
+/// 応答本文に呼び出しブロックがあれば `LlmUpdate::ToolCalling` に置き換える。
+/// 置き換えた場合、ストリーミングで流した本文は破棄させる。
+pub fn extract_tool_calls(mut response: LlmResponse, delta_sender: Option<&UnboundedSender<LlmDelta>>) -> LlmResponse {
+    let LlmUpdate::Finished(finished) = &response.update else {
+        return response;
+    };
+    let Some(tool_calls) = parse_tool_calls(&finished.text) else {
+        return response;
+    };
+
+    if let Some(ds) = delta_sender {
+        ds.unbounded_send(LlmDelta::Restart).ok();
+    }
+    response.update = LlmUpdate::ToolCalling(tool_calls);
+    response
+}
+
+/// 呼び出しブロックを流さないように本文の delta を絞る。
+/// 呼び出しブロックのフェンスが始まった時点以降は流さず、最後に `extract_tool_calls` で判定させる。
+/// ほかのコードフェンスはそのまま流す。
+#[derive(Debug, Default)]
+pub struct TextDeltaFilter {
+    text: String,
+    sent_len: usize,
+    blocked: bool,
+}
+
+impl TextDeltaFilter {
+    /// `delta` を加え、流してよい部分を返す。
+    pub fn push(&mut self, delta: &str) -> Option<String> {
+        if self.blocked {
+            return None;
+        }
+        self.text.push_str(delta);
+
+        let opening = format!("```{TOOL_CALL_TAG}");
+        let sendable_len = match self.text[self.sent_len..].find(&opening) {
+            Some(offset) => {
+                self.blocked = true;
+                self.sent_len + offset
+            }
+            // 呼び出しブロックの開始の途中かもしれない末尾は保留する
+            None => {
+                let pending_len = (1..opening.len())
+                    .rev()
+                    .find(|&len| self.text.ends_with(&opening[..len]))
+                    .unwrap_or(0);
+                self.text.len() - pending_len
+            }
+        };
+        let sendable = &self.text[self.sent_len..sendable_len];
+        if sendable.is_empty() {
+            return None;
+        }
+        let sendable = sendable.to_string();
+        self.sent_len = sendable_len;
+        Some(sendable)
+    }
+}
+
+fn describe_tools(function_descriptors: &[&FunctionDescriptor]) -> Message {
+    let mut text = format!(
+        "以下のツールを利用できます。\
+        ツールを呼び出す場合は、ほかの文章を書かずに次の形式のブロックだけを出力してください。\
+        複数呼び出す場合はブロックを並べてください。\n\
+        ```{TOOL_CALL_TAG}\n{{\"name\": \"ツール名\", \"arguments\": {{引数}}}}\n```\n\
+        呼び出しの結果は {TOOL_RESULT_TAG} ブロックとして渡されます。\n\n\
+        ツール一覧:"
+    );
+    for descriptor in function_descriptors {
+        let parameters = convert_json_schema(&descriptor.parameters);
+        text.push_str(&format!(
+            "\n- {}: {}\n  引数の JSON Schema: {parameters}",
+            descriptor.name, descriptor.description
+        ));
+    }
+    Message::new_system(text)
+}
+
+fn textualize(message: &Message) -> Cow<'_, Message> {
+    match message {
+        Message::FunctionCalls(function_calls_message) => {
+            let blocks: Vec<_> = function_calls_message
+                .0
+                .iter()
+                .map(|c| format_block(TOOL_CALL_TAG, &json!({ "name": c.name, "arguments": c.arguments })))
+                .collect();
+            Cow::Owned(Message::new_assistant(blocks.join("\n"), false, None, false))
+        }
+        Message::FunctionResponse(function_response_message) => {
+            let block = format_block(
+                TOOL_RESULT_TAG,
+                &json!({ "name": function_response_message.name, "result": function_response_message.result }),
+            );
+            Cow::Owned(Message::new_user([UserMessageContent::Text(block)], None, None, false))
+        }
+        otherwise => Cow::Borrowed(otherwise),
+    }
+}
+
+fn format_block(tag: &str, value: &Value) -> String {
+    format!("```{tag}\n{value}\n```")
+}
+
+/// 呼び出しブロックを取り出す。解釈できないブロックがあれば本文として扱う。
+fn parse_tool_calls(text: &str) -> Option<Vec<MessageToolCalling>> {
+    let mut tool_calls = vec![];
+    for captures in TOOL_CALL_BLOCK.captures_iter(text) {
+        let call: PromptToolCall = match serde_json::from_str(captures[1].trim()) {
+            Ok(call) => call,
+            Err(e) => {
+                warn!("ignoring invalid tool call block: {e}");
+                return None;
+            }
+        };
+        tool_calls.push(MessageToolCalling {
+            id: format!("call_{}", Uuid::now_v7().simple()),
+            name: call.name,
+            arguments: call.arguments.unwrap_or_else(|| json!({})),
+        });
+    }
+    (!tool_calls.is_empty()).then_some(tool_calls)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use lnb_core::{
+        interface::llm::LlmAssistantResponse,
+        model::{
+            message::{AssistantActions, FunctionCallsMessage, FunctionResponseMessage},
+            schema::DescribedSchema,
+        },
+    };
+
+    fn finished(text: &str) -> LlmResponse {
+        LlmUpdate::Finished(LlmAssistantResponse {
+            text: text.to_string(),
+            language: None,
+            sensitive: None,
+            actions: AssistantActions::default(),
+        })
+        .into()
+    }
+
+    #[test]
+    fn extracts_tool_call_blocks() {
+        let text = "```tool_call\n{\"name\": \"clock\"}\n```\n```tool_call\n{\"name\": \"echo\", \"arguments\": {\"text\": \"hi\"}}\n```";
+        let response = extract_tool_calls(finished(text), None);
+        let LlmUpdate::ToolCalling(tool_calls) = response.update else {
+            panic!("should be converted into tool calling");
+        };
+        assert_eq!(tool_calls.len(), 2);
+        assert_eq!(tool_calls[0].name, "clock");
+        assert_eq!(tool_calls[0].arguments, json!({}));
+        assert_eq!(tool_calls[1].arguments, json!({ "text": "hi" }));
+        assert_ne!(tool_calls[0].id, tool_calls[1].id);
+    }
+
+    #[test]
+    fn keeps_plain_text_and_broken_blocks() {
+        let plain = extract_tool_calls(finished("今はお昼だよ。"), None);
+        assert!(matches!(plain.update, LlmUpdate::Finished(f) if f.text == "今はお昼だよ。"));
+
+        let broken = extract_tool_calls(finished("```tool_call\n{\"name\": \n```"), None);
+        assert!(matches!(broken.update, LlmUpdate::Finished(_)));
+    }
+
+    #[test]
+    fn filter_holds_back_fenced_blocks() {
+        let mut filter = TextDeltaFilter::default();
+        assert_eq!(filter.push("調べるね。").as_deref(), Some("調べるね。"));
+        assert_eq!(filter.push("\n``").as_deref(), Some("\n"));
+        assert_eq!(filter.push("`tool_call\n{}"), None);
+        assert_eq!(filter.push("\n```"), None);
+
+        let mut partial = TextDeltaFilter::default();
+        assert_eq!(partial.push("```tool"), None);
+        assert_eq!(partial.push("_call\n{}"), None);
+
+        let mut plain = TextDeltaFilter::default();
+        assert_eq!(plain.push("`code` です").as_deref(), Some("`code` です"));
+        assert_eq!(plain.push("\n```tool").as_deref(), Some("\n"));
+        assert_eq!(plain.push("s\n").as_deref(), Some("```tools\n"));
+    }
+
+    #[test]
+    fn filter_streams_other_code_blocks() {
+        let mut filter = TextDeltaFilter::default();
+        assert_eq!(filter.push("例:\n```").as_deref(), Some("例:\n"));
+        assert_eq!(
+            filter.push("rust\nfn main() {}\n```").as_deref(),
+            Some("```rust\nfn main() {}\n")
+        );
+        assert_eq!(filter.push("\n以上").as_deref(), Some("```\n以上"));
+    }
+
+    #[test]
+    fn emulates_tool_messages_as_text() {
+        let descriptor = FunctionDescriptor {
+            name: "clock".to_string(),
+            description: "現在時刻を返す".to_string(),
+            parameters: DescribedSchema::object("parameters", "引数", []),
+        };
+        let messages = [
+            Message::new_system("あなたは夏稀です。"),
+            Message::new_user([UserMessageContent::Text("今何時？".to_string())], None, None, false),
+            FunctionCallsMessage(vec![MessageToolCalling {
+                id: "call_clock".to_string(),
+                name: "clock".to_string(),
+                arguments: json!({}),
+            }])
+            .into(),
+            FunctionResponseMessage {
+                id: "call_clock".to_string(),
+                name: "clock".to_string(),
+                result: json!({ "now": "12:00" }),
+            }
+            .into(),
+        ];
+
+        let emulated = emulate_messages(&messages, &[&descriptor], true);
+        assert_eq!(emulated.len(), 5);
+        assert!(matches!(emulated[1].as_ref(), Message::System(s) if s.0.contains("- clock: 現在時刻を返す")));
+        assert!(matches!(emulated[3].as_ref(), Message::Assistant(a) if a.text.starts_with("```tool_call\n")));
+        assert!(matches!(emulated[4].as_ref(), Message::User(u) if u.contents.len() == 1));
+
+        let chained = emulate_messages(&messages[3..], &[&descriptor], false);
+        assert_eq!(chained.len(), 1);
+    }
+}
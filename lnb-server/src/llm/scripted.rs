@@ -0,0 +1,152 @@
+use std::{
+    path::PathBuf,
+    sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    },
+};
+
+use futures::{FutureExt, future::BoxFuture};
+use lnb_core::{
+    error::LlmError,
+    interface::{
+        function::FunctionDescriptor,
+        llm::{ArcLlm, Llm, LlmAssistantResponse, LlmResponse, LlmUpdate, LlmUsage},
+    },
+    model::{conversation::IncompleteConversation, message::MessageToolCalling},
+};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tracing::debug;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ScriptedModelConfig {
+    /// 応答を列挙した JSON ファイル。
+    pub fixture: PathBuf,
+}
+
+/// fixture に記述する 1 回分の応答。
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScriptedResponse {
+    pub update: ScriptedUpdate,
+
+    #[serde(default)]
+    pub usage: LlmUsage,
+
+    #[serde(default)]
+    pub answered_model: Option<String>,
+
+    #[serde(default)]
+    pub response_id: Option<String>,
+
+    #[serde(default)]
+    pub reasoning_summary: Option<String>,
+}
+
+impl From<LlmResponse> for ScriptedResponse {
+    fn from(value: LlmResponse) -> ScriptedResponse {
+        ScriptedResponse {
+            update: value.update.into(),
+            usage: value.usage,
+            answered_model: value.answered_model,
+            response_id: value.response_id,
+            reasoning_summary: value.reasoning_summary,
+        }
+    }
+}
+
+impl From<ScriptedResponse> for LlmResponse {
+    fn from(value: ScriptedResponse) -> LlmResponse {
+        LlmResponse {
+            update: value.update.into(),
+            answered_model: value.answered_model,
+            usage: value.usage,
+            discarded_usages: vec![],
+            response_id: value.response_id,
+            reasoning_summary: value.reasoning_summary,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case", tag = "kind", content = "value")]
+pub enum ScriptedUpdate {
+    Finished(LlmAssistantResponse),
+    LengthCut(LlmAssistantResponse),
+    ToolCalling(Vec<MessageToolCalling>),
+    Filtered,
+}
+
+impl From<LlmUpdate> for ScriptedUpdate {
+    fn from(value: LlmUpdate) -> ScriptedUpdate {
+        match value {
+            LlmUpdate::Finished(response) => ScriptedUpdate::Finished(response),
+            LlmUpdate::LengthCut(response) => ScriptedUpdate::LengthCut(response),
+            LlmUpdate::ToolCalling(tool_callings) => ScriptedUpdate::ToolCalling(tool_callings),
+            LlmUpdate::Filtered => ScriptedUpdate::Filtered,
+        }
+    }
+}
+
+impl From<ScriptedUpdate> for LlmUpdate {
+    fn from(value: ScriptedUpdate) -> LlmUpdate {
+        match value {
+            ScriptedUpdate::Finished(response) => LlmUpdate::Finished(response),
+            ScriptedUpdate::LengthCut(response) => LlmUpdate::LengthCut(response),
+            ScriptedUpdate::ToolCalling(tool_callings) => LlmUpdate::ToolCalling(tool_callings),
+            ScriptedUpdate::Filtered => LlmUpdate::Filtered,
+        }
+    }
+}
+
+pub async fn create_scripted_llm(config_value: Value) -> Result<ArcLlm, LlmError> {
+    let config: ScriptedModelConfig = serde_json::from_value(config_value).map_err(LlmError::by_format)?;
+    let fixture = tokio::fs::read_to_string(&config.fixture)
+        .await
+        .map_err(LlmError::by_backend)?;
+    let responses: Vec<ScriptedResponse> = serde_json::from_str(&fixture).map_err(LlmError::by_format)?;
+    Ok(Arc::new(ScriptedLlm::new(responses)))
+}
+
+/// fixture に記述された応答を先頭から順に返すバックエンド。
+/// 送信された会話の内容は見ない。
+#[derive(Clone)]
+pub struct ScriptedLlm(Arc<ScriptedLlmInner>);
+
+impl ScriptedLlm {
+    pub fn new(responses: Vec<ScriptedResponse>) -> ScriptedLlm {
+        ScriptedLlm(Arc::new(ScriptedLlmInner {
+            responses,
+            cursor: AtomicUsize::new(0),
+        }))
+    }
+}
+
+impl Llm for ScriptedLlm {
+    fn send_conversation<'a>(
+        &'a self,
+        _conversation: &'a IncompleteConversation,
+        _function_descriptors: &'a [&'a FunctionDescriptor],
+    ) -> BoxFuture<'a, Result<LlmResponse, LlmError>> {
+        let cloned = self.0.clone();
+        async move { cloned.next_response() }.boxed()
+    }
+}
+
+struct ScriptedLlmInner {
+    responses: Vec<ScriptedResponse>,
+    cursor: AtomicUsize,
+}
+
+impl ScriptedLlmInner {
+    fn next_response(&self) -> Result<LlmResponse, LlmError> {
+        let index = self.cursor.fetch_add(1, Ordering::SeqCst);
+        let Some(response) = self.responses.get(index) else {
+            let message = format!("scripted responses exhausted ({} defined)", self.responses.len());
+            return Err(LlmError::ExpectationMismatch(message));
+        };
+
+        debug!("replaying scripted response #{index}");
+        Ok(response.clone().into())
+    }
+}
@@ -2,57 +2,82 @@ mod bang_command;
 mod cli;
 mod function;
 mod llm;
+mod mcp;
+#[cfg(test)]
+mod mock_server;
 mod natsuki;
+mod reload;
 mod shiyu;
 mod storage;
 
 use crate::{
     bang_command::initialize_bang_command,
     function::{
-        ConfigurableFunction, DailyPrivate, ExchangeRate, GetIllustUrl, ImageGenerator, LocalInfo, MathRenderer,
-        SelfInfo,
+        ConfigurableFunction, DailyPrivate, ExchangeRate, GetIllustUrl, HttpTool, ImageGenerator, LocalInfo,
+        MathRenderer, SelfInfo,
     },
-    natsuki::{FunctionStore, LlmCache, Natsuki},
+    mcp::initialize_mcp_functions,
+    natsuki::{FunctionStore, LlmCache, LlmModelStatus, Natsuki},
+    reload::{RateLimiters, Reloader},
     shiyu::{Shiyu, ShiyuProvider},
     storage::initialize_storage,
 };
 
 use std::{collections::HashMap, sync::Arc};
 
-use anyhow::Result;
+use anyhow::{Result, bail};
 use clap::Parser;
 use futures::future::{join, join_all};
 use lnb_common::{
-    config::{Config, load_config, tools::ConfigTools},
-    debug::set_debug_options,
-    rate_limits::{RateLimits, RateLimitsCategory, load_rate_limits},
-    user_roles::load_user_roles,
+    config::{Config, assistant::ConfigAssistant, load_config, tools::ConfigTools},
+    debug::{debug_option_parsed, set_debug_options},
+    rate_limits::load_rate_limits,
+    time_provider::BotDateTimeProvider,
+    user_roles::{SharedUserRolesGroup, load_user_roles},
 };
 use lnb_core::interface::{client::LnbClient, function::ArcFunction, interception::BoxInterception};
 use lnb_discord_client::DiscordLnbClient;
 use lnb_mastodon_client::MastodonLnbClient;
+use lnb_rate_limiter::RateLimiter;
+use time::Duration;
 use tokio::spawn;
-use tracing::info;
+use tracing::{info, warn};
 
 #[tokio::main]
 async fn main() -> Result<()> {
     tracing_subscriber::fmt::init();
     let args = cli::Arguments::parse();
-    let config = load_config(args.config)?;
-    let rate_limits = load_rate_limits(args.rate_limits)?;
-    let user_roles = load_user_roles(args.user_roles)?;
+    let config = load_config(&args.config)?;
+    let rate_limits = load_rate_limits(&args.rate_limits)?;
+    let user_roles = load_user_roles(&args.user_roles)?;
 
     let debug_options: HashMap<_, _> = args.debug_options.into_iter().collect();
     set_debug_options(debug_options);
 
-    let (natsuki, shiyu) = initialize_natsuki(&config, &rate_limits).await?;
+    validate_personas(&config)?;
+    let rate_limiters = RateLimiters::new(&rate_limits, &config.tools)?;
+    let mastodon_roles = SharedUserRolesGroup::new(user_roles.mastodon);
+    let discord_roles = SharedUserRolesGroup::new(user_roles.discord);
+    let (natsuki, shiyu, llm_cache) = initialize_natsuki(&config, &rate_limiters).await?;
+
+    // SIGHUP で再読み込み
+    let reloader = Reloader {
+        config_path: args.config,
+        rate_limits_path: args.rate_limits,
+        user_roles_path: args.user_roles,
+        llm_cache,
+        rate_limiters,
+        mastodon_roles: mastodon_roles.clone(),
+        discord_roles: discord_roles.clone(),
+    };
+    spawn(reloader.run());
 
     let mut client_tasks = vec![];
 
     // Mastodon
     if let Some(mastodon_config) = &config.client.mastodon {
         info!("starting Mastodon client");
-        let mastodon_client = MastodonLnbClient::new(mastodon_config, user_roles.mastodon, natsuki.clone()).await?;
+        let mastodon_client = MastodonLnbClient::new(mastodon_config, mastodon_roles, natsuki.clone()).await?;
         shiyu.register_remindable(mastodon_client.clone()).await;
 
         let mastodon_task = spawn(mastodon_client.execute());
@@ -62,7 +87,7 @@ async fn main() -> Result<()> {
     // Discord
     if let Some(dicsord_config) = &config.client.discord {
         info!("starting Discord client");
-        let discord_client = DiscordLnbClient::new(dicsord_config, user_roles.discord, natsuki.clone()).await?;
+        let discord_client = DiscordLnbClient::new(dicsord_config, discord_roles, natsuki.clone()).await?;
 
         let discord_task = spawn(discord_client.execute());
         client_tasks.push(Box::new(discord_task));
@@ -80,7 +105,21 @@ async fn main() -> Result<()> {
     Ok(())
 }
 
-async fn initialize_natsuki(config: &Config, rate_limits: &RateLimits) -> Result<(Natsuki, Shiyu)> {
+/// クライアントの既定のペルソナが設定されているか確認する。
+fn validate_personas(config: &Config) -> Result<()> {
+    let client_personas = [
+        config.client.mastodon.as_ref().and_then(|c| c.persona.as_ref()),
+        config.client.discord.as_ref().and_then(|c| c.persona.as_ref()),
+    ];
+    for persona in client_personas.into_iter().flatten() {
+        if !config.assistant.personas.contains_key(persona) {
+            bail!("persona not found: {persona}");
+        }
+    }
+    Ok(())
+}
+
+async fn initialize_natsuki(config: &Config, rate_limiters: &RateLimiters) -> Result<(Natsuki, Shiyu, LlmCache)> {
     // Reminder
     let shiyu = Shiyu::new(&config.reminder).await?;
     let shiyu_provider = ShiyuProvider::new(&config.reminder, shiyu.clone()).await?;
@@ -90,54 +129,98 @@ async fn initialize_natsuki(config: &Config, rate_limits: &RateLimits) -> Result
     info!("using storage engine: {}", storage.description());
 
     // LlmCache
-    let llm_cache = LlmCache::new(&config.llm);
+    let llm_cache = LlmCache::new(&config.llm)?;
     info!("{} LLM backend definitions loaded", config.llm.models.len());
+    let llm_statuses = llm_cache.warm_up().await;
+    for (key, status) in &llm_statuses {
+        match status {
+            LlmModelStatus::Unreachable { last_error } => {
+                warn!("LLM {key} is initialized but not reachable: {last_error}")
+            }
+            LlmModelStatus::Failed {
+                attempts,
+                last_error,
+                retry_in,
+            } => warn!(
+                "LLM {key} failed to initialize ({attempts} attempt(s), retrying in {}s): {last_error}",
+                retry_in.as_secs()
+            ),
+            LlmModelStatus::Uninitialized | LlmModelStatus::Ready => (),
+        }
+    }
+    let ready_count = llm_statuses
+        .values()
+        .filter(|s| matches!(s, LlmModelStatus::Ready))
+        .count();
+    info!("{ready_count} LLM backend(s) ready");
 
     // Functions
-    let mut functions = initialize_functions(&config.tools, rate_limits).await?;
+    let mut functions = initialize_functions(&config.tools, rate_limiters).await?;
     functions.push(Arc::new(shiyu_provider));
-    let function_store = FunctionStore::new(functions);
+    let function_store = FunctionStore::new(functions, &config.tools)?;
 
     // Interceptions
-    let interceptions = initialize_interceptions().await?;
+    let interceptions = initialize_interceptions(&config.assistant).await?;
+
+    // DateTime
+    let mut datetime_provider = BotDateTimeProvider::new();
+    let offset_days = debug_option_parsed("datetime_offset")?.unwrap_or(0);
+    if offset_days != 0 {
+        warn!("day offset: {offset_days}");
+        datetime_provider.set_offset(Duration::days(offset_days as i64));
+    }
 
     let natsuki = Natsuki::new(
         storage,
-        Some(rate_limits.conversation.clone().try_into()?),
-        llm_cache,
+        Some(rate_limiters.conversation.clone()),
+        llm_cache.clone(),
         function_store,
         interceptions,
         &config.assistant,
+        Arc::new(datetime_provider),
     )
     .await?;
-    Ok((natsuki, shiyu))
+    Ok((natsuki, shiyu, llm_cache))
 }
 
-async fn initialize_functions(tool_config: &ConfigTools, rate_limits: &RateLimits) -> Result<Vec<ArcFunction>> {
+async fn initialize_functions(tool_config: &ConfigTools, rate_limiters: &RateLimiters) -> Result<Vec<ArcFunction>> {
     let mut functions: Vec<ArcFunction> = vec![];
 
     functions.push(Arc::new(SelfInfo::new()));
     functions.push(Arc::new(LocalInfo::new()?));
 
     functions.extend(
-        configure_function::<ImageGenerator>(tool_config.image_generator.as_ref(), Some(&rate_limits.image_generator))
-            .await?,
+        configure_function::<ImageGenerator>(
+            tool_config.image_generator.as_ref(),
+            Some(rate_limiters.image_generator.clone()),
+        )
+        .await?,
     );
     functions.extend(configure_function::<MathRenderer>(tool_config.math_renderer.as_ref(), None).await?);
     functions.extend(configure_function::<ExchangeRate>(tool_config.exchange_rate.as_ref(), None).await?);
     functions.extend(configure_function::<GetIllustUrl>(tool_config.get_illust_url.as_ref(), None).await?);
     functions.extend(configure_function::<DailyPrivate>(tool_config.daily_private.as_ref(), None).await?);
+    for http_tool in &tool_config.http_tools {
+        // 同じカテゴリを指す tool は bucket を共有する
+        let rate_limiter = http_tool
+            .rate_limit
+            .as_ref()
+            .and_then(|category| rate_limiters.tools.get(category))
+            .cloned();
+        functions.extend(configure_function::<HttpTool>(Some(http_tool), rate_limiter).await?);
+    }
+    functions.extend(initialize_mcp_functions(&tool_config.mcp_servers).await);
 
     Ok(functions)
 }
 
-async fn initialize_interceptions() -> Result<Vec<BoxInterception>> {
-    Ok(vec![initialize_bang_command().await.into()])
+async fn initialize_interceptions(assistant_config: &ConfigAssistant) -> Result<Vec<BoxInterception>> {
+    Ok(vec![initialize_bang_command(assistant_config).await.into()])
 }
 
 async fn configure_function<F>(
     config: Option<&F::Configuration>,
-    rate_limits_category: Option<&RateLimitsCategory>,
+    rate_limiter: Option<RateLimiter>,
 ) -> Result<Option<ArcFunction>>
 where
     F: ConfigurableFunction + 'static,
@@ -145,9 +228,8 @@ where
     let Some(config) = config else {
         return Ok(None);
     };
-    let rate_limiter = rate_limits_category.cloned().map(TryInto::try_into).transpose();
 
-    let simple_function = F::configure(config, rate_limiter?).await?;
+    let simple_function = F::configure(config, rate_limiter).await?;
     info!("simple function configured: {}", F::NAME);
     Ok(Some(Arc::new(simple_function)))
 }
@@ -0,0 +1,99 @@
+mod client;
+mod function;
+mod schema;
+mod transport;
+
+pub use client::McpClient;
+pub use function::McpFunction;
+pub use schema::convert_input_schema;
+
+use std::{
+    collections::{HashMap, HashSet},
+    io::Error as IoError,
+    sync::Arc,
+    time::Duration,
+};
+
+use futures::future::join_all;
+use lnb_common::config::tools::ConfigToolsMcpServer;
+use lnb_core::interface::function::{ArcFunction, Function as _};
+use reqwest::Error as ReqwestError;
+use serde_json::Error as SerdeJsonError;
+use thiserror::Error as ThisError;
+use tokio::time::timeout;
+use tracing::{info, warn};
+
+/// 接続から tool 一覧の取得までに許容する時間。
+const INITIALIZE_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Debug, ThisError)]
+pub enum McpError {
+    #[error("io error: {0}")]
+    Io(#[from] IoError),
+
+    #[error("http error: {0}")]
+    Http(#[from] ReqwestError),
+
+    #[error("serialization error: {0}")]
+    Serialization(#[from] SerdeJsonError),
+
+    #[error("json-rpc error {code}: {message}")]
+    Rpc { code: i64, message: String },
+
+    #[error("server closed the connection")]
+    Closed,
+
+    #[error("initialization timed out")]
+    Timeout,
+
+    #[error("invalid response: {0}")]
+    InvalidResponse(String),
+
+    #[error("unsupported schema: {0}")]
+    UnsupportedSchema(String),
+
+    #[error("invalid tool name: {0}")]
+    InvalidToolName(String),
+}
+
+/// 設定された MCP サーバーに接続し、提供される tool を function として返す。
+/// 接続できなかったサーバーと、変換できなかったり名前が重複したりする tool は警告を出して無視する。
+pub async fn initialize_mcp_functions(configs: &HashMap<String, ConfigToolsMcpServer>) -> Vec<ArcFunction> {
+    let connections = configs.iter().map(|(name, config)| async move {
+        let connecting = async {
+            let client = McpClient::connect(name, config).await?;
+            let tools = client.list_tools().await?;
+            Ok::<_, McpError>((client, tools))
+        };
+        let result = timeout(INITIALIZE_TIMEOUT, connecting)
+            .await
+            .unwrap_or(Err(McpError::Timeout));
+        (name, result)
+    });
+
+    let mut functions: Vec<ArcFunction> = vec![];
+    let mut function_names = HashSet::new();
+    for (name, result) in join_all(connections).await {
+        let (client, tools) = match result {
+            Ok(connected) => connected,
+            Err(e) => {
+                warn!("failed to connect MCP server {name}: {e}");
+                continue;
+            }
+        };
+
+        let tool_count = tools.len();
+        for tool in tools {
+            let tool_name = tool.name.clone();
+            match McpFunction::new(client.clone(), tool) {
+                Ok(function) if !function_names.insert(function.get_descriptor().name) => {
+                    warn!("MCP tool {name}/{tool_name} ignored: duplicate function name");
+                }
+                Ok(function) => functions.push(Arc::new(function)),
+                Err(e) => warn!("MCP tool {name}/{tool_name} ignored: {e}"),
+            }
+        }
+        info!("MCP server connected: {name} ({tool_count} tools)");
+    }
+    functions
+}
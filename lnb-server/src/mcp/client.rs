@@ -0,0 +1,178 @@
+use crate::mcp::{McpError, transport::McpTransport};
+
+use std::sync::Arc;
+
+use lnb_common::config::tools::ConfigToolsMcpServer;
+use serde::Deserialize;
+use serde_json::{Value, json};
+use tracing::debug;
+
+const PROTOCOL_VERSION: &str = "2025-06-18";
+
+/// MCP サーバーとのセッション。
+#[derive(Clone)]
+pub struct McpClient(Arc<McpClientInner>);
+
+struct McpClientInner {
+    name: String,
+    transport: McpTransport,
+}
+
+impl McpClient {
+    /// 接続して initialize まで済ませる。
+    pub async fn connect(name: &str, config: &ConfigToolsMcpServer) -> Result<McpClient, McpError> {
+        let transport = McpTransport::connect(config).await?;
+        let initialized = transport
+            .request(
+                "initialize",
+                json!({
+                    "protocolVersion": PROTOCOL_VERSION,
+                    "capabilities": {},
+                    "clientInfo": {
+                        "name": env!("CARGO_PKG_NAME"),
+                        "version": env!("CARGO_PKG_VERSION"),
+                    },
+                }),
+            )
+            .await?;
+        debug!("MCP server {name} initialized: {}", initialized["serverInfo"]);
+        transport.notify("notifications/initialized", json!({})).await?;
+
+        Ok(McpClient(Arc::new(McpClientInner {
+            name: name.to_string(),
+            transport,
+        })))
+    }
+
+    pub fn name(&self) -> &str {
+        &self.0.name
+    }
+
+    /// 提供されている tool をすべて取得する。
+    pub async fn list_tools(&self) -> Result<Vec<McpTool>, McpError> {
+        let mut tools = vec![];
+        let mut cursor: Option<String> = None;
+        loop {
+            let params = match &cursor {
+                Some(c) => json!({ "cursor": c }),
+                None => json!({}),
+            };
+            let result = self.0.transport.request("tools/list", params).await?;
+            let page: McpToolsPage = serde_json::from_value(result)?;
+            tools.extend(page.tools);
+
+            cursor = page.next_cursor;
+            if cursor.is_none() {
+                break;
+            }
+        }
+        Ok(tools)
+    }
+
+    pub async fn call_tool(&self, name: &str, arguments: Value) -> Result<McpToolResult, McpError> {
+        let params = json!({
+            "name": name,
+            "arguments": arguments,
+        });
+        let result = self.0.transport.request("tools/call", params).await?;
+        Ok(serde_json::from_value(result)?)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct McpToolsPage {
+    tools: Vec<McpTool>,
+    next_cursor: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct McpTool {
+    pub name: String,
+    pub description: Option<String>,
+    pub input_schema: Value,
+}
+
+/// `tools/call` の結果。
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct McpToolResult {
+    #[serde(default)]
+    pub content: Vec<McpContent>,
+
+    pub structured_content: Option<Value>,
+
+    #[serde(default)]
+    pub is_error: bool,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "snake_case", tag = "type")]
+pub enum McpContent {
+    Text {
+        text: String,
+    },
+    Image {
+        /// Base64 エンコードされた画像。
+        data: String,
+    },
+
+    /// 音声やリソースなど、こちらで扱えないもの。
+    #[serde(other)]
+    Unsupported,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::HashMap;
+
+    /// initialize と 2 ページに分かれた tools/list 、 tools/call に応答する。
+    const STUB_SERVER: &str = r#"
+        while read line; do
+            id=$(echo "$line" | sed 's/.*"id":\([0-9]*\).*/\1/')
+            case "$line" in
+                *'"method":"initialize"'*)
+                    printf '{"jsonrpc":"2.0","id":%s,"result":{"serverInfo":{"name":"stub"}}}\n' "$id" ;;
+                *'"method":"notifications/initialized"'*)
+                    echo '{"jsonrpc":"2.0","method":"notifications/message","params":{}}' ;;
+                *'"cursor":"next"'*)
+                    printf '{"jsonrpc":"2.0","id":%s,"result":{"tools":[{"name":"echo","inputSchema":{}}]}}\n' "$id" ;;
+                *'"method":"tools/list"'*)
+                    printf '{"jsonrpc":"2.0","id":%s,"result":{"tools":[{"name":"now","description":"現在時刻","inputSchema":{}}],"nextCursor":"next"}}\n' "$id" ;;
+                *'"method":"tools/call"'*)
+                    printf '{"jsonrpc":"2.0","id":%s,"result":{"content":[{"type":"text","text":"hello"},{"type":"audio","data":""}],"structuredContent":{"request":%s}}}\n' "$id" "$line" ;;
+            esac
+        done
+    "#;
+
+    #[tokio::test]
+    async fn round_trips_over_stdio() {
+        let config = ConfigToolsMcpServer::Stdio {
+            command: "sh".to_string(),
+            args: vec!["-c".to_string(), STUB_SERVER.to_string()],
+            env: HashMap::new(),
+        };
+        let client = McpClient::connect("stub", &config).await.expect("should connect");
+        assert_eq!(client.name(), "stub");
+
+        let tools = client.list_tools().await.expect("should list tools");
+        let names: Vec<_> = tools.iter().map(|t| t.name.as_str()).collect();
+        assert_eq!(names, ["now", "echo"]);
+        assert_eq!(tools[0].description.as_deref(), Some("現在時刻"));
+
+        let result = client
+            .call_tool("echo", json!({ "text": "hello" }))
+            .await
+            .expect("should call tool");
+        assert!(matches!(&result.content[..], [McpContent::Text { text }, McpContent::Unsupported] if text == "hello"));
+        let structured = result.structured_content.expect("should have structured content");
+        assert_eq!(
+            structured["request"]["params"],
+            json!({ "name": "echo", "arguments": { "text": "hello" } })
+        );
+        assert!(!result.is_error);
+    }
+}
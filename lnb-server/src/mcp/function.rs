@@ -0,0 +1,94 @@
+use crate::mcp::{
+    McpClient, McpError,
+    client::{McpContent, McpTool},
+    schema::convert_input_schema,
+};
+
+use base64::{Engine, prelude::BASE64_STANDARD};
+use futures::{FutureExt, future::BoxFuture};
+use lnb_core::{
+    context::Context,
+    error::FunctionError,
+    interface::{
+        MessageContext,
+        function::{Function, FunctionDescriptor, FunctionResponse, is_valid_function_name},
+    },
+    model::{
+        conversation::{ConversationAttachment, IncompleteConversation},
+        message::MessageToolCalling,
+    },
+};
+use serde_json::{Value, json};
+
+/// MCP サーバーの tool を function として公開する。
+/// function 名は `{サーバー名}__{tool 名}` になり、 function 名として使えないものは拒否する。
+pub struct McpFunction {
+    client: McpClient,
+    tool_name: String,
+    descriptor: FunctionDescriptor,
+}
+
+impl McpFunction {
+    pub fn new(client: McpClient, tool: McpTool) -> Result<McpFunction, McpError> {
+        let name = format!("{}__{}", client.name(), tool.name);
+        if !is_valid_function_name(&name) {
+            return Err(McpError::InvalidToolName(name));
+        }
+        let descriptor = FunctionDescriptor {
+            name,
+            description: tool.description.unwrap_or_default(),
+            parameters: convert_input_schema(&tool.input_schema)?,
+        };
+        Ok(McpFunction {
+            client,
+            tool_name: tool.name,
+            descriptor,
+        })
+    }
+
+    async fn call_tool(&self, arguments: Value) -> Result<FunctionResponse, FunctionError> {
+        let result = self
+            .client
+            .call_tool(&self.tool_name, arguments)
+            .await
+            .map_err(FunctionError::by_external)?;
+
+        let mut texts = vec![];
+        let mut attachments = vec![];
+        for content in result.content {
+            match content {
+                McpContent::Text { text } => texts.push(text),
+                McpContent::Image { data } => {
+                    let bytes = BASE64_STANDARD.decode(data).map_err(FunctionError::by_serialization)?;
+                    attachments.push(ConversationAttachment::Image {
+                        bytes,
+                        description: None,
+                    });
+                }
+                McpContent::Unsupported => (),
+            }
+        }
+
+        if result.is_error {
+            return Err(FunctionError::by_external(texts.join("\n")));
+        }
+        let result = result.structured_content.unwrap_or_else(|| json!({ "content": texts }));
+        Ok(FunctionResponse { result, attachments })
+    }
+}
+
+impl Function for McpFunction {
+    fn get_descriptor(&self) -> FunctionDescriptor {
+        self.descriptor.clone()
+    }
+
+    fn call<'a>(
+        &'a self,
+        _ctx: &'a Context,
+        _message_ctx: &'a MessageContext,
+        _incomplete: &'a IncompleteConversation,
+        tool_calling: MessageToolCalling,
+    ) -> BoxFuture<'a, Result<FunctionResponse, FunctionError>> {
+        async move { self.call_tool(tool_calling.arguments).await }.boxed()
+    }
+}
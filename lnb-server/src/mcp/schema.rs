@@ -0,0 +1,229 @@
+use crate::mcp::McpError;
+
+use lnb_core::model::schema::{DescribedSchema, StringFormat};
+use serde_json::{Map, Value};
+
+/// tool の inputSchema (JSON Schema) を `DescribedSchema` に変換する。
+/// `$ref` など `DescribedSchema` で表現できないものを含む場合は失敗する。
+pub fn convert_input_schema(input_schema: &Value) -> Result<DescribedSchema, McpError> {
+    convert(input_schema, "parameters", "引数")
+}
+
+fn convert(schema: &Value, name: &str, fallback_description: &str) -> Result<DescribedSchema, McpError> {
+    let Some(object) = schema.as_object() else {
+        return Err(unsupported(name, "schema must be an object"));
+    };
+    let description = object
+        .get("description")
+        .and_then(Value::as_str)
+        .unwrap_or(fallback_description);
+
+    let (type_name, mut nullable) = match object.get("type") {
+        Some(Value::String(t)) => (Some(t.as_str()), false),
+        Some(Value::Array(types)) => {
+            let non_null: Vec<_> = types
+                .iter()
+                .filter_map(Value::as_str)
+                .filter(|t| *t != "null")
+                .collect();
+            match non_null[..] {
+                [t] => (Some(t), non_null.len() < types.len()),
+                _ => return Err(unsupported(name, "multiple types")),
+            }
+        }
+        None => (None, false),
+        Some(_) => return Err(unsupported(name, "invalid type")),
+    };
+
+    let converted = if let Some(variants) = object.get("anyOf").or_else(|| object.get("oneOf")) {
+        let Some(variants) = variants.as_array() else {
+            return Err(unsupported(name, "invalid anyOf"));
+        };
+        let mut converted_variants = vec![];
+        for variant in variants {
+            if variant.get("type").and_then(Value::as_str) == Some("null") {
+                nullable = true;
+                continue;
+            }
+            converted_variants.push(convert(variant, name, description)?);
+        }
+        match converted_variants.len() {
+            1 => converted_variants.pop().expect("should have an item"),
+            _ => DescribedSchema::union(name, description, converted_variants),
+        }
+    } else if let Some(variants) = object.get("enum") {
+        let variants: Option<Option<Vec<_>>> = variants
+            .as_array()
+            .map(|vs| vs.iter().filter(|v| !v.is_null()).map(Value::as_str).collect());
+        match variants {
+            Some(Some(variants)) => DescribedSchema::string_enum(name, description, variants),
+            _ => return Err(unsupported(name, "enum must consist of strings")),
+        }
+    } else {
+        match type_name {
+            Some("integer") => DescribedSchema::integer(name, description),
+            Some("number") => DescribedSchema::float(name, description),
+            Some("boolean") => DescribedSchema::boolean(name, description),
+            Some("string") => DescribedSchema::string(name, description),
+            Some("array") => {
+                let Some(items) = object.get("items") else {
+                    return Err(unsupported(name, "array without items"));
+                };
+                DescribedSchema::array(name, description, convert(items, "item", description)?)
+            }
+            Some("object") => convert_object(object, name, description)?,
+            None if object.contains_key("properties") => convert_object(object, name, description)?,
+            _ => return Err(unsupported(name, "unknown type")),
+        }
+    };
+
+    let mut converted = apply_constraints(converted, object, name)?;
+    if nullable {
+        converted = converted.as_nullable();
+    }
+    Ok(converted)
+}
+
+fn convert_object(object: &Map<String, Value>, name: &str, description: &str) -> Result<DescribedSchema, McpError> {
+    let required: Vec<_> = object
+        .get("required")
+        .and_then(Value::as_array)
+        .map(|r| r.iter().filter_map(Value::as_str).collect())
+        .unwrap_or_default();
+    let properties = match object.get("properties") {
+        Some(Value::Object(properties)) => properties.iter().collect(),
+        Some(_) => return Err(unsupported(name, "invalid properties")),
+        None => vec![],
+    };
+
+    let mut fields = vec![];
+    for (field_name, field_schema) in properties {
+        let field = convert(field_schema, field_name, "")?;
+        match required.contains(&field_name.as_str()) {
+            true => fields.push(field),
+            false => fields.push(field.as_omittable()),
+        }
+    }
+    Ok(DescribedSchema::object(name, description, fields))
+}
+
+fn apply_constraints(
+    mut schema: DescribedSchema,
+    object: &Map<String, Value>,
+    name: &str,
+) -> Result<DescribedSchema, McpError> {
+    if let Some(minimum) = object.get("minimum").and_then(Value::as_f64) {
+        schema = schema.with_minimum(minimum);
+    }
+    if let Some(maximum) = object.get("maximum").and_then(Value::as_f64) {
+        schema = schema.with_maximum(maximum);
+    }
+    // 知らない format は検証できないので捨てる
+    if let Some(format) = object
+        .get("format")
+        .and_then(|f| serde_json::from_value::<StringFormat>(f.clone()).ok())
+    {
+        schema = schema.with_format(format);
+    }
+    if let Some(pattern) = object.get("pattern").and_then(Value::as_str) {
+        schema = schema.with_pattern(pattern);
+        if schema.check_patterns().is_err() {
+            return Err(unsupported(name, "invalid pattern"));
+        }
+    }
+    if let Some(min_items) = object.get("minItems").and_then(Value::as_u64) {
+        schema = schema.with_min_items(min_items as usize);
+    }
+    if let Some(max_items) = object.get("maxItems").and_then(Value::as_u64) {
+        schema = schema.with_max_items(max_items as usize);
+    }
+    if let Some(default) = object.get("default") {
+        schema = schema.with_default(default.clone());
+    }
+    Ok(schema)
+}
+
+fn unsupported(name: &str, reason: &str) -> McpError {
+    McpError::UnsupportedSchema(format!("{name}: {reason}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use serde_json::json;
+
+    #[test]
+    fn converts_object_schema() {
+        let input_schema = json!({
+            "type": "object",
+            "properties": {
+                "city": { "type": "string", "description": "都市名" },
+                "days": { "type": "integer", "minimum": 1, "maximum": 7 },
+                "unit": { "type": ["string", "null"], "enum": ["celsius", "fahrenheit"] },
+            },
+            "required": ["city", "unit"],
+        });
+        let expected = DescribedSchema::object(
+            "parameters",
+            "引数",
+            vec![
+                DescribedSchema::string("city", "都市名"),
+                DescribedSchema::integer("days", "")
+                    .with_minimum(1.0)
+                    .with_maximum(7.0)
+                    .as_omittable(),
+                DescribedSchema::string_enum("unit", "", ["celsius", "fahrenheit"]).as_nullable(),
+            ],
+        );
+        assert_eq!(convert_input_schema(&input_schema).unwrap(), expected);
+    }
+
+    #[test]
+    fn converts_any_of() {
+        let input_schema = json!({
+            "type": "object",
+            "properties": {
+                "at": {
+                    "anyOf": [
+                        { "type": "string", "format": "date-time" },
+                        { "type": "string", "format": "date" },
+                        { "type": "null" },
+                    ],
+                },
+            },
+            "required": ["at"],
+        });
+        let expected = DescribedSchema::object(
+            "parameters",
+            "引数",
+            vec![
+                DescribedSchema::union(
+                    "at",
+                    "",
+                    vec![
+                        DescribedSchema::string("at", "").with_format(StringFormat::DateTime),
+                        DescribedSchema::string("at", "").with_format(StringFormat::Date),
+                    ],
+                )
+                .as_nullable(),
+            ],
+        );
+        assert_eq!(convert_input_schema(&input_schema).unwrap(), expected);
+    }
+
+    #[test]
+    fn rejects_unsupported_schema() {
+        let input_schema = json!({
+            "type": "object",
+            "properties": { "node": { "$ref": "#/definitions/node" } },
+        });
+        assert!(convert_input_schema(&input_schema).is_err());
+
+        let input_schema = json!({
+            "type": "object",
+            "properties": { "code": { "type": "string", "pattern": "(" } },
+        });
+        assert!(convert_input_schema(&input_schema).is_err());
+    }
+}
@@ -0,0 +1,438 @@
+use crate::mcp::McpError;
+
+use std::{
+    collections::HashMap,
+    process::Stdio,
+    sync::{
+        Arc, Mutex as StdMutex,
+        atomic::{AtomicU64, Ordering},
+    },
+};
+
+use lnb_common::config::tools::ConfigToolsMcpServer;
+use lnb_core::APP_USER_AGENT;
+use reqwest::{
+    Client, ClientBuilder,
+    header::{ACCEPT, CONTENT_TYPE, HeaderMap, HeaderName, HeaderValue},
+};
+use serde::Deserialize;
+use serde_json::{Value, json};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    process::{Child, ChildStdin, ChildStdout, Command},
+    spawn,
+    sync::{Mutex, oneshot},
+};
+use tracing::{debug, warn};
+
+const SESSION_ID_HEADER: &str = "mcp-session-id";
+
+type PendingRequests = Arc<StdMutex<Option<HashMap<u64, oneshot::Sender<Result<Value, McpError>>>>>>;
+
+/// JSON-RPC 2.0 のメッセージの送受信。
+pub enum McpTransport {
+    Stdio(StdioTransport),
+    Http(HttpTransport),
+}
+
+impl McpTransport {
+    pub async fn connect(config: &ConfigToolsMcpServer) -> Result<McpTransport, McpError> {
+        match config {
+            ConfigToolsMcpServer::Stdio { command, args, env } => {
+                Ok(McpTransport::Stdio(StdioTransport::spawn(command, args, env)?))
+            }
+            ConfigToolsMcpServer::Http { url, headers } => Ok(McpTransport::Http(HttpTransport::new(url, headers)?)),
+        }
+    }
+
+    pub async fn request(&self, method: &str, params: Value) -> Result<Value, McpError> {
+        match self {
+            McpTransport::Stdio(t) => t.request(method, params).await,
+            McpTransport::Http(t) => t.request(method, params).await,
+        }
+    }
+
+    pub async fn notify(&self, method: &str, params: Value) -> Result<(), McpError> {
+        let message = json!({
+            "jsonrpc": "2.0",
+            "method": method,
+            "params": params,
+        });
+        match self {
+            McpTransport::Stdio(t) => t.send(&message).await,
+            McpTransport::Http(t) => t.post(&message).await.map(|_| ()),
+        }
+    }
+}
+
+/// サーバーから届いたメッセージ。応答・リクエスト・通知のいずれか。
+#[derive(Debug, Deserialize)]
+struct IncomingMessage {
+    id: Option<Value>,
+    method: Option<String>,
+    result: Option<Value>,
+    error: Option<IncomingError>,
+}
+
+#[derive(Debug, Deserialize)]
+struct IncomingError {
+    code: i64,
+    message: String,
+}
+
+impl IncomingMessage {
+    fn into_result(self) -> Result<Value, McpError> {
+        match (self.result, self.error) {
+            (_, Some(e)) => Err(McpError::Rpc {
+                code: e.code,
+                message: e.message,
+            }),
+            (Some(result), None) => Ok(result),
+            (None, None) => Err(McpError::InvalidResponse("neither result nor error".to_string())),
+        }
+    }
+}
+
+/// 子プロセスの標準入出力に改行区切りの JSON を流す。
+pub struct StdioTransport {
+    _child: Child,
+    stdin: Arc<Mutex<ChildStdin>>,
+    pending: PendingRequests,
+    next_id: AtomicU64,
+}
+
+impl StdioTransport {
+    fn spawn(command: &str, args: &[String], env: &HashMap<String, String>) -> Result<StdioTransport, McpError> {
+        let mut child = Command::new(command)
+            .args(args)
+            .envs(env)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::inherit())
+            .kill_on_drop(true)
+            .spawn()?;
+        let stdin = Arc::new(Mutex::new(child.stdin.take().ok_or(McpError::Closed)?));
+        let stdout = child.stdout.take().ok_or(McpError::Closed)?;
+
+        let pending: PendingRequests = Arc::new(StdMutex::new(Some(HashMap::new())));
+        spawn(read_stdout(stdout, stdin.clone(), pending.clone()));
+
+        Ok(StdioTransport {
+            _child: child,
+            stdin,
+            pending,
+            next_id: AtomicU64::new(1),
+        })
+    }
+
+    async fn request(&self, method: &str, params: Value) -> Result<Value, McpError> {
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        let (sender, receiver) = oneshot::channel();
+        {
+            let mut locked = self.pending.lock().expect("poisoned");
+            let Some(pending) = locked.as_mut() else {
+                return Err(McpError::Closed);
+            };
+            pending.insert(id, sender);
+        }
+
+        self.send(&json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "method": method,
+            "params": params,
+        }))
+        .await?;
+        receiver.await.map_err(|_| McpError::Closed)?
+    }
+
+    async fn send(&self, message: &Value) -> Result<(), McpError> {
+        write_line(&self.stdin, message).await
+    }
+}
+
+async fn write_line(stdin: &Mutex<ChildStdin>, message: &Value) -> Result<(), McpError> {
+    let mut line = serde_json::to_vec(message)?;
+    line.push(b'\n');
+
+    let mut locked = stdin.lock().await;
+    locked.write_all(&line).await?;
+    locked.flush().await?;
+    Ok(())
+}
+
+/// 応答を待っているリクエストに振り分ける。
+/// プロセスが終了したら待っているリクエストをすべて失敗させる。
+async fn read_stdout(stdout: ChildStdout, stdin: Arc<Mutex<ChildStdin>>, pending: PendingRequests) {
+    let mut lines = BufReader::new(stdout).lines();
+    while let Ok(Some(line)) = lines.next_line().await {
+        let message: IncomingMessage = match serde_json::from_str(&line) {
+            Ok(m) => m,
+            Err(e) => {
+                warn!("ignoring malformed MCP message: {e}");
+                continue;
+            }
+        };
+
+        match (message.id.clone(), message.method.clone()) {
+            // サーバーからのリクエストは ping だけ応答する
+            (Some(id), Some(method)) => {
+                let response = match method.as_str() {
+                    "ping" => json!({ "jsonrpc": "2.0", "id": id, "result": {} }),
+                    _ => json!({
+                        "jsonrpc": "2.0",
+                        "id": id,
+                        "error": { "code": -32601, "message": "method not found" },
+                    }),
+                };
+                if let Err(e) = write_line(&stdin, &response).await {
+                    warn!("failed to respond to MCP request: {e}");
+                }
+            }
+            (None, Some(method)) => debug!("MCP notification: {method}"),
+            (Some(id), None) => {
+                let sender = id
+                    .as_u64()
+                    .and_then(|id| pending.lock().expect("poisoned").as_mut()?.remove(&id));
+                match sender {
+                    Some(sender) => {
+                        sender.send(message.into_result()).ok();
+                    }
+                    None => warn!("unexpected MCP response id: {id}"),
+                }
+            }
+            (None, None) => warn!("ignoring MCP message without id and method"),
+        }
+    }
+
+    let closed = pending.lock().expect("poisoned").take().unwrap_or_default();
+    for (_, sender) in closed {
+        sender.send(Err(McpError::Closed)).ok();
+    }
+}
+
+/// Streamable HTTP の POST で送受信する。
+/// 応答が SSE で返ってきた場合もストリームの終わりまで読んでから処理する。
+pub struct HttpTransport {
+    client: Client,
+    url: String,
+    session_id: StdMutex<Option<String>>,
+    next_id: AtomicU64,
+}
+
+impl HttpTransport {
+    fn new(url: &str, headers: &HashMap<String, String>) -> Result<HttpTransport, McpError> {
+        let mut default_headers = HeaderMap::new();
+        for (name, value) in headers {
+            let name = HeaderName::try_from(name).map_err(|e| McpError::InvalidResponse(e.to_string()))?;
+            let value = HeaderValue::try_from(value).map_err(|e| McpError::InvalidResponse(e.to_string()))?;
+            default_headers.insert(name, value);
+        }
+        let client = ClientBuilder::new()
+            .user_agent(APP_USER_AGENT)
+            .default_headers(default_headers)
+            .build()?;
+
+        Ok(HttpTransport {
+            client,
+            url: url.to_string(),
+            session_id: StdMutex::new(None),
+            next_id: AtomicU64::new(1),
+        })
+    }
+
+    async fn request(&self, method: &str, params: Value) -> Result<Value, McpError> {
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        let messages = self
+            .post(&json!({
+                "jsonrpc": "2.0",
+                "id": id,
+                "method": method,
+                "params": params,
+            }))
+            .await?;
+
+        let response = messages
+            .into_iter()
+            .find(|m| m.method.is_none() && m.id.as_ref().and_then(Value::as_u64) == Some(id));
+        match response {
+            Some(response) => response.into_result(),
+            None => Err(McpError::InvalidResponse(format!("no response for request {id}"))),
+        }
+    }
+
+    async fn post(&self, message: &Value) -> Result<Vec<IncomingMessage>, McpError> {
+        let mut request = self
+            .client
+            .post(&self.url)
+            .header(ACCEPT, "application/json, text/event-stream")
+            .json(message);
+        if let Some(session_id) = self.session_id.lock().expect("poisoned").as_deref() {
+            request = request.header(SESSION_ID_HEADER, session_id);
+        }
+
+        let response = request.send().await?.error_for_status()?;
+        if let Some(session_id) = response.headers().get(SESSION_ID_HEADER).and_then(|v| v.to_str().ok()) {
+            *self.session_id.lock().expect("poisoned") = Some(session_id.to_string());
+        }
+
+        let is_sse = response
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .is_some_and(|ct| ct.starts_with("text/event-stream"));
+        let body = response.text().await?;
+        if body.trim().is_empty() {
+            return Ok(vec![]);
+        }
+
+        if is_sse {
+            parse_sse_messages(&body)
+        } else {
+            Ok(vec![serde_json::from_str(&body)?])
+        }
+    }
+}
+
+/// SSE の各イベントの data を JSON-RPC のメッセージとして読む。
+fn parse_sse_messages(body: &str) -> Result<Vec<IncomingMessage>, McpError> {
+    let mut messages = vec![];
+    for event in body.replace("\r\n", "\n").split("\n\n") {
+        let data: Vec<_> = event
+            .lines()
+            .filter_map(|l| l.strip_prefix("data:"))
+            .map(|d| d.strip_prefix(' ').unwrap_or(d))
+            .collect();
+        if data.is_empty() {
+            continue;
+        }
+        messages.push(serde_json::from_str(&data.join("\n"))?);
+    }
+    Ok(messages)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::mock_server::{MockResponse, serve};
+
+    use tokio::join;
+
+    /// 2 つのリクエストを受け取ってから ping を送り、逆順に応答する。
+    const SWAPPING_SERVER: &str = r#"
+        read a
+        read b
+        echo '{"jsonrpc":"2.0","id":"server","method":"ping"}'
+        read pong
+        id_a=$(echo "$a" | sed 's/.*"id":\([0-9]*\).*/\1/')
+        id_b=$(echo "$b" | sed 's/.*"id":\([0-9]*\).*/\1/')
+        printf '{"jsonrpc":"2.0","id":%s,"result":{"echo":%s}}\n' "$id_b" "$b"
+        printf '{"jsonrpc":"2.0","id":%s,"result":{"echo":%s,"pong":%s}}\n' "$id_a" "$a" "$pong"
+    "#;
+
+    fn spawn_shell(script: &str) -> StdioTransport {
+        let args = ["-c".to_string(), script.to_string()];
+        StdioTransport::spawn("sh", &args, &HashMap::new()).expect("should spawn")
+    }
+
+    #[tokio::test]
+    async fn stdio_routes_responses_by_id() {
+        let transport = spawn_shell(SWAPPING_SERVER);
+        let (a, b) = join!(
+            transport.request("first", json!({ "n": 1 })),
+            transport.request("second", json!({ "n": 2 })),
+        );
+        let (a, b) = (a.expect("should respond"), b.expect("should respond"));
+
+        let mut echoed = [a["echo"]["method"].clone(), b["echo"]["method"].clone()];
+        echoed.sort_by_key(|m| m.to_string());
+        assert_eq!(echoed, [json!("first"), json!("second")]);
+        assert_eq!(a["echo"]["params"]["n"], json!(1));
+        assert_eq!(b["echo"]["params"]["n"], json!(2));
+
+        let pong = [&a, &b]
+            .into_iter()
+            .find_map(|r| r.get("pong"))
+            .expect("should answer ping");
+        assert_eq!(pong, &json!({ "jsonrpc": "2.0", "id": "server", "result": {} }));
+    }
+
+    #[tokio::test]
+    async fn stdio_fails_pending_requests_on_eof() {
+        let transport = spawn_shell("read request");
+        let result = transport.request("never_answered", json!({})).await;
+        assert!(matches!(result, Err(McpError::Closed)), "{result:?}");
+    }
+
+    #[tokio::test]
+    async fn stdio_reports_rpc_errors() {
+        let transport = spawn_shell(
+            r#"
+            read request
+            id=$(echo "$request" | sed 's/.*"id":\([0-9]*\).*/\1/')
+            printf '{"jsonrpc":"2.0","id":%s,"error":{"code":-32602,"message":"bad params"}}\n' "$id"
+            "#,
+        );
+        let result = transport.request("tools/call", json!({})).await;
+        assert!(
+            matches!(result, Err(McpError::Rpc { code: -32602, ref message }) if message == "bad params"),
+            "{result:?}"
+        );
+    }
+
+    #[tokio::test]
+    async fn http_keeps_session_id() {
+        let sse_body = concat!(
+            "event: message\n",
+            "data: {\"jsonrpc\":\"2.0\",\"method\":\"notifications/progress\",\"params\":{}}\n",
+            "\n",
+            "event: message\n",
+            "data: {\"jsonrpc\":\"2.0\",\"id\":2,\"result\":{\"tools\":[]}}\n",
+            "\n",
+        );
+        let (endpoint, server) = serve(vec![
+            MockResponse::json(json!({ "jsonrpc": "2.0", "id": 1, "result": {} }))
+                .with_header(SESSION_ID_HEADER, "session-1"),
+            MockResponse::text("text/event-stream", sse_body),
+        ])
+        .await;
+
+        let transport = HttpTransport::new(&endpoint, &HashMap::new()).expect("should build");
+        let initialized = transport
+            .request("initialize", json!({}))
+            .await
+            .expect("should respond");
+        assert_eq!(initialized, json!({}));
+        let listed = transport
+            .request("tools/list", json!({}))
+            .await
+            .expect("should respond");
+        assert_eq!(listed, json!({ "tools": [] }));
+
+        let requests = server.await.expect("should finish");
+        assert_eq!(requests[0].header(SESSION_ID_HEADER), None);
+        assert_eq!(requests[1].header(SESSION_ID_HEADER), Some("session-1"));
+        assert_eq!(requests[1].json()["method"], json!("tools/list"));
+    }
+
+    #[test]
+    fn parses_sse_messages() {
+        let body = concat!(
+            ": comment\r\n",
+            "\r\n",
+            "event: message\r\n",
+            "data: {\"jsonrpc\":\"2.0\",\r\n",
+            "data: \"id\":1,\"result\":{}}\r\n",
+            "\r\n",
+            "data:{\"jsonrpc\":\"2.0\",\"method\":\"notifications/message\"}\r\n",
+        );
+        let messages = parse_sse_messages(body).expect("should parse");
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0].id, Some(json!(1)));
+        assert_eq!(messages[0].result, Some(json!({})));
+        assert_eq!(messages[1].method.as_deref(), Some("notifications/message"));
+
+        assert!(parse_sse_messages("data: {broken\n\n").is_err());
+    }
+}
@@ -0,0 +1,151 @@
+use std::time::Duration;
+
+use serde_json::Value;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    spawn,
+    task::JoinHandle,
+    time::sleep,
+};
+
+/// モックサーバーが受け取ったリクエスト。
+#[derive(Debug, Clone)]
+pub struct MockRequest {
+    pub method: String,
+    pub target: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl MockRequest {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    pub fn json(&self) -> Value {
+        serde_json::from_slice(&self.body).expect("should be JSON")
+    }
+}
+
+/// モックサーバーが返す応答。
+/// `chunks` は間隔を空けて 1 つずつ書き込むので、クライアント側でも分かれて届く。
+#[derive(Debug, Clone)]
+pub struct MockResponse {
+    status: u16,
+    content_type: &'static str,
+    headers: Vec<(&'static str, String)>,
+    chunks: Vec<Vec<u8>>,
+}
+
+impl MockResponse {
+    pub fn json(body: Value) -> MockResponse {
+        MockResponse::text("application/json", &body.to_string())
+    }
+
+    pub fn text(content_type: &'static str, body: &str) -> MockResponse {
+        MockResponse::chunked(content_type, vec![body.as_bytes().to_vec()])
+    }
+
+    pub fn chunked(content_type: &'static str, chunks: Vec<Vec<u8>>) -> MockResponse {
+        MockResponse {
+            status: 200,
+            content_type,
+            headers: vec![],
+            chunks,
+        }
+    }
+
+    pub fn with_status(self, status: u16) -> MockResponse {
+        MockResponse { status, ..self }
+    }
+
+    pub fn with_header(mut self, name: &'static str, value: impl Into<String>) -> MockResponse {
+        self.headers.push((name, value.into()));
+        self
+    }
+}
+
+/// `responses` を 1 接続に 1 つずつ順に返すモックサーバーを立てる。
+/// 全部返し終えると受け取ったリクエストを返して終了する。
+pub async fn serve(responses: Vec<MockResponse>) -> (String, JoinHandle<Vec<MockRequest>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.expect("should bind");
+    let endpoint = format!("http://{}", listener.local_addr().expect("should have address"));
+
+    let handle = spawn(async move {
+        let mut requests = vec![];
+        for response in responses {
+            let (mut stream, _) = listener.accept().await.expect("should accept");
+            requests.push(read_request(&mut stream).await);
+            write_response(&mut stream, response).await;
+        }
+        requests
+    });
+
+    (endpoint, handle)
+}
+
+async fn read_request(stream: &mut TcpStream) -> MockRequest {
+    let mut received = vec![];
+    let mut buffer = [0u8; 4096];
+    loop {
+        let read = stream.read(&mut buffer).await.expect("should read");
+        if read == 0 {
+            panic!("connection closed before request completed");
+        }
+        received.extend_from_slice(&buffer[..read]);
+
+        let Some(header_end) = received.windows(4).position(|w| w == b"\r\n\r\n") else {
+            continue;
+        };
+        let header_text = String::from_utf8_lossy(&received[..header_end]).to_string();
+        let mut lines = header_text.split("\r\n");
+        let mut request_line = lines.next().expect("should have request line").split(' ');
+        let method = request_line.next().expect("should have method").to_string();
+        let target = request_line.next().expect("should have target").to_string();
+        let headers: Vec<_> = lines
+            .filter_map(|l| {
+                let (name, value) = l.split_once(':')?;
+                Some((name.to_string(), value.trim().to_string()))
+            })
+            .collect();
+        let content_length: usize = headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case("content-length"))
+            .and_then(|(_, v)| v.parse().ok())
+            .unwrap_or(0);
+
+        let body = &received[header_end + 4..];
+        if body.len() >= content_length {
+            return MockRequest {
+                method,
+                target,
+                headers,
+                body: body.to_vec(),
+            };
+        }
+    }
+}
+
+async fn write_response(stream: &mut TcpStream, response: MockResponse) {
+    // content-length を付けずに接続を閉じて終端とする
+    let headers: String = response
+        .headers
+        .iter()
+        .map(|(name, value)| format!("{name}: {value}\r\n"))
+        .collect();
+    let head = format!(
+        "HTTP/1.1 {} Mock\r\ncontent-type: {}\r\n{headers}connection: close\r\n\r\n",
+        response.status, response.content_type
+    );
+    stream.write_all(head.as_bytes()).await.expect("should write");
+    for chunk in response.chunks {
+        stream.write_all(&chunk).await.expect("should write");
+        stream.flush().await.expect("should flush");
+        sleep(Duration::from_millis(20)).await;
+    }
+    stream.shutdown().await.ok();
+}
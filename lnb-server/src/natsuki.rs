@@ -1,9 +1,14 @@
+mod context_window;
 mod function_store;
 mod inner;
 mod llm_cache;
+mod model_router;
+
+#[cfg(test)]
+mod tests;
 
 pub use function_store::FunctionStore;
-pub use llm_cache::LlmCache;
+pub use llm_cache::{LlmCache, LlmModelStatus};
 use lnb_common::config::assistant::ConfigAssistant;
 use lnb_rate_limiter::RateLimiter;
 
@@ -11,12 +16,18 @@ use crate::natsuki::inner::NatsukiInner;
 
 use std::sync::Arc;
 
-use futures::{FutureExt, future::BoxFuture};
+use futures::{FutureExt, channel::mpsc::UnboundedSender, future::BoxFuture};
 use lnb_core::{
     error::ServerError,
-    interface::{MessageContext, interception::BoxInterception, server::LnbServer, storage::BoxConversationStorage},
+    interface::{
+        MessageContext,
+        interception::BoxInterception,
+        server::{ConversationProgress, LnbServer},
+        storage::BoxConversationStorage,
+        time::DateTimeProvider,
+    },
     model::{
-        conversation::{ConversationId, ConversationUpdate},
+        conversation::{ConversationId, ConversationPersona, ConversationUpdate},
         message::Message,
     },
 };
@@ -32,6 +43,7 @@ impl Natsuki {
         function_store: function_store::FunctionStore,
         interceptions: impl IntoIterator<Item = BoxInterception>,
         assistant_identity: &ConfigAssistant,
+        datetime_provider: Arc<dyn DateTimeProvider>,
     ) -> Result<Natsuki, ServerError> {
         let inner = NatsukiInner::new(
             storage,
@@ -40,14 +52,15 @@ impl Natsuki {
             function_store,
             interceptions.into_iter().collect(),
             assistant_identity,
+            datetime_provider,
         )?;
         Ok(Natsuki(Arc::new(inner)))
     }
 }
 
 impl LnbServer for Natsuki {
-    fn new_conversation(&self) -> BoxFuture<'_, Result<ConversationId, ServerError>> {
-        async move { self.0.new_conversation().await }.boxed()
+    fn new_conversation(&self, persona: ConversationPersona) -> BoxFuture<'_, Result<ConversationId, ServerError>> {
+        async move { self.0.new_conversation(persona).await }.boxed()
     }
 
     fn restore_conversation<'a>(
@@ -73,7 +86,22 @@ impl LnbServer for Natsuki {
     ) -> BoxFuture<'_, Result<ConversationUpdate, ServerError>> {
         async move {
             self.0
-                .process_conversation(message_ctx, conversation_id, user_message)
+                .process_conversation(message_ctx, conversation_id, user_message, None)
+                .await
+        }
+        .boxed()
+    }
+
+    fn process_conversation_stream(
+        &self,
+        message_ctx: MessageContext,
+        conversation_id: ConversationId,
+        user_message: Vec<Message>,
+        progress_sender: UnboundedSender<ConversationProgress>,
+    ) -> BoxFuture<'_, Result<ConversationUpdate, ServerError>> {
+        async move {
+            self.0
+                .process_conversation(message_ctx, conversation_id, user_message, Some(progress_sender))
                 .await
         }
         .boxed()
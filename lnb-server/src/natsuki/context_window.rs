@@ -0,0 +1,264 @@
+use lnb_common::config::llm::ConfigLlmModelContext;
+use lnb_core::{
+    error::LlmError,
+    interface::llm::{ArcLlm, LlmUpdate, LlmUsage},
+    model::{
+        conversation::{Conversation, ConversationSummary, IncompleteConversation},
+        message::{Message, UserMessageContent},
+    },
+};
+use tracing::{debug, info, warn};
+
+/// 要約生成に使うシステムメッセージ。
+const SUMMARY_SYSTEM_PROMPT: &str = "\
+あなたは会話の要約を作成するアシスタントです。
+与えられた会話の記録を、後で会話を続けるために必要な事実・話題・約束事を落とさないように簡潔に要約してください。
+要約本文のみを出力してください。";
+
+/// 関数の結果を要約に含める際の最大文字数。
+const FUNCTION_RESULT_MAX_CHARS: usize = 200;
+
+/// 画像 1 枚あたりの概算トークン数。
+const IMAGE_TOKENS: usize = 800;
+
+/// メッセージ 1 件あたりの固定の概算トークン数。
+const MESSAGE_OVERHEAD_TOKENS: usize = 4;
+
+/// 会話がコンテキストの予算を超えていれば、古いターンを要約で置き換える。
+/// 要約の生成に失敗した場合は要約を更新せずに切り詰める。
+/// 要約を生成した場合はそれで消費したトークン数を返す。
+pub async fn fit_context_window(
+    incomplete_conversation: &mut IncompleteConversation,
+    context_config: &ConfigLlmModelContext,
+    summary_llm: &ArcLlm,
+) -> Option<LlmUsage> {
+    let total_tokens: usize = incomplete_conversation
+        .llm_sending_messages()
+        .map(estimate_message_tokens)
+        .sum();
+    if total_tokens <= context_config.budget_tokens {
+        return None;
+    }
+
+    let base_messages = incomplete_conversation.base_messages();
+    let system_count = incomplete_conversation.leading_system_count();
+    let start = incomplete_conversation
+        .summary()
+        .map_or(0, |s| s.covered)
+        .max(system_count);
+    let fixed_tokens: usize = base_messages[..system_count]
+        .iter()
+        .chain(incomplete_conversation.pushed_messages())
+        .map(estimate_message_tokens)
+        .sum();
+    let recent_budget =
+        ((context_config.budget_tokens as f64 * context_config.recent_ratio) as usize).saturating_sub(fixed_tokens);
+
+    let Some(cut) = find_cut(base_messages, start, recent_budget) else {
+        debug!("context exceeds budget ({total_tokens} tokens) but nothing to summarize");
+        return None;
+    };
+    info!(
+        "context exceeds budget ({total_tokens} / {} tokens), summarizing messages {start}..{cut}",
+        context_config.budget_tokens
+    );
+
+    let previous_summary = incomplete_conversation.summary().map(|s| s.text.as_str());
+    let transcript = render_transcript(previous_summary, &base_messages[start..cut]);
+    let (text, usage) = match summarize(summary_llm, transcript).await {
+        Ok((text, usage)) => (text, Some(usage)),
+        Err(e) => {
+            warn!("failed to summarize conversation, truncating instead: {e}");
+            (previous_summary.unwrap_or_default().to_string(), None)
+        }
+    };
+
+    incomplete_conversation.set_summary(ConversationSummary { covered: cut, text });
+    usage
+}
+
+/// `Message` の概算トークン数。
+/// ASCII は 4 文字で 1 トークン、それ以外は 1 文字で 1 トークンとして数える。
+pub fn estimate_message_tokens(message: &Message) -> usize {
+    let content_tokens = match message {
+        Message::System(system) => estimate_text_tokens(&system.0),
+        Message::User(user) if user.skip_llm => return 0,
+        Message::User(user) => user
+            .contents
+            .iter()
+            .map(|c| match c {
+                UserMessageContent::Text(text) => estimate_text_tokens(text),
+                UserMessageContent::ImageUrl(_) => IMAGE_TOKENS,
+            })
+            .sum(),
+        Message::FunctionCalls(calls) => calls
+            .0
+            .iter()
+            .map(|c| estimate_text_tokens(&c.name) + estimate_text_tokens(&c.arguments.to_string()))
+            .sum(),
+        Message::FunctionResponse(response) => {
+            estimate_text_tokens(&response.name) + estimate_text_tokens(&response.result.to_string())
+        }
+        Message::Assistant(assistant) if assistant.skip_llm => return 0,
+        Message::Assistant(assistant) => estimate_text_tokens(&assistant.text),
+    };
+    content_tokens + MESSAGE_OVERHEAD_TOKENS
+}
+
+/// 文字列の概算トークン数。
+pub fn estimate_text_tokens(text: &str) -> usize {
+    let (ascii, others) = text.chars().fold(
+        (0usize, 0usize),
+        |(a, o), c| if c.is_ascii() { (a + 1, o) } else { (a, o + 1) },
+    );
+    ascii.div_ceil(4) + others
+}
+
+/// 直近のメッセージが `recent_budget` に収まるように、要約で置き換える範囲の終端を決める。
+/// tool calling の組を分断しないよう、終端は `UserMessage` の直前か末尾に限る。
+fn find_cut(messages: &[Message], start: usize, recent_budget: usize) -> Option<usize> {
+    let mut recent_tokens = 0;
+    let mut cut = messages.len();
+    for (index, message) in messages.iter().enumerate().skip(start).rev() {
+        recent_tokens += estimate_message_tokens(message);
+        if recent_tokens > recent_budget {
+            break;
+        }
+        if matches!(message, Message::User(_)) {
+            cut = index;
+        }
+    }
+
+    (cut > start).then_some(cut)
+}
+
+/// 要約対象の会話をテキストに起こす。
+fn render_transcript(previous_summary: Option<&str>, messages: &[Message]) -> String {
+    let mut lines = vec![];
+    if let Some(summary) = previous_summary {
+        lines.push(format!("[これまでの要約]\n{summary}"));
+    }
+
+    for message in messages {
+        match message {
+            Message::System(system) => lines.push(format!("[システム] {}", system.0)),
+            Message::User(user) if user.skip_llm => continue,
+            Message::User(user) => {
+                let texts: Vec<_> = user
+                    .contents
+                    .iter()
+                    .map(|c| match c {
+                        UserMessageContent::Text(text) => text.as_str(),
+                        UserMessageContent::ImageUrl(_) => "(画像)",
+                    })
+                    .collect();
+                let name = user.name.as_deref().unwrap_or("ユーザー");
+                lines.push(format!("[{name}] {}", texts.join(" ")));
+            }
+            Message::FunctionCalls(calls) => {
+                for call in &calls.0 {
+                    lines.push(format!("[関数呼び出し] {}({})", call.name, call.arguments));
+                }
+            }
+            Message::FunctionResponse(response) => {
+                let result: String = response
+                    .result
+                    .to_string()
+                    .chars()
+                    .take(FUNCTION_RESULT_MAX_CHARS)
+                    .collect();
+                lines.push(format!("[関数の結果] {}: {result}", response.name));
+            }
+            Message::Assistant(assistant) if assistant.skip_llm => continue,
+            Message::Assistant(assistant) => lines.push(format!("[アシスタント] {}", assistant.text)),
+        }
+    }
+
+    lines.join("\n")
+}
+
+async fn summarize(llm: &ArcLlm, transcript: String) -> Result<(String, LlmUsage), LlmError> {
+    let conversation = Conversation::new_now(Some(Message::new_system(SUMMARY_SYSTEM_PROMPT)));
+    let mut summary_conversation = IncompleteConversation::start(conversation);
+    summary_conversation.extend_messages([Message::new_user(
+        [UserMessageContent::Text(transcript)],
+        None,
+        None,
+        false,
+    )]);
+
+    let response = llm.send_conversation(&summary_conversation, &[]).await?;
+    match response.update {
+        LlmUpdate::Finished(summary) | LlmUpdate::LengthCut(summary) => Ok((summary.text, response.usage)),
+        LlmUpdate::ToolCalling(_) | LlmUpdate::Filtered => Err(LlmError::ExpectationMismatch(
+            "summary response must be text".to_string(),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use lnb_core::model::message::MessageToolCalling;
+    use serde_json::json;
+
+    fn user(text: &str) -> Message {
+        Message::new_user([UserMessageContent::Text(text.to_string())], None, None, false)
+    }
+
+    fn assistant(text: &str) -> Message {
+        Message::new_assistant(text, false, None, false)
+    }
+
+    #[test]
+    fn estimates_ascii_and_japanese() {
+        assert_eq!(estimate_text_tokens("abcdefgh"), 2);
+        assert_eq!(estimate_text_tokens("abcde"), 2);
+        assert_eq!(estimate_text_tokens("こんにちは"), 5);
+    }
+
+    #[test]
+    fn cut_keeps_recent_turns() {
+        let messages = vec![
+            Message::new_system("system"),
+            user("old question"),
+            assistant("old answer"),
+            user("new question"),
+            assistant("new answer"),
+        ];
+        let recent_budget = estimate_message_tokens(&messages[3]) + estimate_message_tokens(&messages[4]);
+        assert_eq!(find_cut(&messages, 1, recent_budget), Some(3));
+    }
+
+    #[test]
+    fn cut_never_splits_tool_calling() {
+        let messages = vec![
+            Message::new_system("system"),
+            user("old question"),
+            assistant("old answer"),
+            user("what time is it?"),
+            Message::new_function_calls([MessageToolCalling {
+                id: "call".to_string(),
+                name: "get_time".to_string(),
+                arguments: json!({}),
+            }]),
+            Message::new_function_response("call", "get_time", json!({ "time": "12:00" })),
+            assistant("it's noon"),
+        ];
+
+        // 関数の結果と応答だけが収まる予算でも、その途中では切らない
+        let recent_budget = estimate_message_tokens(&messages[5]) + estimate_message_tokens(&messages[6]);
+        assert_eq!(find_cut(&messages, 1, recent_budget), Some(7));
+
+        let recent_budget = messages[3..].iter().map(estimate_message_tokens).sum();
+        assert_eq!(find_cut(&messages, 1, recent_budget), Some(3));
+    }
+
+    #[test]
+    fn cut_skips_already_summarized() {
+        let messages = vec![Message::new_system("system"), user("question"), assistant("answer")];
+        assert_eq!(find_cut(&messages, 3, 0), None);
+        assert_eq!(find_cut(&messages, 1, usize::MAX), None);
+    }
+}
@@ -1,50 +1,214 @@
-use std::{collections::HashMap, sync::Arc};
+use std::{collections::HashMap, sync::Arc, time::Duration};
 
+use lnb_common::config::tools::ConfigTools;
 use lnb_core::{
     context::Context,
     error::FunctionError,
     interface::{
         MessageContext,
-        function::{ArcFunction, FunctionDescriptor, FunctionResponse},
+        function::{ArcFunction, FunctionDescriptor, FunctionResponse, is_valid_function_name},
+    },
+    model::{
+        conversation::IncompleteConversation,
+        message::MessageToolCalling,
+        schema::{SchemaPatternError, SchemaValidationError},
+        user_role::UserRole,
     },
-    model::{conversation::IncompleteConversation, message::MessageToolCalling},
 };
+use serde_json::{Value, json};
+use thiserror::Error as ThisError;
+use tokio::time::timeout;
+
+/// `ConfigTools` でタイムアウトが指定されていない場合の秒数。
+const DEFAULT_TIMEOUT_SECONDS: f64 = 60.0;
 
 #[derive(Clone)]
 pub struct FunctionStore {
-    functions: Arc<HashMap<String, (ArcFunction, FunctionDescriptor)>>,
+    functions: Arc<HashMap<String, StoredFunction>>,
+}
+
+struct StoredFunction {
+    function: ArcFunction,
+    descriptor: FunctionDescriptor,
+    timeout: Duration,
+    required_scope: Option<String>,
+}
+
+impl StoredFunction {
+    fn is_visible_to(&self, role: &UserRole) -> bool {
+        match &self.required_scope {
+            Some(scope) => role.accepts(scope),
+            None => true,
+        }
+    }
 }
 
 impl FunctionStore {
-    pub fn new(functions: impl IntoIterator<Item = ArcFunction>) -> FunctionStore {
-        let functions = functions
-            .into_iter()
-            .map(|f| {
-                let descriptor = f.get_descriptor();
-                (descriptor.name.clone(), (f, descriptor))
-            })
-            .collect();
-        FunctionStore {
-            functions: Arc::new(functions),
+    pub fn new(
+        functions: impl IntoIterator<Item = ArcFunction>,
+        config: &ConfigTools,
+    ) -> Result<FunctionStore, FunctionStoreError> {
+        let default_timeout = config.default_timeout_seconds.unwrap_or(DEFAULT_TIMEOUT_SECONDS);
+        let mut stored_functions = HashMap::new();
+        for function in functions {
+            let descriptor = function.get_descriptor();
+            if !is_valid_function_name(&descriptor.name) {
+                return Err(FunctionStoreError::InvalidName(descriptor.name));
+            }
+            if stored_functions.contains_key(&descriptor.name) {
+                return Err(FunctionStoreError::DuplicateName(descriptor.name));
+            }
+            if let Err(source) = descriptor.parameters.check_patterns() {
+                return Err(FunctionStoreError::InvalidSchema {
+                    name: descriptor.name,
+                    source,
+                });
+            }
+            let policy = config.policies.get(&descriptor.name);
+            let timeout_seconds = policy.and_then(|p| p.timeout_seconds).unwrap_or(default_timeout);
+            let Ok(timeout) = Duration::try_from_secs_f64(timeout_seconds) else {
+                return Err(FunctionStoreError::InvalidTimeout(descriptor.name, timeout_seconds));
+            };
+            let stored = StoredFunction {
+                function,
+                timeout,
+                required_scope: policy.and_then(|p| p.required_scope.clone()),
+                descriptor,
+            };
+            stored_functions.insert(stored.descriptor.name.clone(), stored);
         }
+        Ok(FunctionStore {
+            functions: Arc::new(stored_functions),
+        })
     }
 
-    pub fn descriptors(&self) -> impl Iterator<Item = &FunctionDescriptor> {
-        self.functions.iter().map(|(_, (_, d))| d)
+    /// `role` に提示できる function の一覧。
+    pub fn descriptors(&self, role: &UserRole) -> impl Iterator<Item = &FunctionDescriptor> {
+        self.functions
+            .values()
+            .filter(|f| f.is_visible_to(role))
+            .map(|f| &f.descriptor)
     }
 
+    /// 引数の省略された値を埋め、スキーマで検証してから function を呼び出す。
+    /// ユーザーに提示していない function は存在しないものとして扱う。
     pub async fn find_call(
         &self,
-        tool_calling: MessageToolCalling,
+        mut tool_calling: MessageToolCalling,
         ctx: &Context,
         message_ctx: &MessageContext,
         incomplete: &IncompleteConversation,
-    ) -> Option<Result<FunctionResponse, FunctionError>> {
-        if let Some((function, _)) = self.functions.get(&tool_calling.name) {
-            let result = function.call(ctx, message_ctx, incomplete, tool_calling).await;
-            Some(result)
-        } else {
-            None
+    ) -> Result<FunctionResponse, FunctionCallError> {
+        let Some(stored) = self
+            .functions
+            .get(&tool_calling.name)
+            .filter(|f| f.is_visible_to(message_ctx.role()))
+        else {
+            return Err(FunctionCallError::NotFound(tool_calling.name));
+        };
+
+        let name = &stored.descriptor.name;
+        let parameters = &stored.descriptor.parameters;
+        parameters.fill_defaults(&mut tool_calling.arguments);
+        if let Err(source) = parameters.validate(&tool_calling.arguments) {
+            return Err(FunctionCallError::InvalidArguments {
+                name: name.clone(),
+                source,
+            });
+        }
+
+        let calling = stored.function.call(ctx, message_ctx, incomplete, tool_calling);
+        match timeout(stored.timeout, calling).await {
+            Ok(Ok(response)) => Ok(response),
+            Ok(Err(source)) => Err(FunctionCallError::Failed {
+                name: name.clone(),
+                source,
+            }),
+            Err(_) => Err(FunctionCallError::Timeout(name.clone(), stored.timeout)),
+        }
+    }
+}
+
+/// function の登録時のエラー。
+#[derive(Debug, ThisError)]
+pub enum FunctionStoreError {
+    #[error("invalid function name: {0}")]
+    InvalidName(String),
+
+    #[error("function {0} is registered more than once")]
+    DuplicateName(String),
+
+    #[error("invalid timeout for function {0}: {1} seconds")]
+    InvalidTimeout(String, f64),
+
+    #[error("invalid parameters schema for function {name}: {source}")]
+    InvalidSchema {
+        name: String,
+        #[source]
+        source: SchemaPatternError,
+    },
+}
+
+/// function の呼び出しの失敗。
+/// いずれも LLM に結果として返し、会話は続行させる。
+#[derive(Debug, ThisError)]
+pub enum FunctionCallError {
+    #[error("function {0} not found")]
+    NotFound(String),
+
+    #[error("function {name} called with invalid arguments: {source}")]
+    InvalidArguments {
+        name: String,
+        #[source]
+        source: SchemaValidationError,
+    },
+
+    #[error("function {0} timed out after {1:?}")]
+    Timeout(String, Duration),
+
+    #[error("function {name} failed: {source}")]
+    Failed {
+        name: String,
+        #[source]
+        source: FunctionError,
+    },
+}
+
+impl FunctionCallError {
+    /// LLM に返す結果としてのエラーオブジェクト。
+    pub fn to_result(&self) -> Value {
+        match self {
+            FunctionCallError::NotFound(name) => json!({
+                "status": "error",
+                "error": "not_found",
+                "message": format!("function {name} does not exist"),
+            }),
+            FunctionCallError::InvalidArguments { source, .. } => json!({
+                "status": "error",
+                "error": "invalid_arguments",
+                "message": source.to_string(),
+            }),
+            FunctionCallError::Timeout(_, timeout) => json!({
+                "status": "timeout",
+                "message": "function did not respond in time",
+                "timeout_seconds": timeout.as_secs_f64(),
+            }),
+            FunctionCallError::Failed {
+                source: FunctionError::Serialization(e),
+                ..
+            } => json!({
+                "status": "error",
+                "error": "invalid_arguments",
+                "message": e.to_string(),
+            }),
+            FunctionCallError::Failed {
+                source: FunctionError::External(e),
+                ..
+            } => json!({
+                "status": "error",
+                "error": "external",
+                "message": e.to_string(),
+            }),
         }
     }
 }
@@ -1,33 +1,68 @@
-use crate::natsuki::{function_store::FunctionStore, llm_cache::LlmCache};
+use crate::natsuki::{
+    context_window::fit_context_window,
+    function_store::{FunctionCallError, FunctionStore},
+    llm_cache::LlmCache,
+};
 
-use std::{collections::HashMap, iter::once, sync::Arc};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    iter::once,
+    sync::Arc,
+    time::Instant,
+};
 
+use futures::{
+    StreamExt,
+    channel::mpsc::{UnboundedSender, unbounded},
+    future::{join, join_all},
+};
 use lnb_common::{
-    config::assistant::ConfigAssistant, debug::debug_option_parsed, text_provider::InterpolatableTextProvider,
-    time_provider::BotDateTimeProvider,
+    config::{assistant::ConfigAssistant, llm::ConfigLlmLimitPolicy},
+    extension::ContextExt,
+    text_provider::InterpolatableTextProvider,
 };
 use lnb_core::{
     YMDHM_JAPANESE,
     context::Context,
-    error::{FunctionError, ServerError},
+    error::{LlmError, ServerError},
     interface::{
         MessageContext,
+        function::FunctionDescriptor,
         interception::{BoxInterception, InterceptionStatus},
-        llm::LlmUpdate,
-        storage::BoxConversationStorage,
+        llm::{ArcLlm, LlmDelta, LlmResponse, LlmUpdate, LlmUsage},
+        server::ConversationProgress,
+        storage::{BoxConversationStorage, ToolCallRecord},
+        text::TextProvider,
+        time::DateTimeProvider,
     },
     model::{
         conversation::{
-            Conversation, ConversationAttachment, ConversationId, ConversationUpdate, IncompleteConversation,
+            Conversation, ConversationAttachment, ConversationId, ConversationPersona, ConversationUpdate,
+            IncompleteConversation,
         },
         message::{AssistantMessage, FunctionResponseMessage, Message, MessageToolCalling},
     },
 };
 use lnb_rate_limiter::{RateLimiter, Rated};
-use time::{Duration, UtcDateTime};
+use serde_json::Value;
+use time::UtcDateTime;
 use tracing::{debug, info, warn};
 
-const MAX_CONVERSATION_LOOP: usize = 8;
+/// 設定で指定されていない場合の tool calling の往復回数の上限。
+const DEFAULT_MAX_TOOL_ROUNDS: usize = 7;
+
+/// 設定で指定されていない場合の続きの生成回数の上限。
+const DEFAULT_MAX_CONTINUATION_ROUNDS: usize = 7;
+
+/// 設定で指定されていない場合の tool calling と続きの生成を合わせた回数の上限。
+/// 最初の送信と合わせて、 LLM に送るのは 8 回までになる。
+const DEFAULT_MAX_TOTAL_ROUNDS: usize = 7;
+
+/// 回数制限で打ち切った応答の末尾に付ける印。
+const OMITTED_MARKER: &str = "(omitted)";
+
+/// 呼び出し記録に残す結果の最大文字数。
+const TOOL_RESULT_SUMMARY_LENGTH: usize = 200;
 
 pub struct NatsukiInner {
     storage: BoxConversationStorage,
@@ -36,6 +71,24 @@ pub struct NatsukiInner {
     function_store: FunctionStore,
     interceptions: Vec<BoxInterception>,
     context: Context,
+    default_persona: Persona,
+    personas: HashMap<String, Persona>,
+}
+
+/// ペルソナごとの設定。
+struct Persona {
+    system_role: Arc<dyn TextProvider<Data = HashMap<String, String>>>,
+    model: Option<String>,
+
+    /// 使える function 。 `None` ならすべて。
+    tools: Option<HashSet<String>>,
+    response_description: Option<String>,
+}
+
+impl Persona {
+    fn allows(&self, function_name: &str) -> bool {
+        self.tools.as_ref().is_none_or(|t| t.contains(function_name))
+    }
 }
 
 impl NatsukiInner {
@@ -46,27 +99,38 @@ impl NatsukiInner {
         function_store: FunctionStore,
         interceptions: Vec<BoxInterception>,
         assistant_identity: &ConfigAssistant,
+        datetime_provider: Arc<dyn DateTimeProvider>,
     ) -> Result<NatsukiInner, ServerError> {
         let context = {
             let system_role = InterpolatableTextProvider::new(assistant_identity.system_role.clone())
                 .map_err(ServerError::by_internal)?;
 
-            let mut dtp = BotDateTimeProvider::new();
-            let offset_days = debug_option_parsed("datetime_offset")
-                .map_err(FunctionError::by_serialization)?
-                .unwrap_or(0);
-            if offset_days != 0 {
-                warn!("day offset: {offset_days}");
-                dtp.set_offset(Duration::days(offset_days as i64));
-            }
-
             Context {
                 system_role: Arc::new(system_role),
                 sensitive_marker: assistant_identity.sensitive_marker.clone().into(),
-                datetime_provider: Arc::new(dtp),
+                datetime_provider,
             }
         };
 
+        let default_persona = Persona {
+            system_role: context.system_role.clone(),
+            model: None,
+            tools: None,
+            response_description: assistant_identity.response_description.clone(),
+        };
+        let mut personas = HashMap::new();
+        for (name, persona_config) in &assistant_identity.personas {
+            let system_role = InterpolatableTextProvider::new(persona_config.system_role.clone())
+                .map_err(ServerError::by_internal)?;
+            let persona = Persona {
+                system_role: Arc::new(system_role),
+                model: persona_config.model.clone(),
+                tools: persona_config.tools.as_ref().map(|t| t.iter().cloned().collect()),
+                response_description: persona_config.response_description.clone(),
+            };
+            personas.insert(name.clone(), persona);
+        }
+
         Ok(NatsukiInner {
             storage,
             rate_limiter,
@@ -74,6 +138,8 @@ impl NatsukiInner {
             function_store,
             interceptions,
             context,
+            default_persona,
+            personas,
         })
     }
 
@@ -82,6 +148,7 @@ impl NatsukiInner {
         message_ctx: MessageContext,
         conversation_id: ConversationId,
         new_messages: Vec<Message>,
+        progress_sender: Option<UnboundedSender<ConversationProgress>>,
     ) -> Result<ConversationUpdate, ServerError> {
         // TODO: Context に UserRole を統合する
         if !self.ensure_in_rate(&message_ctx).await {
@@ -120,36 +187,132 @@ impl NatsukiInner {
             }
         }
 
+        // 設定から消えたペルソナの会話は既定のペルソナで続ける
+        let persona = match self.find_persona(incomplete_conversation.current_persona()) {
+            Ok(persona) => persona,
+            Err(e) => {
+                warn!("{e}; falling back to default persona");
+                &self.default_persona
+            }
+        };
+        if let Some(description) = &persona.response_description {
+            incomplete_conversation.set_response_description(description);
+        }
+
         // LLM updates
-        let model = incomplete_conversation.current_model();
-        let llm = self.llm_cache.get(model).await.map_err(ServerError::by_internal)?;
-        debug!("using model {model:?}");
+        let model_name = self
+            .llm_cache
+            .route(&message_ctx, &incomplete_conversation, persona.model.as_deref());
+        debug!("using model {model_name}");
+
+        let mut usages = BTreeMap::new();
+        if let Some(context_config) = self.llm_cache.context_of(&model_name) {
+            let summary_model_name = context_config.summary_model.as_deref().unwrap_or(&model_name);
+            match self.llm_cache.get_by_name(summary_model_name).await {
+                Ok(summary_llm) => {
+                    let usage = fit_context_window(&mut incomplete_conversation, &context_config, &summary_llm).await;
+                    if let Some(usage) = usage {
+                        usages.insert(summary_model_name.to_string(), usage);
+                    }
+                }
+                Err(e) => warn!("summary model {summary_model_name} unavailable: {e}"),
+            }
+        }
+
+        let result = self
+            .process_llm_loop(
+                &message_ctx,
+                persona,
+                incomplete_conversation,
+                &model_name,
+                progress_sender,
+                &mut usages,
+            )
+            .await;
+        self.record_usages(&message_ctx, usages).await;
+        result
+    }
 
-        let function_descriptors: Vec<_> = self.function_store.descriptors().collect();
+    /// tool calling を含めて LLM とのやりとりを完了させる。
+    /// 消費したトークン数はエラーの場合も含めてモデルごとに `usages` に加算される。
+    async fn process_llm_loop(
+        &self,
+        message_ctx: &MessageContext,
+        persona: &Persona,
+        mut incomplete_conversation: IncompleteConversation,
+        model_name: &str,
+        progress_sender: Option<UnboundedSender<ConversationProgress>>,
+        usages: &mut BTreeMap<String, LlmUsage>,
+    ) -> Result<ConversationUpdate, ServerError> {
+        let llm = self
+            .llm_cache
+            .get_by_name(model_name)
+            .await
+            .map_err(ServerError::by_internal)?;
+        let function_descriptors: Vec<_> = self
+            .function_store
+            .descriptors(message_ctx.role())
+            .filter(|d| persona.allows(&d.name))
+            .collect();
         debug!("registered functions: {}", function_descriptors.len());
 
-        let mut sent_count = 0;
+        let limits = self.llm_cache.limits_of(model_name, message_ctx.role());
+        let max_tool_rounds = limits.max_tool_rounds.unwrap_or(DEFAULT_MAX_TOOL_ROUNDS);
+        let max_continuation_rounds = limits
+            .max_continuation_rounds
+            .unwrap_or(DEFAULT_MAX_CONTINUATION_ROUNDS);
+        let max_total_rounds = limits.max_total_rounds.unwrap_or(DEFAULT_MAX_TOTAL_ROUNDS);
+        let policy = limits.on_exceeded.unwrap_or_default();
+
+        let mut tool_rounds = 0;
+        let mut continuation_rounds = 0;
+        let mut tools_withheld = false;
         loop {
-            // 超過したらエラー
-            if sent_count >= MAX_CONVERSATION_LOOP {
-                return Err(ServerError::TooMuchConversationCall);
-            }
+            let sending_descriptors = if tools_withheld {
+                &[][..]
+            } else {
+                &function_descriptors[..]
+            };
+            let response = match &progress_sender {
+                Some(ps) => {
+                    self.send_llm_stream(&llm, &incomplete_conversation, sending_descriptors, ps)
+                        .await?
+                }
+                None => {
+                    llm.send_conversation(&incomplete_conversation, sending_descriptors)
+                        .await?
+                }
+            };
 
-            let update = llm
-                .send_conversation(&incomplete_conversation, &function_descriptors)
-                .await?;
-            sent_count += 1;
+            let answered_model = response.answered_model.unwrap_or_else(|| model_name.to_string());
+            if answered_model != model_name {
+                info!("model {model_name} fell back to {answered_model}");
+            }
+            *usages.entry(answered_model.clone()).or_default() += response.usage;
+            for (discarded_model, usage) in response.discarded_usages {
+                *usages.entry(discarded_model).or_default() += usage;
+            }
+            if let Some(response_id) = &response.response_id {
+                incomplete_conversation.set_response_chain(&answered_model, response_id);
+            }
+            if let Some(reasoning_summary) = response.reasoning_summary {
+                incomplete_conversation.push_reasoning_summary(reasoning_summary);
+            }
+            incomplete_conversation.set_answered_model(answered_model);
 
-            match update {
+            let total_exceeded = tool_rounds + continuation_rounds >= max_total_rounds;
+            match response.update {
                 // 正常終了
                 LlmUpdate::Finished(finished) => {
                     debug!("conversation finished");
                     let (text, is_sensitive) = self.strip_sensitive_text(finished.text, finished.sensitive);
+                    incomplete_conversation.set_actions(finished.actions);
                     return Ok(incomplete_conversation.finish(AssistantMessage {
                         text,
                         is_sensitive,
                         language: finished.language,
                         skip_llm: false,
+                        reasoning_summary: None,
                     }));
                 }
 
@@ -162,16 +325,50 @@ impl NatsukiInner {
                         is_sensitive,
                         language: cut.language,
                         skip_llm: false,
+                        reasoning_summary: None,
                     });
+
+                    if continuation_rounds >= max_continuation_rounds || total_exceeded {
+                        info!("continuation limit ({max_continuation_rounds}, total {max_total_rounds}) exceeded");
+                        return match policy {
+                            ConfigLlmLimitPolicy::Error => Err(ServerError::TooMuchConversationCall),
+                            _ => Ok(incomplete_conversation.finish(omitted_message())),
+                        };
+                    }
+                    continuation_rounds += 1;
                 }
 
                 // Tool Calling
                 LlmUpdate::ToolCalling(tool_callings) => {
                     debug!("conversation requested tool calling");
+                    // tool を渡さなかったのに要求してきた場合も上限に達したものとして扱う
+                    if tool_rounds >= max_tool_rounds || total_exceeded || tools_withheld {
+                        info!("tool calling limit ({max_tool_rounds}, total {max_total_rounds}) exceeded");
+                        match policy {
+                            ConfigLlmLimitPolicy::FinalAnswer if !tools_withheld => {
+                                tools_withheld = true;
+                                continue;
+                            }
+                            ConfigLlmLimitPolicy::Error => return Err(ServerError::TooMuchConversationCall),
+                            _ => {
+                                // 返せる本文がなければエラーと同じ扱いにする
+                                let Some(truncated) = truncated_message(&incomplete_conversation) else {
+                                    return Err(ServerError::TooMuchConversationCall);
+                                };
+                                return Ok(incomplete_conversation.finish(truncated));
+                            }
+                        }
+                    }
+                    tool_rounds += 1;
+
+                    if let Some(ps) = &progress_sender {
+                        let names = tool_callings.iter().map(|tc| tc.name.clone()).collect();
+                        ps.unbounded_send(ConversationProgress::ToolCalling(names)).ok();
+                    }
                     let call_message = Message::new_function_calls(tool_callings.clone());
                     let (response_messages, called_attachments) = self
-                        .process_tool_callings(&message_ctx, &incomplete_conversation, tool_callings)
-                        .await?;
+                        .process_tool_callings(message_ctx, persona, &incomplete_conversation, tool_callings)
+                        .await;
 
                     let extending_messages = once(call_message).chain(response_messages.into_iter().map(|m| m.into()));
                     incomplete_conversation.extend_messages(extending_messages);
@@ -186,12 +383,66 @@ impl NatsukiInner {
                         is_sensitive: true,
                         language: None,
                         skip_llm: false,
+                        reasoning_summary: None,
                     }));
                 }
             }
         }
     }
 
+    /// 消費したトークン数を記録する。記録に失敗しても会話は続行する。
+    /// identity は tool calling の記録と同じくハッシュ化して残す。
+    async fn record_usages(&self, message_ctx: &MessageContext, usages: BTreeMap<String, LlmUsage>) {
+        let identity = message_ctx.hashed_identity();
+        let date = self.context.datetime_provider.now().date();
+        for (model, usage) in usages {
+            info!(
+                "usage of {model} by {identity}: prompt {} (cached {}), completion {} (reasoning {})",
+                usage.prompt_tokens, usage.cached_tokens, usage.completion_tokens, usage.reasoning_tokens
+            );
+            if let Err(e) = self.storage.add_usage(&identity, &model, date, usage).await {
+                warn!("failed to record usage: {e}");
+            }
+        }
+    }
+
+    /// ストリーミングで LLM に送信し、本文の途中経過を `progress_sender` に流す。
+    async fn send_llm_stream(
+        &self,
+        llm: &ArcLlm,
+        incomplete_conversation: &IncompleteConversation,
+        function_descriptors: &[&FunctionDescriptor],
+        progress_sender: &UnboundedSender<ConversationProgress>,
+    ) -> Result<LlmResponse, LlmError> {
+        let (delta_sender, delta_receiver) = unbounded();
+        let sending = llm.send_conversation_stream(incomplete_conversation, function_descriptors, delta_sender);
+        let forwarding = async {
+            let mut text = String::new();
+            let mut deltas = delta_receiver;
+            while let Some(delta) = deltas.next().await {
+                match delta {
+                    LlmDelta::Text(delta_text) => text.push_str(&delta_text),
+                    LlmDelta::Restart => text.clear(),
+                    LlmDelta::ToolCalling { .. } => continue,
+                }
+
+                // sensitive marker と判別できるまでは流さない
+                let marker = &*self.context.sensitive_marker;
+                if !marker.is_empty() && marker.starts_with(text.as_str()) {
+                    continue;
+                }
+                let (progress_text, _) = self.strip_sensitive_text(text.clone(), None);
+                progress_sender
+                    .unbounded_send(ConversationProgress::Text(progress_text))
+                    .ok();
+            }
+        };
+
+        // send_conversation_stream が終われば delta_sender が drop されて forwarding も終わる
+        let (update, _) = join(sending, forwarding).await;
+        update
+    }
+
     fn strip_sensitive_text(&self, original: String, explicit_sensitive: Option<bool>) -> (String, bool) {
         match explicit_sensitive {
             Some(v) => (original, v),
@@ -218,52 +469,70 @@ impl NatsukiInner {
     async fn process_tool_callings(
         &self,
         message_ctx: &MessageContext,
+        persona: &Persona,
         incomplete_conversation: &IncompleteConversation,
         tool_callings: Vec<MessageToolCalling>,
-    ) -> Result<(Vec<FunctionResponseMessage>, Vec<ConversationAttachment>), ServerError> {
-        let mut responses = vec![];
-        let mut attachments = vec![];
-        for tool_calling in tool_callings {
+    ) -> (Vec<FunctionResponseMessage>, Vec<ConversationAttachment>) {
+        // 互いに独立しているので並行して呼び出し、結果は呼び出し順に並べる
+        let callings = tool_callings.into_iter().map(|tool_calling| async move {
             let (id, name) = (tool_calling.id.clone(), tool_calling.name.clone());
+            let arguments = tool_calling.arguments.clone();
             info!("calling tool {name} (id: {id})");
 
-            let Some(response) = self
-                .function_store
-                .find_call(tool_calling, &self.context, message_ctx, incomplete_conversation)
-                .await
-            else {
-                warn!("tool {name} not found, skipping");
-                continue;
+            let called_at = self.context.datetime_provider.now();
+            let started = Instant::now();
+            // ペルソナに提示していない function は存在しないものとして扱う
+            let response = if persona.allows(&name) {
+                self.function_store
+                    .find_call(tool_calling, &self.context, message_ctx, incomplete_conversation)
+                    .await
+            } else {
+                Err(FunctionCallError::NotFound(name.clone()))
+            };
+            let record = ToolCallRecord {
+                conversation_id: incomplete_conversation.id(),
+                identity: message_ctx.hashed_identity(),
+                tool_name: name.clone(),
+                arguments,
+                result_summary: response.as_ref().ok().map(|r| summarize_result(&r.result)),
+                error: response.as_ref().err().map(|e| e.to_string()),
+                duration: started.elapsed(),
+                called_at,
+            };
+            (id, name, response, record)
+        });
+        let called = join_all(callings).await;
+
+        let mut responses = vec![];
+        let mut attachments = vec![];
+        for (id, name, response, record) in called {
+            if let Err(e) = self.storage.add_tool_call(&record).await {
+                warn!("failed to record tool call: {e}");
+            }
+
+            // 呼び出しに対応する結果がないと LLM 側で拒否されるので、失敗してもエラーを結果として返す
+            let result = match response {
+                Ok(response) => {
+                    attachments.extend(response.attachments);
+                    response.result
+                }
+                Err(e) => {
+                    warn!(
+                        "tool {name} (id: {id}) failed in conversation {}: {e}",
+                        incomplete_conversation.id().0
+                    );
+                    e.to_result()
+                }
             };
-            let response = response?;
-
-            responses.push(FunctionResponseMessage {
-                id,
-                name,
-                result: response.result,
-            });
-            attachments.extend(response.attachments);
+            responses.push(FunctionResponseMessage { id, name, result });
         }
 
-        Ok((responses, attachments))
+        (responses, attachments)
     }
 
-    pub async fn new_conversation(&self) -> Result<ConversationId, ServerError> {
-        let system_role = {
-            let mut data = HashMap::new();
-            data.insert(
-                "datetime".into(),
-                self.context
-                    .datetime_provider
-                    .now()
-                    .format(YMDHM_JAPANESE)
-                    .expect("failed to format datetime"),
-            );
-            self.context.system_role.generate(data)
-        };
-
-        let system_message = Message::new_system(system_role);
-        let conversation = Conversation::new_now(Some(system_message));
+    pub async fn new_conversation(&self, persona: ConversationPersona) -> Result<ConversationId, ServerError> {
+        let system_message = self.generate_system_message(self.find_persona(&persona)?);
+        let conversation = Conversation::new_now_as(Some(system_message), persona);
         self.storage.upsert(&conversation, None).await?;
         Ok(conversation.id())
     }
@@ -279,8 +548,92 @@ impl NatsukiInner {
             .fetch_content_by_id(update.id())
             .await?
             .ok_or_else(|| ServerError::ConversationNotFound(update.id()))?;
-        let updated_conversation = update.complete_conversation_with(current_conversation);
+        let persona_override = update.persona_override().cloned();
+        let mut updated_conversation = update.complete_conversation_with(current_conversation);
+
+        // ペルソナが変わったらシステムメッセージも差し替える
+        if let Some(persona) = persona_override {
+            let system_message = self.generate_system_message(self.find_persona(&persona)?);
+            updated_conversation.replace_system(system_message);
+        }
+
         self.storage.upsert(&updated_conversation, Some(context_key)).await?;
         Ok(())
     }
+
+    fn find_persona(&self, persona: &ConversationPersona) -> Result<&Persona, ServerError> {
+        match persona {
+            ConversationPersona::Default => Ok(&self.default_persona),
+            ConversationPersona::Named(name) => self
+                .personas
+                .get(name)
+                .ok_or_else(|| ServerError::PersonaNotFound(name.clone())),
+        }
+    }
+
+    fn generate_system_message(&self, persona: &Persona) -> Message {
+        let mut data = HashMap::new();
+        data.insert(
+            "datetime".into(),
+            self.context
+                .datetime_provider
+                .now()
+                .format(YMDHM_JAPANESE)
+                .expect("failed to format datetime"),
+        );
+        Message::new_system(persona.system_role.generate(data))
+    }
+}
+
+/// 回数制限で打ち切ったときの応答。それまでの本文に追記される。
+fn omitted_message() -> AssistantMessage {
+    AssistantMessage {
+        text: OMITTED_MARKER.to_string(),
+        is_sensitive: false,
+        language: None,
+        skip_llm: false,
+        reasoning_summary: None,
+    }
+}
+
+/// tool calling の回数制限で打ち切ったときの応答。
+/// 今回生成された本文に印を付けてまとめる。本文は履歴に残っているので、この応答は LLM には送らない。
+fn truncated_message(incomplete_conversation: &IncompleteConversation) -> Option<AssistantMessage> {
+    let pushed = incomplete_conversation.pushed_messages();
+    // 末尾が続きの生成待ちの本文なら、 `finish` でそこに印が追記される
+    if matches!(pushed.last(), Some(Message::Assistant(_))) {
+        return Some(omitted_message());
+    }
+
+    let produced: Vec<_> = pushed
+        .iter()
+        .filter_map(|m| match m {
+            Message::Assistant(assistant_message) => Some(assistant_message),
+            _ => None,
+        })
+        .collect();
+    if produced.is_empty() {
+        return None;
+    }
+
+    Some(AssistantMessage {
+        text: produced
+            .iter()
+            .map(|a| a.text.as_str())
+            .chain([OMITTED_MARKER])
+            .collect(),
+        is_sensitive: produced.iter().any(|a| a.is_sensitive),
+        language: produced.iter().rev().find_map(|a| a.language.clone()),
+        skip_llm: true,
+        reasoning_summary: None,
+    })
+}
+
+/// 記録用に結果の JSON を切り詰める。
+fn summarize_result(result: &Value) -> String {
+    let serialized = result.to_string();
+    match serialized.char_indices().nth(TOOL_RESULT_SUMMARY_LENGTH) {
+        Some((end, _)) => format!("{}...", &serialized[..end]),
+        None => serialized,
+    }
 }
@@ -1,78 +1,297 @@
-use crate::llm::create_llm;
+use crate::{llm::create_llm, natsuki::model_router::ModelRouter};
 
 use std::{
-    collections::{HashMap, HashSet},
-    sync::Arc,
+    collections::{BTreeMap, HashMap},
+    sync::{Arc, RwLock as StdRwLock},
+    time::{Duration, Instant},
 };
 
-use lnb_common::config::llm::{ConfigLlm, ConfigLlmModel};
-use lnb_core::{interface::llm::ArcLlm, model::conversation::ConversationModel};
+use lnb_common::config::llm::{
+    ConfigLlm, ConfigLlmBackend, ConfigLlmLimits, ConfigLlmModel, ConfigLlmModelContext, ConfigLlmRoleLimits,
+};
+use lnb_core::{
+    interface::{MessageContext, llm::ArcLlm},
+    model::{
+        conversation::{ConversationModel, IncompleteConversation},
+        user_role::UserRole,
+    },
+};
 use thiserror::Error as ThisError;
-use tokio::sync::RwLock;
-use tracing::{debug, warn};
+use tokio::sync::{Mutex, RwLock};
+use tracing::{debug, info, warn};
 
 #[derive(Clone)]
 pub struct LlmCache {
-    default_model: String,
-    uninitialized_models: Arc<RwLock<HashMap<String, ConfigLlmModel>>>,
+    definitions: Arc<StdRwLock<LlmDefinitions>>,
+    cooldown: Duration,
+    max_cooldown: Duration,
     created_models: Arc<RwLock<HashMap<String, ArcLlm>>>,
-    failed_models: Arc<RwLock<HashSet<String>>>,
+    failed_models: Arc<RwLock<HashMap<String, FailedModel>>>,
+
+    /// 初期化できたが疎通確認に失敗したモデルと、そのエラー。
+    unreachable_models: Arc<RwLock<HashMap<String, String>>>,
+
+    /// 同じモデルを同時に初期化しないようにする。
+    initializing: Arc<Mutex<()>>,
 }
 
-impl LlmCache {
-    pub fn new(config: &ConfigLlm) -> LlmCache {
-        let uninitialized_models = config.models.iter().map(|(k, v)| (k.clone(), v.clone())).collect();
+/// 再読み込みで差し替えられる部分。
+struct LlmDefinitions {
+    default_model: String,
+    models: HashMap<String, ConfigLlmModel>,
+    limits: ConfigLlmLimits,
+    role_limits: ConfigLlmRoleLimits,
+    router: ModelRouter,
+}
 
-        LlmCache {
+impl LlmDefinitions {
+    fn new(config: &ConfigLlm) -> Result<LlmDefinitions, LlmCacheError> {
+        let router = ModelRouter::new(&config.routes).map_err(|e| LlmCacheError::InvalidRoute(e.to_string()))?;
+        if let Some(undefined) = router.models().find(|m| !config.models.contains_key(*m)) {
+            return Err(LlmCacheError::Undefined(undefined.to_string()));
+        }
+
+        Ok(LlmDefinitions {
             default_model: config.default.clone(),
-            uninitialized_models: Arc::new(RwLock::new(uninitialized_models)),
+            models: config.models.clone(),
+            limits: config.limits.clone(),
+            role_limits: config.role_limits.clone(),
+            router,
+        })
+    }
+}
+
+/// 初期化に失敗したモデルの記録。
+#[derive(Debug, Clone)]
+struct FailedModel {
+    attempts: usize,
+    last_error: String,
+    retry_at: Instant,
+}
+
+/// モデルごとの初期化状態。
+#[derive(Debug, Clone)]
+pub enum LlmModelStatus {
+    /// まだ初期化されていない。
+    Uninitialized,
+
+    /// 初期化済みで利用可能。
+    Ready,
+
+    /// 初期化済みだが、疎通確認に失敗している。
+    Unreachable { last_error: String },
+
+    /// 初期化に失敗している。`retry_in` 経過後に再試行される。
+    Failed {
+        attempts: usize,
+        last_error: String,
+        retry_in: Duration,
+    },
+}
+
+impl LlmCache {
+    pub fn new(config: &ConfigLlm) -> Result<LlmCache, LlmCacheError> {
+        Ok(LlmCache {
+            definitions: Arc::new(StdRwLock::new(LlmDefinitions::new(config)?)),
+            cooldown: Duration::from_secs(config.initialization.cooldown_seconds),
+            max_cooldown: Duration::from_secs(config.initialization.max_cooldown_seconds),
             created_models: Arc::new(RwLock::new(HashMap::new())),
-            failed_models: Arc::new(RwLock::new(HashSet::new())),
+            failed_models: Arc::new(RwLock::new(HashMap::new())),
+            unreachable_models: Arc::new(RwLock::new(HashMap::new())),
+            initializing: Arc::new(Mutex::new(())),
+        })
+    }
+
+    /// モデル定義を差し替え、初期化済みのモデルを破棄する。
+    /// 破棄したモデルは次に使われたときに新しい定義で初期化される。
+    /// 定義が不正な場合は何も変更しない。
+    pub async fn reload(&self, config: &ConfigLlm) -> Result<(), LlmCacheError> {
+        let definitions = LlmDefinitions::new(config)?;
+        let _initializing = self.initializing.lock().await;
+        *self.definitions.write().expect("poisoned") = definitions;
+        self.created_models.write().await.clear();
+        self.failed_models.write().await.clear();
+        self.unreachable_models.write().await.clear();
+        info!("{} LLM backend definitions reloaded", config.models.len());
+        Ok(())
+    }
+
+    /// 会話に使うモデル名を返す。
+    /// モデルが指定されている会話ではそれを、そうでなければ自動選択の規則に従う。
+    /// どの規則にも当てはまらなければ `persona_model` 、それもなければ既定のモデルを使う。
+    pub fn route(
+        &self,
+        message_ctx: &MessageContext,
+        incomplete: &IncompleteConversation,
+        persona_model: Option<&str>,
+    ) -> String {
+        let definitions = self.definitions.read().expect("poisoned");
+        if let ConversationModel::Specified(model) = incomplete.current_model() {
+            return model.clone();
+        }
+        match definitions.router.route(message_ctx, incomplete.last_user()) {
+            Some((index, model)) => {
+                info!("routed to model {model} by route #{index}");
+                model.to_string()
+            }
+            None => persona_model.unwrap_or(&definitions.default_model).to_string(),
         }
     }
 
-    pub async fn get(&self, model: &ConversationModel) -> Result<ArcLlm, LlmCacheError> {
-        let key = model.specified_or(&self.default_model);
+    /// モデル定義のバックエンド種別を返す。
+    pub fn backend_of(&self, key: &str) -> Option<ConfigLlmBackend> {
+        self.definition_of(key).map(|d| d.backend)
+    }
+
+    /// モデル定義のコンテキストウィンドウ設定を返す。
+    pub fn context_of(&self, key: &str) -> Option<ConfigLlmModelContext> {
+        self.definition_of(key)?.context
+    }
 
-        let created_llm = {
-            let created_lock = self.created_models.read().await;
-            created_lock.get(key).cloned()
+    /// 共通・モデル・権限の順に上書きした回数制限を返す。
+    pub fn limits_of(&self, key: &str, role: &UserRole) -> ConfigLlmLimits {
+        let definitions = self.definitions.read().expect("poisoned");
+        let mut limits = definitions.limits.clone();
+        if let Some(model_limits) = definitions.models.get(key).and_then(|d| d.limits.as_ref()) {
+            limits = limits.overridden_by(model_limits);
+        }
+        let role_limits = match role {
+            UserRole::Privileged => &definitions.role_limits.privileged,
+            UserRole::Normal => &definitions.role_limits.normal,
+            UserRole::Scoped(_) => &definitions.role_limits.scoped,
         };
-        if let Some(created_llm) = created_llm {
+        if let Some(role_limits) = role_limits {
+            limits = limits.overridden_by(role_limits);
+        }
+        limits
+    }
+
+    pub async fn get_by_name(&self, key: &str) -> Result<ArcLlm, LlmCacheError> {
+        if let Some(created_llm) = self.find_created(key).await {
             return Ok(created_llm);
         }
 
-        let failed_llm_name = {
-            let failed_lock = self.failed_models.read().await;
-            failed_lock.get(key).cloned()
+        let Some(definition) = self.definition_of(key) else {
+            return Err(LlmCacheError::Undefined(key.to_string()));
         };
-        if let Some(failed_llm_name) = failed_llm_name {
-            return Err(LlmCacheError::Failed(failed_llm_name));
+        let _initializing = self.initializing.lock().await;
+
+        // 待っている間に初期化されているかもしれない
+        if let Some(created_llm) = self.find_created(key).await {
+            return Ok(created_llm);
         }
 
-        let creating_llm_config = {
-            let mut uninitialized_lock = self.uninitialized_models.write().await;
-            uninitialized_lock.remove(key)
-        };
-        let Some(creating_llm_config) = creating_llm_config else {
-            return Err(LlmCacheError::Undefined(key.to_string()));
+        let previous_attempts = {
+            let failed_lock = self.failed_models.read().await;
+            match failed_lock.get(key) {
+                Some(failed) if failed.retry_at > Instant::now() => {
+                    return Err(LlmCacheError::Failed(key.to_string()));
+                }
+                Some(failed) => failed.attempts,
+                None => 0,
+            }
         };
 
-        match create_llm(creating_llm_config).await {
+        match create_llm(key, definition, self).await {
             Ok(llm) => {
                 debug!("initialized and cached LLM {key}");
-                let mut created_lock = self.created_models.write().await;
-                created_lock.insert(key.to_string(), llm.clone());
+                self.failed_models.write().await.remove(key);
+                self.created_models.write().await.insert(key.to_string(), llm.clone());
                 Ok(llm)
             }
             Err(e) => {
-                warn!("failed to initialize LLM {key}: {e}");
-                let mut failed_lock = self.failed_models.write().await;
-                failed_lock.insert(key.to_string());
+                let attempts = previous_attempts + 1;
+                let cooldown = self.cooldown_for(attempts);
+                warn!(
+                    "failed to initialize LLM {key} ({attempts} attempt(s)), retrying after {}s: {e}",
+                    cooldown.as_secs()
+                );
+
+                let failed = FailedModel {
+                    attempts,
+                    last_error: e.to_string(),
+                    retry_at: Instant::now() + cooldown,
+                };
+                self.failed_models.write().await.insert(key.to_string(), failed);
                 Err(LlmCacheError::Failed(key.to_string()))
             }
         }
     }
+
+    /// 定義されているすべてのモデルを初期化して疎通を確認し、その結果を返す。
+    pub async fn warm_up(&self) -> BTreeMap<String, LlmModelStatus> {
+        for key in self.model_keys() {
+            // 失敗の詳細は返す状態に含まれる
+            let Ok(llm) = self.get_by_name(&key).await else {
+                continue;
+            };
+            match llm.probe().await {
+                Ok(()) => {
+                    info!("LLM {key} is ready");
+                    self.unreachable_models.write().await.remove(&key);
+                }
+                Err(e) => {
+                    self.unreachable_models.write().await.insert(key, e.to_string());
+                }
+            }
+        }
+        self.statuses().await
+    }
+
+    /// 定義されているすべてのモデルの状態を返す。
+    pub async fn statuses(&self) -> BTreeMap<String, LlmModelStatus> {
+        let created_lock = self.created_models.read().await;
+        let failed_lock = self.failed_models.read().await;
+        let unreachable_lock = self.unreachable_models.read().await;
+        let now = Instant::now();
+
+        self.model_keys()
+            .into_iter()
+            .map(|key| {
+                let status = if let Some(last_error) = unreachable_lock.get(&key) {
+                    LlmModelStatus::Unreachable {
+                        last_error: last_error.clone(),
+                    }
+                } else if created_lock.contains_key(&key) {
+                    LlmModelStatus::Ready
+                } else if let Some(failed) = failed_lock.get(&key) {
+                    LlmModelStatus::Failed {
+                        attempts: failed.attempts,
+                        last_error: failed.last_error.clone(),
+                        retry_in: failed.retry_at.saturating_duration_since(now),
+                    }
+                } else {
+                    LlmModelStatus::Uninitialized
+                };
+                (key, status)
+            })
+            .collect()
+    }
+
+    fn definition_of(&self, key: &str) -> Option<ConfigLlmModel> {
+        self.definitions.read().expect("poisoned").models.get(key).cloned()
+    }
+
+    fn model_keys(&self) -> Vec<String> {
+        self.definitions
+            .read()
+            .expect("poisoned")
+            .models
+            .keys()
+            .cloned()
+            .collect()
+    }
+
+    async fn find_created(&self, key: &str) -> Option<ArcLlm> {
+        let created_lock = self.created_models.read().await;
+        created_lock.get(key).cloned()
+    }
+
+    /// `attempts` 回失敗した後の待ち時間。失敗するたびに倍になる。
+    fn cooldown_for(&self, attempts: usize) -> Duration {
+        let exponent = attempts.saturating_sub(1).min(16) as u32;
+        self.cooldown.saturating_mul(1 << exponent).min(self.max_cooldown)
+    }
 }
 
 #[derive(Debug, ThisError)]
@@ -82,4 +301,7 @@ pub enum LlmCacheError {
 
     #[error("model {0} reported initialization failure")]
     Failed(String),
+
+    #[error("invalid route: {0}")]
+    InvalidRoute(String),
 }
@@ -0,0 +1,190 @@
+use lnb_common::config::llm::{ConfigLlmRoute, ConfigLlmRouteCondition};
+use lnb_core::{
+    interface::MessageContext,
+    model::message::{UserMessage, UserMessageContent},
+};
+use regex::{Error as RegexError, Regex};
+
+/// 設定された規則に従って会話に使うモデルを選ぶ。
+#[derive(Debug, Clone)]
+pub struct ModelRouter {
+    routes: Vec<Route>,
+}
+
+#[derive(Debug, Clone)]
+struct Route {
+    model: String,
+    condition: ConfigLlmRouteCondition,
+    pattern: Option<Regex>,
+}
+
+impl ModelRouter {
+    pub fn new(routes: &[ConfigLlmRoute]) -> Result<ModelRouter, RegexError> {
+        let routes = routes
+            .iter()
+            .map(|r| {
+                Ok(Route {
+                    model: r.model.clone(),
+                    condition: r.when.clone(),
+                    pattern: r.when.pattern.as_deref().map(Regex::new).transpose()?,
+                })
+            })
+            .collect::<Result<_, RegexError>>()?;
+        Ok(ModelRouter { routes })
+    }
+
+    /// 規則が参照するモデル名。
+    pub fn models(&self) -> impl Iterator<Item = &str> {
+        self.routes.iter().map(|r| r.model.as_str())
+    }
+
+    /// 最初に条件を満たした規則の番号とモデル名を返す。
+    pub fn route(&self, message_ctx: &MessageContext, last_user: Option<&UserMessage>) -> Option<(usize, &str)> {
+        self.routes
+            .iter()
+            .enumerate()
+            .find(|(_, r)| r.matches(message_ctx, last_user))
+            .map(|(i, r)| (i, r.model.as_str()))
+    }
+}
+
+impl Route {
+    fn matches(&self, message_ctx: &MessageContext, last_user: Option<&UserMessage>) -> bool {
+        let condition = &self.condition;
+        if let Some(scope) = &condition.scope
+            && !message_ctx.role().accepts(scope)
+        {
+            return false;
+        }
+        if let Some(platform) = &condition.platform {
+            let identity_platform = message_ctx.identity().and_then(|i| i.split_once(':')).map(|(p, _)| p);
+            if identity_platform != Some(platform.as_str()) {
+                return false;
+            }
+        }
+
+        let contents = last_user.map(|u| u.contents.as_slice()).unwrap_or_default();
+        if let Some(has_image) = condition.has_image {
+            let image_attached = contents.iter().any(|c| matches!(c, UserMessageContent::ImageUrl(_)));
+            if image_attached != has_image {
+                return false;
+            }
+        }
+
+        let text = contents
+            .iter()
+            .filter_map(|c| match c {
+                UserMessageContent::Text(t) => Some(t.as_str()),
+                _ => None,
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+        let length = text.chars().count();
+        if condition.min_length.is_some_and(|min| length < min) {
+            return false;
+        }
+        if condition.max_length.is_some_and(|max| length > max) {
+            return false;
+        }
+        if let Some(pattern) = &self.pattern
+            && !pattern.is_match(&text)
+        {
+            return false;
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use lnb_core::model::user_role::UserRole;
+    use url::Url;
+
+    fn user_message(contents: Vec<UserMessageContent>) -> UserMessage {
+        UserMessage {
+            contents,
+            name: None,
+            language: None,
+            skip_llm: false,
+        }
+    }
+
+    fn route(model: &str, when: ConfigLlmRouteCondition) -> ConfigLlmRoute {
+        ConfigLlmRoute {
+            model: model.to_string(),
+            when,
+        }
+    }
+
+    #[test]
+    fn routes_by_image_and_length() {
+        let router = ModelRouter::new(&[
+            route(
+                "vision",
+                ConfigLlmRouteCondition {
+                    has_image: Some(true),
+                    ..Default::default()
+                },
+            ),
+            route(
+                "cheap",
+                ConfigLlmRouteCondition {
+                    max_length: Some(10),
+                    ..Default::default()
+                },
+            ),
+        ])
+        .expect("should compile");
+        let ctx = MessageContext::new_user("mastodon:tester", UserRole::Normal);
+
+        let image = user_message(vec![
+            UserMessageContent::Text("これ何？".to_string()),
+            UserMessageContent::ImageUrl(Url::parse("https://example.com/a.png").unwrap()),
+        ]);
+        assert_eq!(router.route(&ctx, Some(&image)), Some((0, "vision")));
+
+        let short = user_message(vec![UserMessageContent::Text("おはよう".to_string())]);
+        assert_eq!(router.route(&ctx, Some(&short)), Some((1, "cheap")));
+
+        let long = user_message(vec![UserMessageContent::Text("a".repeat(100))]);
+        assert_eq!(router.route(&ctx, Some(&long)), None);
+    }
+
+    #[test]
+    fn routes_by_scope_platform_and_pattern() {
+        let router = ModelRouter::new(&[route(
+            "coder",
+            ConfigLlmRouteCondition {
+                scope: Some("coding".to_string()),
+                platform: Some("discord".to_string()),
+                pattern: Some(r"(?i)\brust\b".to_string()),
+                ..Default::default()
+            },
+        )])
+        .expect("should compile");
+        let message = user_message(vec![UserMessageContent::Text("Rust で書いて".to_string())]);
+
+        let scoped = MessageContext::new_user("discord:1234", UserRole::scoped_with(["coding"]));
+        assert_eq!(router.route(&scoped, Some(&message)), Some((0, "coder")));
+
+        let other_platform = MessageContext::new_user("mastodon:tester", UserRole::scoped_with(["coding"]));
+        assert_eq!(router.route(&other_platform, Some(&message)), None);
+
+        let normal = MessageContext::new_user("discord:1234", UserRole::Normal);
+        assert_eq!(router.route(&normal, Some(&message)), None);
+    }
+
+    #[test]
+    fn rejects_invalid_pattern() {
+        let routes = [route(
+            "broken",
+            ConfigLlmRouteCondition {
+                pattern: Some("(".to_string()),
+                ..Default::default()
+            },
+        )];
+        assert!(ModelRouter::new(&routes).is_err());
+    }
+}
@@ -0,0 +1,783 @@
+//! `Natsuki` 全体を通したテスト。
+//! LLM は `ConfigLlmBackend::Scripted` で fixture の応答を再生し、ネットワークには接続しない。
+
+use crate::{
+    bang_command::initialize_bang_command,
+    natsuki::{FunctionStore, LlmCache, Natsuki, function_store::FunctionStoreError},
+    storage::MemoryConversationStorage,
+};
+
+use std::{collections::HashMap, sync::Arc};
+
+use futures::{FutureExt, future::BoxFuture};
+use lnb_common::{
+    config::{
+        assistant::{ConfigAssistant, ConfigAssistantPersona},
+        llm::{ConfigLlm, ConfigLlmBackend, ConfigLlmLimitPolicy, ConfigLlmLimits, ConfigLlmModel},
+        tools::{ConfigTools, ConfigToolsPolicy},
+    },
+    extension::hash_identity,
+};
+use lnb_core::{
+    RFC3339_NUMOFFSET,
+    context::Context,
+    error::{FunctionError, ServerError},
+    interface::{
+        MessageContext,
+        function::{ArcFunction, Function, FunctionDescriptor, FunctionResponse},
+        server::LnbServer,
+        storage::ConversationStorage,
+        time::DateTimeProvider,
+    },
+    model::{
+        conversation::{
+            Conversation, ConversationAttachment, ConversationId, ConversationPersona, ConversationUpdate,
+            IncompleteConversation,
+        },
+        message::{
+            AssistantActions, AssistantMessage, AssistantVisibility, Message, MessageToolCalling, UserMessageContent,
+        },
+        schema::DescribedSchema,
+        user_role::UserRole,
+    },
+};
+use serde_json::json;
+use time::{OffsetDateTime, macros::datetime};
+use tokio::{
+    sync::Barrier,
+    time::{Duration, sleep},
+};
+
+const SCRIPTED_MODEL: &str = "scripted";
+const SENSITIVE_MARKER: &str = "[NSFW]";
+const HELPER_PERSONA: &str = "helper";
+const FIXED_NOW: OffsetDateTime = datetime!(2025-04-01 12:00 +09:00);
+
+/// 常に同じ日時を返す。
+#[derive(Debug)]
+struct FixedDateTimeProvider(OffsetDateTime);
+
+impl DateTimeProvider for FixedDateTimeProvider {
+    fn now(&self) -> OffsetDateTime {
+        self.0
+    }
+}
+
+/// 引数の説明付きの画像を添付する。
+struct AttachImage;
+
+impl Function for AttachImage {
+    fn get_descriptor(&self) -> FunctionDescriptor {
+        FunctionDescriptor {
+            name: "attach_image".to_string(),
+            description: "画像を添付する。".to_string(),
+            parameters: DescribedSchema::object(
+                "parameters",
+                "引数",
+                vec![DescribedSchema::string("description", "画像の説明。")],
+            ),
+        }
+    }
+
+    fn call<'a>(
+        &'a self,
+        _ctx: &'a Context,
+        _message_ctx: &'a MessageContext,
+        _incomplete: &'a IncompleteConversation,
+        tool_calling: MessageToolCalling,
+    ) -> BoxFuture<'a, Result<FunctionResponse, FunctionError>> {
+        let description = tool_calling.arguments["description"].as_str().map(str::to_string);
+        async move {
+            Ok(FunctionResponse {
+                result: json!({ "attached": true }),
+                attachments: vec![ConversationAttachment::Image {
+                    bytes: vec![0x89, 0x50, 0x4E, 0x47],
+                    description,
+                }],
+            })
+        }
+        .boxed()
+    }
+}
+
+/// `Context` の現在時刻を返す。
+struct Clock;
+
+impl Function for Clock {
+    fn get_descriptor(&self) -> FunctionDescriptor {
+        FunctionDescriptor {
+            name: "clock".to_string(),
+            description: "現在時刻を返す。".to_string(),
+            parameters: DescribedSchema::object("parameters", "引数", vec![]),
+        }
+    }
+
+    fn call<'a>(
+        &'a self,
+        ctx: &'a Context,
+        _message_ctx: &'a MessageContext,
+        _incomplete: &'a IncompleteConversation,
+        _tool_calling: MessageToolCalling,
+    ) -> BoxFuture<'a, Result<FunctionResponse, FunctionError>> {
+        async move {
+            let now = ctx
+                .datetime_provider
+                .now()
+                .format(RFC3339_NUMOFFSET)
+                .map_err(FunctionError::by_serialization)?;
+            Ok(FunctionResponse {
+                result: json!({ "now": now }),
+                ..Default::default()
+            })
+        }
+        .boxed()
+    }
+}
+
+/// 同名の組の全員が呼び出されるまで待つ。
+struct Rendezvous {
+    name: &'static str,
+    barrier: Arc<Barrier>,
+}
+
+impl Function for Rendezvous {
+    fn get_descriptor(&self) -> FunctionDescriptor {
+        FunctionDescriptor {
+            name: self.name.to_string(),
+            description: "他の呼び出しを待つ。".to_string(),
+            parameters: DescribedSchema::object("parameters", "引数", vec![]),
+        }
+    }
+
+    fn call<'a>(
+        &'a self,
+        _ctx: &'a Context,
+        _message_ctx: &'a MessageContext,
+        _incomplete: &'a IncompleteConversation,
+        _tool_calling: MessageToolCalling,
+    ) -> BoxFuture<'a, Result<FunctionResponse, FunctionError>> {
+        async move {
+            self.barrier.wait().await;
+            Ok(FunctionResponse {
+                result: json!({ "name": self.name }),
+                ..Default::default()
+            })
+        }
+        .boxed()
+    }
+}
+
+/// 応答しない。
+struct Sleeper;
+
+impl Function for Sleeper {
+    fn get_descriptor(&self) -> FunctionDescriptor {
+        FunctionDescriptor {
+            name: "sleeper".to_string(),
+            description: "ずっと寝ている。".to_string(),
+            parameters: DescribedSchema::object("parameters", "引数", vec![]),
+        }
+    }
+
+    fn call<'a>(
+        &'a self,
+        _ctx: &'a Context,
+        _message_ctx: &'a MessageContext,
+        _incomplete: &'a IncompleteConversation,
+        _tool_calling: MessageToolCalling,
+    ) -> BoxFuture<'a, Result<FunctionResponse, FunctionError>> {
+        async move {
+            sleep(Duration::from_secs(3600)).await;
+            Ok(FunctionResponse::default())
+        }
+        .boxed()
+    }
+}
+
+/// 常に失敗する。
+struct Broken;
+
+impl Function for Broken {
+    fn get_descriptor(&self) -> FunctionDescriptor {
+        FunctionDescriptor {
+            name: "broken".to_string(),
+            description: "常に失敗する。".to_string(),
+            parameters: DescribedSchema::object("parameters", "引数", vec![]),
+        }
+    }
+
+    fn call<'a>(
+        &'a self,
+        _ctx: &'a Context,
+        _message_ctx: &'a MessageContext,
+        _incomplete: &'a IncompleteConversation,
+        _tool_calling: MessageToolCalling,
+    ) -> BoxFuture<'a, Result<FunctionResponse, FunctionError>> {
+        async { Err(FunctionError::by_external("upstream unavailable")) }.boxed()
+    }
+}
+
+/// 引数のスキーマにコンパイルできない pattern を含む。
+struct MalformedPattern;
+
+impl Function for MalformedPattern {
+    fn get_descriptor(&self) -> FunctionDescriptor {
+        FunctionDescriptor {
+            name: "malformed_pattern".to_string(),
+            description: "壊れたスキーマを持つ。".to_string(),
+            parameters: DescribedSchema::object(
+                "parameters",
+                "引数",
+                vec![DescribedSchema::string("code", "コード").with_pattern("[A-Z")],
+            ),
+        }
+    }
+
+    fn call<'a>(
+        &'a self,
+        _ctx: &'a Context,
+        _message_ctx: &'a MessageContext,
+        _incomplete: &'a IncompleteConversation,
+        _tool_calling: MessageToolCalling,
+    ) -> BoxFuture<'a, Result<FunctionResponse, FunctionError>> {
+        async { Ok(FunctionResponse::default()) }.boxed()
+    }
+}
+
+struct Harness {
+    natsuki: Natsuki,
+    storage: MemoryConversationStorage,
+}
+
+impl Harness {
+    async fn new(fixture: &str, functions: Vec<ArcFunction>) -> Harness {
+        Harness::with_tools(fixture, functions, ConfigTools::default()).await
+    }
+
+    async fn with_tools(fixture: &str, functions: Vec<ArcFunction>, tools_config: ConfigTools) -> Harness {
+        Harness::build(fixture, functions, tools_config, ConfigLlmLimits::default()).await
+    }
+
+    async fn with_limits(fixture: &str, functions: Vec<ArcFunction>, limits: ConfigLlmLimits) -> Harness {
+        Harness::build(fixture, functions, ConfigTools::default(), limits).await
+    }
+
+    async fn build(
+        fixture: &str,
+        functions: Vec<ArcFunction>,
+        tools_config: ConfigTools,
+        limits: ConfigLlmLimits,
+    ) -> Harness {
+        let fixture_path = format!("{}/fixtures/scripted/{fixture}.json", env!("CARGO_MANIFEST_DIR"));
+        let llm_config = ConfigLlm {
+            default: SCRIPTED_MODEL.to_string(),
+            models: HashMap::from([(
+                SCRIPTED_MODEL.to_string(),
+                ConfigLlmModel {
+                    backend: ConfigLlmBackend::Scripted,
+                    config: json!({ "fixture": fixture_path }),
+                    price: None,
+                    context: None,
+                    limits: None,
+                },
+            )]),
+            initialization: Default::default(),
+            limits,
+            role_limits: Default::default(),
+            routes: vec![],
+        };
+        let assistant_config = ConfigAssistant {
+            system_role: "あなたは夏稀です。現在時刻は {{ datetime }} です。".to_string(),
+            sensitive_marker: SENSITIVE_MARKER.to_string(),
+            response_description: None,
+            personas: HashMap::from([(
+                HELPER_PERSONA.to_string(),
+                ConfigAssistantPersona {
+                    system_role: "あなたは中立的なアシスタントです。".to_string(),
+                    model: None,
+                    tools: Some(vec![]),
+                    response_description: None,
+                },
+            )]),
+        };
+
+        let storage = MemoryConversationStorage::new();
+        let natsuki = Natsuki::new(
+            Box::new(storage.clone()),
+            None,
+            LlmCache::new(&llm_config).expect("should build LlmCache"),
+            FunctionStore::new(functions, &tools_config).expect("should build FunctionStore"),
+            [initialize_bang_command(&assistant_config).await.into()],
+            &assistant_config,
+            Arc::new(FixedDateTimeProvider(FIXED_NOW)),
+        )
+        .await
+        .expect("should build Natsuki");
+
+        Harness { natsuki, storage }
+    }
+
+    /// 新しい会話で 1 回やりとりして保存する。
+    async fn talk(&self, text: &str) -> (ConversationId, ConversationUpdate) {
+        self.talk_as(ConversationPersona::Default, text).await
+    }
+
+    /// `persona` の新しい会話で 1 回やりとりして保存する。
+    async fn talk_as(&self, persona: ConversationPersona, text: &str) -> (ConversationId, ConversationUpdate) {
+        let conversation_id = self.natsuki.new_conversation(persona).await.expect("should create");
+        let user_message = Message::new_user([UserMessageContent::Text(text.to_string())], None, None, false);
+        let update = self
+            .natsuki
+            .process_conversation(
+                MessageContext::new_user("tester", UserRole::Normal),
+                conversation_id,
+                vec![user_message],
+            )
+            .await
+            .expect("should process");
+        self.natsuki
+            .save_conversation(update.clone(), "test")
+            .await
+            .expect("should save");
+        (conversation_id, update)
+    }
+
+    async fn stored_messages(&self, conversation_id: ConversationId) -> Vec<Message> {
+        self.stored_conversation(conversation_id).await.messages().to_vec()
+    }
+
+    async fn stored_conversation(&self, conversation_id: ConversationId) -> Conversation {
+        self.storage
+            .fetch_content_by_id(conversation_id)
+            .await
+            .expect("should fetch")
+            .expect("should exist")
+    }
+}
+
+#[tokio::test]
+async fn finishes_with_scripted_text() {
+    let harness = Harness::new("finished", vec![]).await;
+    let (conversation_id, update) = harness.talk("こんにちは").await;
+
+    assert_eq!(update.assistant_response().text, "こんにちは、先輩！");
+    assert_eq!(update.answered_model(), Some(SCRIPTED_MODEL));
+
+    let messages = harness.stored_messages(conversation_id).await;
+    assert!(matches!(&messages[0], Message::System(s) if s.0.contains("2025年4月1日 12時0分")));
+    assert!(matches!(&messages[1], Message::User(_)));
+    assert!(matches!(&messages[2], Message::Assistant(_)));
+}
+
+#[tokio::test]
+async fn tool_attachment_reaches_update() {
+    let harness = Harness::new("tool_attachment", vec![Arc::new(AttachImage)]).await;
+    let (conversation_id, update) = harness.talk("猫の絵を描いて").await;
+
+    assert_eq!(update.assistant_response().text, "猫の絵を添付したよ。");
+    let [ConversationAttachment::Image { description, .. }] = update.attachments() else {
+        panic!("exactly one image should be attached");
+    };
+    assert_eq!(description.as_deref(), Some("猫の絵"));
+
+    let messages = harness.stored_messages(conversation_id).await;
+    assert!(matches!(&messages[2], Message::FunctionCalls(c) if c.0[0].name == "attach_image"));
+    assert!(matches!(&messages[3], Message::FunctionResponse(r) if r.id == "call_attach"));
+}
+
+#[tokio::test]
+async fn tool_receives_fixed_clock() {
+    let harness = Harness::new("tool_clock", vec![Arc::new(Clock)]).await;
+    let (conversation_id, _) = harness.talk("今何時？").await;
+
+    let messages = harness.stored_messages(conversation_id).await;
+    let Some(Message::FunctionResponse(response)) = messages.get(3) else {
+        panic!("function response should be stored");
+    };
+    assert_eq!(response.result, json!({ "now": "2025-04-01T12:00:00+09:00" }));
+}
+
+#[tokio::test]
+async fn response_chain_continues_with_new_messages() {
+    let harness = Harness::new("response_chain", vec![Arc::new(Clock)]).await;
+    let (conversation_id, _) = harness.talk("今何時？").await;
+
+    // system, user, function calls, function response, assistant
+    let conversation = harness.stored_conversation(conversation_id).await;
+    let chain = conversation.response_chain(SCRIPTED_MODEL).expect("should be recorded");
+    assert_eq!(chain.response_id, "resp_final");
+    assert_eq!(chain.covered, 4);
+
+    let mut incomplete = IncompleteConversation::start(conversation);
+    let user_message = Message::new_user([UserMessageContent::Text("ありがとう".to_string())], None, None, false);
+    incomplete.extend_messages([user_message]);
+    let (previous_response_id, messages) = incomplete.chained_messages(SCRIPTED_MODEL).expect("should be intact");
+    assert_eq!(previous_response_id, "resp_final");
+    assert!(matches!(messages[..], [Message::User(_)]));
+    assert!(incomplete.chained_messages("other").is_none());
+
+    // 他のモデルが応答した後は全履歴を送る
+    incomplete.push_assistant(AssistantMessage {
+        text: "どういたしまして".to_string(),
+        ..Default::default()
+    });
+    incomplete.extend_messages([Message::new_user(
+        [UserMessageContent::Text("またね".to_string())],
+        None,
+        None,
+        false,
+    )]);
+    assert!(incomplete.chained_messages(SCRIPTED_MODEL).is_none());
+}
+
+#[tokio::test]
+async fn reasoning_summaries_are_stored() {
+    let harness = Harness::new("reasoning", vec![Arc::new(Clock)]).await;
+    let (conversation_id, update) = harness.talk("今何時？").await;
+
+    assert_eq!(update.assistant_response().text, "今はお昼の 12 時だよ。");
+    let messages = harness.stored_messages(conversation_id).await;
+    let Some(Message::Assistant(assistant)) = messages.last() else {
+        panic!("assistant message should be stored");
+    };
+    assert_eq!(
+        assistant.reasoning_summary.as_deref(),
+        Some("時刻を聞かれたので clock で確認する。\n\nclock の結果をそのまま伝える。")
+    );
+}
+
+#[tokio::test]
+async fn tool_call_is_recorded() {
+    let harness = Harness::new("tool_clock", vec![Arc::new(Clock)]).await;
+    let (conversation_id, _) = harness.talk("今何時？").await;
+
+    let records = harness.storage.tool_calls().await;
+    assert_eq!(records.len(), 1);
+    assert_eq!(records[0].conversation_id, conversation_id);
+    assert_eq!(records[0].identity, hash_identity("tester"));
+    assert_eq!(records[0].tool_name, "clock");
+    assert_eq!(records[0].arguments, json!({}));
+    assert_eq!(
+        records[0].result_summary.as_deref(),
+        Some(r#"{"now":"2025-04-01T12:00:00+09:00"}"#)
+    );
+    assert_eq!(records[0].error, None);
+    assert_eq!(records[0].called_at, FIXED_NOW);
+}
+
+#[test]
+fn invalid_timeout_is_rejected() {
+    for timeout_seconds in [-1.0, f64::NAN, f64::INFINITY] {
+        let tools_config = ConfigTools {
+            policies: HashMap::from([(
+                "clock".to_string(),
+                ConfigToolsPolicy {
+                    timeout_seconds: Some(timeout_seconds),
+                    ..Default::default()
+                },
+            )]),
+            ..Default::default()
+        };
+        let result = FunctionStore::new([Arc::new(Clock) as ArcFunction], &tools_config);
+        assert!(
+            matches!(result, Err(FunctionStoreError::InvalidTimeout(ref name, _)) if name == "clock"),
+            "{timeout_seconds}"
+        );
+    }
+}
+
+#[test]
+fn invalid_or_duplicate_names_are_rejected() {
+    let rendezvous = |name| {
+        Arc::new(Rendezvous {
+            name,
+            barrier: Arc::new(Barrier::new(1)),
+        }) as ArcFunction
+    };
+    let too_long = "0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef_";
+    for name in ["", "get weather", "天気", too_long] {
+        let result = FunctionStore::new([rendezvous(name)], &ConfigTools::default());
+        assert!(
+            matches!(result, Err(FunctionStoreError::InvalidName(ref n)) if n == name),
+            "{name}"
+        );
+    }
+
+    let result = FunctionStore::new([rendezvous("clock"), Arc::new(Clock)], &ConfigTools::default());
+    assert!(matches!(result, Err(FunctionStoreError::DuplicateName(ref n)) if n == "clock"));
+}
+
+#[test]
+fn invalid_pattern_is_rejected() {
+    let result = FunctionStore::new([Arc::new(MalformedPattern) as ArcFunction], &ConfigTools::default());
+    assert!(matches!(
+        result,
+        Err(FunctionStoreError::InvalidSchema { ref name, ref source })
+            if name == "malformed_pattern" && source.path == "parameters.code"
+    ));
+}
+
+#[tokio::test]
+async fn tools_run_concurrently_with_timeout() {
+    let barrier = Arc::new(Barrier::new(2));
+    let functions: Vec<ArcFunction> = vec![
+        Arc::new(Rendezvous {
+            name: "rendezvous_a",
+            barrier: barrier.clone(),
+        }),
+        Arc::new(Rendezvous {
+            name: "rendezvous_b",
+            barrier,
+        }),
+        Arc::new(Sleeper),
+    ];
+    let tools_config = ConfigTools {
+        default_timeout_seconds: Some(10.0),
+        policies: HashMap::from([(
+            "sleeper".to_string(),
+            ConfigToolsPolicy {
+                timeout_seconds: Some(0.1),
+                ..Default::default()
+            },
+        )]),
+        ..Default::default()
+    };
+
+    // 順に呼び出すと rendezvous_a が rendezvous_b を待ち続けてタイムアウトする
+    let harness = Harness::with_tools("tool_concurrent", functions, tools_config).await;
+    let (conversation_id, update) = harness.talk("全部呼んで").await;
+    assert_eq!(update.assistant_response().text, "全部呼び出したよ。");
+
+    let messages = harness.stored_messages(conversation_id).await;
+    let results: Vec<_> = messages
+        .iter()
+        .filter_map(|m| match m {
+            Message::FunctionResponse(r) => Some((r.id.as_str(), &r.result)),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(results.len(), 3);
+    assert_eq!(results[0], ("call_a", &json!({ "name": "rendezvous_a" })));
+    assert_eq!(results[1], ("call_b", &json!({ "name": "rendezvous_b" })));
+    assert_eq!(results[2].0, "call_sleeper");
+    assert_eq!(results[2].1["status"], "timeout");
+}
+
+#[tokio::test]
+async fn tool_limit_truncates_response() {
+    let limits = ConfigLlmLimits {
+        max_tool_rounds: Some(1),
+        on_exceeded: Some(ConfigLlmLimitPolicy::Truncate),
+        ..Default::default()
+    };
+    let harness = Harness::with_limits("tool_loop_after_text", vec![Arc::new(Clock)], limits).await;
+    let (conversation_id, update) = harness.talk("今何時？").await;
+    assert_eq!(update.assistant_response().text, "時計を見てみるね。(omitted)");
+
+    // 打ち切りの応答は LLM に送らない
+    let messages = harness.stored_messages(conversation_id).await;
+    assert!(matches!(messages.last(), Some(Message::Assistant(a)) if a.skip_llm));
+}
+
+#[tokio::test]
+async fn tool_limit_without_text_is_error() {
+    let limits = ConfigLlmLimits {
+        max_tool_rounds: Some(1),
+        on_exceeded: Some(ConfigLlmLimitPolicy::Truncate),
+        ..Default::default()
+    };
+    let harness = Harness::with_limits("tool_loop", vec![Arc::new(Clock)], limits).await;
+    let conversation_id = harness
+        .natsuki
+        .new_conversation(ConversationPersona::Default)
+        .await
+        .expect("should create");
+    let user_message = Message::new_user([UserMessageContent::Text("今何時？".to_string())], None, None, false);
+    let result = harness
+        .natsuki
+        .process_conversation(
+            MessageContext::new_user("tester", UserRole::Normal),
+            conversation_id,
+            vec![user_message],
+        )
+        .await;
+    assert!(matches!(result, Err(ServerError::TooMuchConversationCall)));
+}
+
+#[tokio::test]
+async fn tool_limit_forces_final_answer() {
+    let limits = ConfigLlmLimits {
+        max_tool_rounds: Some(1),
+        on_exceeded: Some(ConfigLlmLimitPolicy::FinalAnswer),
+        ..Default::default()
+    };
+    let harness = Harness::with_limits("tool_loop", vec![Arc::new(Clock)], limits).await;
+    let (conversation_id, update) = harness.talk("今何時？").await;
+    assert_eq!(update.assistant_response().text, "今はお昼の 12 時だよ。");
+
+    // 2 回目の tool calling は実行されない
+    let records = harness.storage.tool_calls().await;
+    assert_eq!(records.len(), 1);
+    let messages = harness.stored_messages(conversation_id).await;
+    assert_eq!(messages.len(), 5);
+}
+
+#[tokio::test]
+async fn continuation_limit_truncates_response() {
+    let limits = ConfigLlmLimits {
+        max_continuation_rounds: Some(1),
+        on_exceeded: Some(ConfigLlmLimitPolicy::Truncate),
+        ..Default::default()
+    };
+    let harness = Harness::with_limits("length_cut_loop", vec![], limits).await;
+    let (_, update) = harness.talk("昔話をして").await;
+    assert_eq!(update.assistant_response().text, "むかしむかし、あるところに(omitted)");
+}
+
+#[tokio::test]
+async fn total_limit_covers_both_rounds() {
+    let limits = ConfigLlmLimits {
+        max_tool_rounds: Some(4),
+        max_continuation_rounds: Some(4),
+        max_total_rounds: Some(1),
+        on_exceeded: Some(ConfigLlmLimitPolicy::Truncate),
+    };
+    let harness = Harness::with_limits("tool_loop_after_text", vec![Arc::new(Clock)], limits).await;
+    let (_, update) = harness.talk("今何時？").await;
+    assert_eq!(update.assistant_response().text, "時計を見てみるね。(omitted)");
+
+    // 続きの生成で使い切ったので tool calling は実行されない
+    assert!(harness.storage.tool_calls().await.is_empty());
+}
+
+#[tokio::test]
+async fn unknown_tool_is_answered_with_error() {
+    let harness = Harness::new("unknown_tool", vec![]).await;
+    let (conversation_id, update) = harness.talk("何かして").await;
+    assert_eq!(update.assistant_response().text, "その機能は使えないみたい。");
+
+    let messages = harness.stored_messages(conversation_id).await;
+    let Some(Message::FunctionResponse(response)) = messages.get(3) else {
+        panic!("function response should be stored");
+    };
+    assert_eq!(response.id, "call_missing");
+    assert_eq!(response.result["error"], "not_found");
+}
+
+#[tokio::test]
+async fn scoped_tool_is_hidden_from_normal_user() {
+    let tools_config = ConfigTools {
+        policies: HashMap::from([(
+            "clock".to_string(),
+            ConfigToolsPolicy {
+                required_scope: Some("clock".to_string()),
+                ..Default::default()
+            },
+        )]),
+        ..Default::default()
+    };
+    let harness = Harness::with_tools("tool_clock", vec![Arc::new(Clock)], tools_config).await;
+    let (conversation_id, _) = harness.talk("今何時？").await;
+
+    let messages = harness.stored_messages(conversation_id).await;
+    let Some(Message::FunctionResponse(response)) = messages.get(3) else {
+        panic!("function response should be stored");
+    };
+    assert_eq!(response.result["error"], "not_found");
+}
+
+#[tokio::test]
+async fn persona_without_tool_hides_it() {
+    let harness = Harness::new("tool_clock", vec![Arc::new(Clock)]).await;
+    let persona = ConversationPersona::Named(HELPER_PERSONA.to_string());
+    let (conversation_id, _) = harness.talk_as(persona, "今何時？").await;
+
+    let messages = harness.stored_messages(conversation_id).await;
+    assert!(matches!(&messages[0], Message::System(s) if s.0.contains("中立的なアシスタント")));
+    let Some(Message::FunctionResponse(response)) = messages.get(3) else {
+        panic!("function response should be stored");
+    };
+    assert_eq!(response.result["error"], "not_found");
+}
+
+#[tokio::test]
+async fn persona_command_replaces_system_role() {
+    let harness = Harness::new("empty", vec![]).await;
+    let (conversation_id, update) = harness.talk(&format!("!persona {HELPER_PERSONA}")).await;
+    assert_eq!(update.assistant_response().text, "persona changed to helper");
+
+    let conversation = harness.stored_conversation(conversation_id).await;
+    assert_eq!(
+        conversation.persona(),
+        &ConversationPersona::Named(HELPER_PERSONA.to_string())
+    );
+    assert!(matches!(&conversation.messages()[0], Message::System(s) if s.0.contains("中立的なアシスタント")));
+
+    let (_, update) = harness.talk("!persona nobody").await;
+    assert_eq!(update.assistant_response().text, "unknown persona: nobody");
+}
+
+#[tokio::test]
+async fn invalid_arguments_are_answered_with_error() {
+    let harness = Harness::new("tool_invalid_arguments", vec![Arc::new(AttachImage)]).await;
+    let (conversation_id, update) = harness.talk("猫の絵を描いて").await;
+    assert_eq!(update.assistant_response().text, "うまく描けなかったみたい。");
+    assert!(update.attachments().is_empty(), "function should not be called");
+
+    let messages = harness.stored_messages(conversation_id).await;
+    let Some(Message::FunctionResponse(response)) = messages.get(3) else {
+        panic!("function response should be stored");
+    };
+    assert_eq!(response.result["error"], "invalid_arguments");
+    assert_eq!(response.result["message"], "parameters.description: must be a string");
+}
+
+#[tokio::test]
+async fn function_error_is_answered_with_error() {
+    let harness = Harness::new("tool_failing", vec![Arc::new(Broken)]).await;
+    let (conversation_id, update) = harness.talk("為替を調べて").await;
+    assert_eq!(update.assistant_response().text, "ごめん、今は調べられないみたい。");
+
+    let messages = harness.stored_messages(conversation_id).await;
+    let Some(Message::FunctionResponse(response)) = messages.get(3) else {
+        panic!("function response should be stored");
+    };
+    assert_eq!(response.result["error"], "external");
+    assert_eq!(response.result["message"], "upstream unavailable");
+}
+
+#[tokio::test]
+async fn sensitive_marker_is_stripped() {
+    let harness = Harness::new("sensitive", vec![]).await;
+    let (_, update) = harness.talk("秘密を教えて").await;
+
+    assert_eq!(update.assistant_response().text, "ないしょの話だよ。");
+    assert!(update.assistant_response().is_sensitive);
+}
+
+#[tokio::test]
+async fn structured_actions_reach_update() {
+    let harness = Harness::new("actions", vec![]).await;
+    let (_, update) = harness.talk("あの映画のラストどうだった？").await;
+
+    let actions = update.actions();
+    assert_eq!(actions.reaction.as_deref(), Some("👍"));
+    assert_eq!(actions.content_warning.as_deref(), Some("映画の結末"));
+    assert_eq!(actions.visibility, Some(AssistantVisibility::Private));
+    assert!(!actions.no_reply);
+
+    // 動作の指定がなければ何もしない
+    let harness = Harness::new("finished", vec![]).await;
+    let (_, update) = harness.talk("こんにちは").await;
+    assert_eq!(update.actions(), &AssistantActions::default());
+}
+
+#[tokio::test]
+async fn bang_command_bypasses_llm() {
+    // 応答が定義されていないので LLM が呼ばれると失敗する
+    let harness = Harness::new("empty", vec![]).await;
+    let (_, update) = harness.talk("!ping").await;
+
+    assert_eq!(update.assistant_response().text, "pong");
+    assert_eq!(update.answered_model(), None);
+}
@@ -0,0 +1,119 @@
+use crate::natsuki::LlmCache;
+
+use std::{collections::HashMap, path::PathBuf};
+
+use anyhow::{Result, bail};
+use lnb_common::{
+    config::{load_config, tools::ConfigTools},
+    rate_limits::{RateLimits, load_rate_limits},
+    user_roles::{SharedUserRolesGroup, load_user_roles},
+};
+use lnb_rate_limiter::RateLimiter;
+use tokio::signal::unix::{SignalKind, signal};
+use tracing::{info, warn};
+
+/// 再読み込みで規則を差し替える `RateLimiter` 群。
+#[derive(Debug, Clone)]
+pub struct RateLimiters {
+    pub conversation: RateLimiter,
+    pub image_generator: RateLimiter,
+
+    /// HTTP tool が参照するカテゴリ名ごとの `RateLimiter` 。
+    pub tools: HashMap<String, RateLimiter>,
+}
+
+impl RateLimiters {
+    pub fn new(rate_limits: &RateLimits, tool_config: &ConfigTools) -> Result<RateLimiters> {
+        let mut tools = HashMap::new();
+        for category in tool_config.http_tools.iter().filter_map(|t| t.rate_limit.as_ref()) {
+            let Some(rate_limits_category) = rate_limits.tools.get(category) else {
+                bail!("rate limits category not found: {category}");
+            };
+            tools.insert(category.clone(), rate_limits_category.clone().try_into()?);
+        }
+
+        Ok(RateLimiters {
+            conversation: rate_limits.conversation.clone().try_into()?,
+            image_generator: rate_limits.image_generator.clone().try_into()?,
+            tools,
+        })
+    }
+
+    /// すべての規則を解釈できた場合のみ差し替える。
+    fn replace(&self, rate_limits: &RateLimits) -> Result<()> {
+        let conversation: RateLimiter = rate_limits.conversation.clone().try_into()?;
+        let image_generator: RateLimiter = rate_limits.image_generator.clone().try_into()?;
+        let mut tools = vec![];
+        for (category, rate_limiter) in &self.tools {
+            let Some(rate_limits_category) = rate_limits.tools.get(category) else {
+                bail!("rate limits category not found: {category}");
+            };
+            let source: RateLimiter = rate_limits_category.clone().try_into()?;
+            tools.push((rate_limiter, source));
+        }
+
+        self.conversation.replace_rules(&conversation);
+        self.image_generator.replace_rules(&image_generator);
+        for (rate_limiter, source) in tools {
+            rate_limiter.replace_rules(&source);
+        }
+        Ok(())
+    }
+}
+
+/// SIGHUP を受けて設定ファイルを読み直す。
+pub struct Reloader {
+    pub config_path: PathBuf,
+    pub rate_limits_path: PathBuf,
+    pub user_roles_path: PathBuf,
+    pub llm_cache: LlmCache,
+    pub rate_limiters: RateLimiters,
+    pub mastodon_roles: SharedUserRolesGroup,
+    pub discord_roles: SharedUserRolesGroup,
+}
+
+impl Reloader {
+    pub async fn run(self) {
+        let mut hangup = match signal(SignalKind::hangup()) {
+            Ok(s) => s,
+            Err(e) => {
+                warn!("failed to listen SIGHUP, reloading disabled: {e}");
+                return;
+            }
+        };
+
+        while hangup.recv().await.is_some() {
+            info!("SIGHUP received, reloading");
+            self.reload().await;
+        }
+    }
+
+    /// 読み込みに失敗したものは以前の状態のまま使い続ける。
+    async fn reload(&self) {
+        match load_config(&self.config_path) {
+            Ok(config) => {
+                if let Err(e) = self.llm_cache.reload(&config.llm).await {
+                    warn!("failed to reload LLM definitions: {e}");
+                }
+            }
+            Err(e) => warn!("failed to reload config: {e}"),
+        }
+
+        let rate_limits_result = load_rate_limits(&self.rate_limits_path)
+            .map_err(Into::into)
+            .and_then(|rate_limits| self.rate_limiters.replace(&rate_limits));
+        match rate_limits_result {
+            Ok(()) => info!("rate limits reloaded"),
+            Err(e) => warn!("failed to reload rate limits: {e}"),
+        }
+
+        match load_user_roles(&self.user_roles_path) {
+            Ok(user_roles) => {
+                self.mastodon_roles.replace(user_roles.mastodon);
+                self.discord_roles.replace(user_roles.discord);
+                info!("user roles reloaded");
+            }
+            Err(e) => warn!("failed to reload user roles: {e}"),
+        }
+    }
+}
@@ -8,7 +8,11 @@ use lnb_core::{
         function::{Function, FunctionDescriptor, FunctionResponse},
         reminder::{Remind, RemindableContext, Reminder},
     },
-    model::{conversation::IncompleteConversation, message::MessageToolCalling, schema::DescribedSchema},
+    model::{
+        conversation::IncompleteConversation,
+        message::MessageToolCalling,
+        schema::{DescribeSchema, DescribedSchema, StringFormat},
+    },
 };
 use serde::{Deserialize, Serialize};
 use time::{
@@ -35,27 +39,7 @@ impl Function for ShiyuProvider {
                 - 会話の中でリマインダーのキャンセルを要求された場合、そのリマインダーの設定時のレスポンスに含まれる id を cancel に指定してください。
             "#
             .to_string(),
-            parameters: DescribedSchema::object(
-                "parameters",
-                "引数",
-                vec![
-                    DescribedSchema::string(
-                        "remind_at",
-                        r#"
-                            リマインドする絶対時刻(RFC3339形式)。ユーザーが明示的に時刻を指定しなかった場合は日付のみを指定してください。
-                            相対時刻指定の場合は無視してください。
-                        "#,
-                    ).as_nullable(),
-                    DescribedSchema::string(
-                        "cancel",
-                        "ユーザーがキャンセルを要求したリマインドの id。新規設定時は無視してください。",
-                    ).as_nullable(),
-                    DescribedSchema::string(
-                        "content",
-                        "ユーザーがリマインドを希望した内容。キャンセルの要求時は空にしてください。",
-                    ),
-                ],
-            ),
+            parameters: ReminderParameters::describe_schema("parameters", "引数"),
         }
     }
 
@@ -99,7 +83,7 @@ impl ShiyuProvider {
             return self.cancel(cancel_id).await;
         }
 
-        let complete_remind_at = if let Some(remind_at) = parameters.remind_at {
+        let complete_remind_at = if let Some(RemindAt(remind_at)) = parameters.remind_at {
             if let Ok(full_datetime) = OffsetDateTime::parse(&remind_at, &Rfc3339) {
                 full_datetime
             } else if let Ok(date) = Date::parse(&remind_at, DATE_FORMAT) {
@@ -166,13 +150,38 @@ impl ShiyuProvider {
     }
 }
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Deserialize, DescribedSchema)]
 struct ReminderParameters {
-    remind_at: Option<String>,
+    /// リマインドする絶対時刻(RFC3339形式)。ユーザーが明示的に時刻を指定しなかった場合は日付のみを指定してください。
+    /// 相対時刻指定の場合は無視してください。
+    remind_at: Option<RemindAt>,
+
+    /// ユーザーがキャンセルを要求したリマインドの id。新規設定時は無視してください。
+    #[schema(format = "uuid")]
     cancel: Option<String>,
+
+    /// ユーザーがリマインドを希望した内容。キャンセルの要求時は空にしてください。
     content: String,
 }
 
+/// RFC3339 形式の日時か、日付のみ。
+#[derive(Debug, Clone, Deserialize)]
+#[serde(transparent)]
+struct RemindAt(String);
+
+impl DescribeSchema for RemindAt {
+    fn describe_schema(name: impl Into<String>, description: impl Into<String>) -> DescribedSchema {
+        DescribedSchema::union(
+            name,
+            description,
+            vec![
+                DescribedSchema::string("datetime", "RFC3339 形式の日時。").with_format(StringFormat::DateTime),
+                DescribedSchema::string("date", "YYYY-MM-DD 形式の日付。").with_format(StringFormat::Date),
+            ],
+        )
+    }
+}
+
 #[derive(Debug, Clone, Serialize)]
 #[serde(rename_all = "snake_case", tag = "status", content = "data")]
 enum ReminderResponse {
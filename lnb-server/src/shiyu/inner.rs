@@ -11,7 +11,10 @@ use lnb_core::{
         reminder::{Remind, Remindable},
         server::LnbServer,
     },
-    model::message::{UserMessage, UserMessageContent},
+    model::{
+        conversation::ConversationPersona,
+        message::{UserMessage, UserMessageContent},
+    },
 };
 use serde::{Deserialize, Serialize};
 use time::UtcDateTime;
@@ -130,7 +133,10 @@ impl ShiyuDispatcher {
         remind: Remind,
         virtual_text: Arc<str>,
     ) -> Result<(), ReminderError> {
-        let conversation_id = server.new_conversation().map_err(ReminderError::by_internal).await?;
+        let conversation_id = server
+            .new_conversation(ConversationPersona::Default)
+            .map_err(ReminderError::by_internal)
+            .await?;
         let text = format!("{}\n{}", virtual_text, remind.content);
         let user_message = UserMessage {
             contents: vec![UserMessageContent::Text(text)],
@@ -4,6 +4,9 @@ mod sqlite;
 use lnb_common::config::storage::{ConfigStorage, ConfigStorageBackend};
 use lnb_core::{error::StorageError, interface::storage::BoxConversationStorage};
 
+#[cfg(test)]
+pub use memory::MemoryConversationStorage;
+
 pub async fn initialize_storage(config: &ConfigStorage) -> Result<BoxConversationStorage, StorageError> {
     match config.backend {
         ConfigStorageBackend::Memory => Ok(Box::new(memory::MemoryConversationStorage::new())),
@@ -4,9 +4,13 @@ use bimap::BiHashMap;
 use futures::{FutureExt, future::BoxFuture};
 use lnb_core::{
     error::StorageError,
-    interface::storage::ConversationStorage,
+    interface::{
+        llm::LlmUsage,
+        storage::{ConversationStorage, ToolCallRecord},
+    },
     model::conversation::{Conversation, ConversationId},
 };
+use time::Date;
 use tokio::sync::Mutex;
 
 #[derive(Debug, Clone)]
@@ -17,8 +21,15 @@ impl MemoryConversationStorage {
         MemoryConversationStorage(Arc::new(MemoryConversationStorageInner {
             conversations: Mutex::new(HashMap::new()),
             context_keys: Mutex::new(BiHashMap::new()),
+            usages: Mutex::new(HashMap::new()),
+            tool_calls: Mutex::new(vec![]),
         }))
     }
+
+    #[cfg(test)]
+    pub async fn tool_calls(&self) -> Vec<ToolCallRecord> {
+        self.0.tool_calls.lock().await.clone()
+    }
 }
 
 impl ConversationStorage for MemoryConversationStorage {
@@ -51,12 +62,28 @@ impl ConversationStorage for MemoryConversationStorage {
     ) -> BoxFuture<'a, Result<(), StorageError>> {
         async move { self.0.upsert(conversation, context_key).await }.boxed()
     }
+
+    fn add_usage<'a>(
+        &'a self,
+        identity: &'a str,
+        model: &'a str,
+        date: Date,
+        usage: LlmUsage,
+    ) -> BoxFuture<'a, Result<(), StorageError>> {
+        async move { self.0.add_usage(identity, model, date, usage).await }.boxed()
+    }
+
+    fn add_tool_call<'a>(&'a self, record: &'a ToolCallRecord) -> BoxFuture<'a, Result<(), StorageError>> {
+        async move { self.0.add_tool_call(record).await }.boxed()
+    }
 }
 
 #[derive(Debug)]
 struct MemoryConversationStorageInner {
     conversations: Mutex<HashMap<ConversationId, Conversation>>,
     context_keys: Mutex<BiHashMap<String, ConversationId>>,
+    usages: Mutex<HashMap<(String, String, Date), LlmUsage>>,
+    tool_calls: Mutex<Vec<ToolCallRecord>>,
 }
 
 impl MemoryConversationStorageInner {
@@ -93,4 +120,17 @@ impl MemoryConversationStorageInner {
         }
         Ok(())
     }
+
+    async fn add_usage(&self, identity: &str, model: &str, date: Date, usage: LlmUsage) -> Result<(), StorageError> {
+        let mut locked_usages = self.usages.lock().await;
+        let key = (identity.to_string(), model.to_string(), date);
+        *locked_usages.entry(key).or_default() += usage;
+        Ok(())
+    }
+
+    async fn add_tool_call(&self, record: &ToolCallRecord) -> Result<(), StorageError> {
+        let mut locked_tool_calls = self.tool_calls.lock().await;
+        locked_tool_calls.push(record.clone());
+        Ok(())
+    }
 }
@@ -1,12 +1,20 @@
 use std::sync::Arc;
 
 use futures::{FutureExt, TryFutureExt, future::BoxFuture};
-use lnb_common::{config::storage::ConfigStorageSqlite, persistence::SqliteConversationDb};
+use lnb_common::{
+    config::storage::ConfigStorageSqlite,
+    persistence::{SqliteConversationDb, SqliteToolCallDb, SqliteUsageDb},
+};
 use lnb_core::{
     error::StorageError,
-    interface::storage::ConversationStorage,
+    interface::{
+        llm::LlmUsage,
+        storage::{ConversationStorage, ToolCallRecord},
+    },
     model::conversation::{Conversation, ConversationId},
 };
+use time::{Date, Duration};
+use tracing::debug;
 
 #[derive(Debug, Clone)]
 pub struct SqliteConversationStorage(Arc<SqliteConversationStorageInner>);
@@ -16,8 +24,15 @@ impl SqliteConversationStorage {
         let db = SqliteConversationDb::connect(config)
             .map_err(StorageError::by_backend)
             .await?;
+        let usage_db = SqliteUsageDb::connect(config).map_err(StorageError::by_backend).await?;
+        let tool_call_db = SqliteToolCallDb::connect(config)
+            .map_err(StorageError::by_backend)
+            .await?;
         Ok(SqliteConversationStorage(Arc::new(SqliteConversationStorageInner {
             db,
+            usage_db,
+            tool_call_db,
+            tool_call_retention: config.tool_call_retention_days.map(|d| Duration::days(d as i64)),
         })))
     }
 }
@@ -52,11 +67,28 @@ impl ConversationStorage for SqliteConversationStorage {
     ) -> BoxFuture<'a, Result<(), StorageError>> {
         async move { self.0.upsert(conversation, context_key).await }.boxed()
     }
+
+    fn add_usage<'a>(
+        &'a self,
+        identity: &'a str,
+        model: &'a str,
+        date: Date,
+        usage: LlmUsage,
+    ) -> BoxFuture<'a, Result<(), StorageError>> {
+        async move { self.0.add_usage(identity, model, date, usage).await }.boxed()
+    }
+
+    fn add_tool_call<'a>(&'a self, record: &'a ToolCallRecord) -> BoxFuture<'a, Result<(), StorageError>> {
+        async move { self.0.add_tool_call(record).await }.boxed()
+    }
 }
 
 #[derive(Debug)]
 struct SqliteConversationStorageInner {
     db: SqliteConversationDb,
+    usage_db: SqliteUsageDb,
+    tool_call_db: SqliteToolCallDb,
+    tool_call_retention: Option<Duration>,
 }
 
 impl SqliteConversationStorageInner {
@@ -95,4 +127,28 @@ impl SqliteConversationStorageInner {
             .map_err(StorageError::by_backend)
             .await
     }
+
+    async fn add_usage(&self, identity: &str, model: &str, date: Date, usage: LlmUsage) -> Result<(), StorageError> {
+        self.usage_db
+            .add(identity, model, date, &usage)
+            .map_err(StorageError::by_backend)
+            .await
+    }
+
+    /// 記録のついでに保持期間を過ぎたものを削除する。
+    async fn add_tool_call(&self, record: &ToolCallRecord) -> Result<(), StorageError> {
+        self.tool_call_db.add(record).map_err(StorageError::by_backend).await?;
+
+        if let Some(retention) = self.tool_call_retention {
+            let purged = self
+                .tool_call_db
+                .purge(record.called_at - retention)
+                .map_err(StorageError::by_backend)
+                .await?;
+            if purged > 0 {
+                debug!("{purged} expired tool call record(s) purged");
+            }
+        }
+        Ok(())
+    }
 }